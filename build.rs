@@ -0,0 +1,29 @@
+fn main() {
+    #[cfg(feature = "capi")]
+    capi::build();
+}
+
+/// Generates the C header of `src/capi.rs`.
+#[cfg(feature = "capi")]
+mod capi {
+    use std::env;
+    use std::path::PathBuf;
+
+    pub(super) fn build() {
+        let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+        for path in ["src/capi.rs", "include/castbox.h", "cbindgen.toml"] {
+            println!("cargo:rerun-if-changed={path}");
+        }
+
+        // the tests check it matches the committed `include/castbox.h`
+        let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+        cbindgen::Builder::new()
+            .with_crate(&crate_dir)
+            .with_config(config)
+            .generate()
+            .expect("unable to generate the C header")
+            .write_to_file(out_dir.join("castbox.h"));
+    }
+}
@@ -0,0 +1,15 @@
+//! Builds the C harness against the committed header, linked by the tests of this crate.
+
+fn main() {
+    for path in ["harness.c", "../include/castbox.h"] {
+        println!("cargo:rerun-if-changed={path}");
+    }
+
+    cc::Build::new()
+        .file("harness.c")
+        .include("../include")
+        .warnings(true)
+        .extra_warnings(true)
+        .warnings_into_errors(true)
+        .compile("castbox_capi_harness");
+}
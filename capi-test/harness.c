@@ -0,0 +1,123 @@
+/* Exercises the C ABI through the committed header, run by capi-test/src/lib.rs. */
+
+#include <string.h>
+
+#include "castbox.h"
+
+/* returns the line of the first failed check */
+#define CHECK(cond)          \
+    do {                     \
+        if (!(cond)) {       \
+            return __LINE__; \
+        }                    \
+    } while (0)
+
+static int test_mutex(void) {
+    CastboxMutex *m = castbox_mutex_new();
+    CastboxMutex *m2 = castbox_mutex_clone(m);
+
+    castbox_mutex_lock(m);
+    CHECK(!castbox_mutex_try_lock(m2));
+    castbox_mutex_unlock(m);
+
+    CHECK(castbox_mutex_try_lock(m2));
+    castbox_mutex_unlock(m2);
+
+    castbox_mutex_lock_shared(m);
+    castbox_mutex_lock_shared(m2);
+    CHECK(!castbox_mutex_try_lock(m));
+    castbox_mutex_unlock_shared(m2);
+    castbox_mutex_unlock_shared(m);
+
+    castbox_mutex_free(m);
+    CHECK(castbox_mutex_try_lock(m2));
+    castbox_mutex_unlock(m2);
+    castbox_mutex_free(m2);
+    castbox_mutex_free(NULL);
+    return 0;
+}
+
+struct copy {
+    uint8_t data[16];
+    size_t len;
+};
+
+static void copy_bytes(void *ctx, const uint8_t *data, size_t len) {
+    struct copy *copy = ctx;
+    copy->len = len;
+    memcpy(copy->data, data, len < sizeof copy->data ? len : sizeof copy->data);
+}
+
+static void add_bytes(void *ctx, uint8_t *data, size_t len) {
+    const uint8_t *delta = ctx;
+    for (size_t i = 0; i < len; i++) {
+        data[i] += *delta;
+    }
+}
+
+static int test_bytes(void) {
+    const uint8_t init[] = {1, 2, 3};
+    CastboxBytes *b = castbox_bytes_new(init, sizeof init);
+    CastboxBytes *b2 = castbox_bytes_clone(b);
+    CHECK(castbox_bytes_len(b) == 3);
+
+    uint8_t delta = 10;
+    castbox_bytes_write(b2, add_bytes, &delta);
+
+    struct copy copy = {{0}, 0};
+    castbox_bytes_read(b, copy_bytes, &copy);
+    CHECK(copy.len == 3);
+    CHECK(copy.data[0] == 11 && copy.data[1] == 12 && copy.data[2] == 13);
+
+    castbox_bytes_free(b);
+    castbox_bytes_set(b2, (const uint8_t *)"castbox", 7);
+    castbox_bytes_read(b2, copy_bytes, &copy);
+    CHECK(copy.len == 7 && memcmp(copy.data, "castbox", 7) == 0);
+
+    castbox_bytes_set(b2, NULL, 0);
+    CHECK(castbox_bytes_len(b2) == 0);
+    castbox_bytes_free(b2);
+    return 0;
+}
+
+static int test_map(void) {
+    CastboxMap *map = castbox_map_new();
+    CastboxMap *map2 = castbox_map_clone(map);
+    CastboxBuf buf = {NULL, 0};
+
+    castbox_map_insert(map, (const uint8_t *)"key", 3, (const uint8_t *)"value", 5);
+    castbox_map_insert(map2, NULL, 0, NULL, 0);
+    CHECK(castbox_map_len(map) == 2);
+
+    CHECK(castbox_map_get(map2, (const uint8_t *)"key", 3, &buf));
+    CHECK(buf.len == 5 && memcmp(buf.data, "value", 5) == 0);
+    castbox_buf_free(&buf);
+    CHECK(buf.data == NULL && buf.len == 0);
+    castbox_buf_free(&buf);
+
+    CHECK(castbox_map_get(map, NULL, 0, &buf));
+    CHECK(buf.len == 0);
+    castbox_buf_free(&buf);
+    CHECK(!castbox_map_get(map, (const uint8_t *)"missing", 7, &buf));
+
+    castbox_map_insert(map, (const uint8_t *)"key", 3, (const uint8_t *)"other", 5);
+    CHECK(castbox_map_remove(map, (const uint8_t *)"key", 3, &buf));
+    CHECK(buf.len == 5 && memcmp(buf.data, "other", 5) == 0);
+    castbox_buf_free(&buf);
+    CHECK(!castbox_map_remove(map, (const uint8_t *)"key", 3, &buf));
+    CHECK(castbox_map_remove(map2, NULL, 0, NULL));
+    CHECK(castbox_map_len(map2) == 0);
+
+    castbox_map_free(map);
+    castbox_map_free(map2);
+    return 0;
+}
+
+/* 0 on success, otherwise the line of the first failed check */
+int castbox_capi_harness(void) {
+    int line;
+    if ((line = test_mutex()) != 0 || (line = test_bytes()) != 0 || (line = test_map()) != 0) {
+        return line;
+    }
+    return 0;
+}
@@ -0,0 +1,22 @@
+//! Runs `harness.c`, built by `build.rs`, against the C ABI of castbox.
+//!
+//! Kept out of the castbox build so that its users don't need a C compiler: only the tests
+//! of this unpublished crate compile and link the harness.
+
+#[cfg(test)]
+mod tests {
+    // the harness calls the exported functions, which are linked only if castbox is
+    extern crate castbox;
+
+    use core::ffi::c_int;
+
+    unsafe extern "C" {
+        fn castbox_capi_harness() -> c_int;
+    }
+
+    #[test]
+    fn c_harness() {
+        // the line of the first failed check in harness.c
+        assert_eq!(unsafe { castbox_capi_harness() }, 0);
+    }
+}
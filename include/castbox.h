@@ -0,0 +1,202 @@
+/* castbox C ABI, see src/capi.rs. */
+
+#ifndef CASTBOX_H
+#define CASTBOX_H
+
+/* Generated by cbindgen with the `capi` feature, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// A reference to a byte buffer guarded by a lock.
+typedef struct CastboxBytes CastboxBytes;
+
+// A reference to a concurrent map from byte strings to byte strings.
+typedef struct CastboxMap CastboxMap;
+
+// A reference to a lock with exclusive and shared modes.
+typedef struct CastboxMutex CastboxMutex;
+
+// A byte string allocated by castbox, released with `castbox_buf_free`.
+typedef struct CastboxBuf {
+  uint8_t *data;
+  size_t len;
+} CastboxBuf;
+
+// Called with the contents of a buffer, which stay valid only during the call.
+typedef void (*CastboxReadFn)(void *ctx,
+                              const uint8_t *data,
+                              size_t len);
+
+// Called with the contents of a buffer to update them in place, which stay valid only
+// during the call.
+typedef void (*CastboxWriteFn)(void *ctx,
+                               uint8_t *data,
+                               size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Releases the bytes of `buf`. Does nothing for a buffer already released or left empty.
+//
+// # Safety
+// `buf` must be null or point to a buffer filled by castbox.
+void castbox_buf_free(struct CastboxBuf *buf);
+
+// Creates an unlocked mutex.
+struct CastboxMutex *castbox_mutex_new(void);
+
+// Makes another reference to the same lock.
+//
+// # Safety
+// `mutex` must be a live handle.
+struct CastboxMutex *castbox_mutex_clone(const struct CastboxMutex *mutex);
+
+// Releases a reference, the lock is freed with the last one. Does nothing if null.
+//
+// # Safety
+// `mutex` must be null or a live handle, not used anymore afterwards.
+void castbox_mutex_free(struct CastboxMutex *mutex);
+
+// Takes the lock exclusively, blocking until it is free.
+//
+// # Safety
+// `mutex` must be a live handle.
+void castbox_mutex_lock(const struct CastboxMutex *mutex);
+
+// Takes the lock exclusively if it is free, returning whether it did.
+//
+// # Safety
+// `mutex` must be a live handle.
+bool castbox_mutex_try_lock(const struct CastboxMutex *mutex);
+
+// Releases the lock taken by `castbox_mutex_lock` or `castbox_mutex_try_lock`.
+//
+// # Safety
+// `mutex` must be a live handle, locked exclusively by the caller.
+void castbox_mutex_unlock(const struct CastboxMutex *mutex);
+
+// Takes the lock shared with the other readers, blocking while it is held exclusively.
+//
+// # Safety
+// `mutex` must be a live handle.
+void castbox_mutex_lock_shared(const struct CastboxMutex *mutex);
+
+// Releases the lock taken by `castbox_mutex_lock_shared`.
+//
+// # Safety
+// `mutex` must be a live handle, locked shared by the caller.
+void castbox_mutex_unlock_shared(const struct CastboxMutex *mutex);
+
+// Creates a buffer holding a copy of the `len` bytes at `data`, which may be null when
+// `len` is 0.
+//
+// # Safety
+// `data` must be valid for reads of `len` bytes.
+struct CastboxBytes *castbox_bytes_new(const uint8_t *data,
+                                       size_t len);
+
+// Makes another reference to the same buffer.
+//
+// # Safety
+// `buf` must be a live handle.
+struct CastboxBytes *castbox_bytes_clone(const struct CastboxBytes *buf);
+
+// Releases a reference, the buffer is freed with the last one. Does nothing if null.
+//
+// # Safety
+// `buf` must be null or a live handle, not used anymore afterwards.
+void castbox_bytes_free(struct CastboxBytes *buf);
+
+// Returns the length of the buffer.
+//
+// # Safety
+// `buf` must be a live handle.
+size_t castbox_bytes_len(const struct CastboxBytes *buf);
+
+// Calls `read` with the contents of the buffer, under its shared lock.
+//
+// # Safety
+// `buf` must be a live handle, and `read` must not use it to write.
+void castbox_bytes_read(const struct CastboxBytes *buf,
+                        CastboxReadFn read,
+                        void *ctx);
+
+// Calls `write` with the contents of the buffer, under its exclusive lock.
+//
+// # Safety
+// `buf` must be a live handle, and `write` must not use it.
+void castbox_bytes_write(const struct CastboxBytes *buf,
+                         CastboxWriteFn write,
+                         void *ctx);
+
+// Replaces the contents of the buffer with a copy of the `len` bytes at `data`, which
+// may be null when `len` is 0.
+//
+// # Safety
+// `buf` must be a live handle and `data` valid for reads of `len` bytes.
+void castbox_bytes_set(const struct CastboxBytes *buf,
+                       const uint8_t *data,
+                       size_t len);
+
+// Creates an empty map.
+struct CastboxMap *castbox_map_new(void);
+
+// Makes another reference to the same map.
+//
+// # Safety
+// `map` must be a live handle.
+struct CastboxMap *castbox_map_clone(const struct CastboxMap *map);
+
+// Releases a reference, the map is freed with the last one. Does nothing if null.
+//
+// # Safety
+// `map` must be null or a live handle, not used anymore afterwards.
+void castbox_map_free(struct CastboxMap *map);
+
+// Returns the number of entries.
+//
+// # Safety
+// `map` must be a live handle.
+size_t castbox_map_len(const struct CastboxMap *map);
+
+// Inserts copies of the key and the value, replacing the value already stored for the key.
+//
+// # Safety
+// `map` must be a live handle, `key` valid for reads of `key_len` bytes and `value` of
+// `value_len` bytes. Either may be null when its length is 0.
+void castbox_map_insert(const struct CastboxMap *map,
+                        const uint8_t *key,
+                        size_t key_len,
+                        const uint8_t *value,
+                        size_t value_len);
+
+// Copies the value of the key into `out`, to be released with `castbox_buf_free`.
+// Returns `false` and leaves `out` untouched if the key is missing.
+//
+// # Safety
+// `map` must be a live handle, `key` valid for reads of `key_len` bytes, or null when
+// it is 0, and `out` valid for writes.
+bool castbox_map_get(const struct CastboxMap *map,
+                     const uint8_t *key,
+                     size_t key_len,
+                     struct CastboxBuf *out);
+
+// Removes the key, moving its value into `out` unless it is null. Returns `false` if the
+// key is missing.
+//
+// # Safety
+// `map` must be a live handle, `key` valid for reads of `key_len` bytes, or null when
+// it is 0, and `out` null or valid for writes.
+bool castbox_map_remove(const struct CastboxMap *map,
+                        const uint8_t *key,
+                        size_t key_len,
+                        struct CastboxBuf *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CASTBOX_H */
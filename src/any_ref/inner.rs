@@ -1,17 +1,27 @@
 use crate::mutex::Mutex;
-use std::any::{Any, TypeId};
-use std::cell::UnsafeCell;
-use std::ptr::NonNull;
-use std::sync::atomic::AtomicUsize;
+use alloc::boxed::Box;
+use core::any::{Any, TypeId};
+use core::cell::UnsafeCell;
+use core::mem;
+use core::sync::atomic::Ordering::{Acquire, Release};
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
 
 /// Max number of reference that an any_ref could have
 pub(super) const MAX_REFCOUNT: usize = isize::MAX as usize;
 
+/// Describes the type of the value, see [`type_of`].
+type TypeOf = fn() -> (TypeId, &'static str);
+
+fn type_of<T: Any>() -> (TypeId, &'static str) {
+    (TypeId::of::<T>(), core::any::type_name::<T>())
+}
+
 /// Actually the main worker of AnyRef
 pub(crate) struct AnyRefInner {
     pub(crate) data: UnsafeCell<Box<dyn Any>>,
-    pub(crate) type_id: TypeId,
-    pub(crate) type_name: &'static str,
+    /// The [`TypeOf`] of `data`, in a single word so it can be checked without the lock
+    /// while a writer replaces the value.
+    type_of: AtomicPtr<()>,
     pub(crate) lock: Mutex,
     pub(crate) strong: AtomicUsize,
     pub(crate) weak: AtomicUsize,
@@ -33,14 +43,35 @@ impl AnyRefInner {
     {
         Self {
             data: UnsafeCell::new(src as Box<dyn Any>),
-            type_id: TypeId::of::<T>(),
-            type_name: std::any::type_name::<T>(),
+            type_of: AtomicPtr::new(type_of::<T> as TypeOf as *mut ()),
             lock: Mutex::new(),
             strong: AtomicUsize::new(1),
             weak: AtomicUsize::new(1),
         }
     }
 
+    #[inline]
+    fn type_of(&self) -> TypeOf {
+        let f = self.type_of.load(Acquire);
+        // SAFETY: only `TypeOf` pointers are stored
+        unsafe { mem::transmute::<*mut (), TypeOf>(f) }
+    }
+
+    pub(crate) fn type_id(&self) -> TypeId {
+        self.type_of()().0
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.type_of()().1
+    }
+
+    /// Records `T` as the type of the value, which must be a `T` already or be replaced by
+    /// one before the exclusive lock is released.
+    pub(crate) fn set_type<T: Any>(&self) {
+        self.type_of
+            .store(type_of::<T> as TypeOf as *mut (), Release);
+    }
+
     #[inline(always)]
     fn internal_get(&self) -> *mut dyn Any {
         let ptr = self.data.get();
@@ -48,14 +79,14 @@ impl AnyRefInner {
         data
     }
 
-    pub(crate) fn get_ref(&self) -> &dyn Any {
-        unsafe { &*self.internal_get() }
+    /// Pointer to the value if it is a `U`. The caller holds the lock, so the value can't
+    /// be replaced until it is used.
+    pub(crate) fn downcast_ptr<U: Any>(&self) -> Option<*mut U> {
+        let ptr = self.internal_get();
+        // SAFETY: the value is only read to check its type
+        unsafe { (*ptr).is::<U>() }.then_some(ptr as *mut U)
     }
 
-    pub(crate) fn get_mut_ref(&self) -> &mut dyn Any {
-        let mut value = unsafe { NonNull::new_unchecked(self.internal_get()) };
-        unsafe { &mut *value.as_mut() }
-    }
 }
 
 impl Default for AnyRefInner {
@@ -1,8 +1,8 @@
 use crate::any_ref::inner::AnyRefInner;
 use crate::utils::is_dangling;
-use std::any::Any;
-use std::mem::offset_of;
-use std::ptr;
+use core::any::Any;
+use core::mem::offset_of;
+use core::ptr;
 
 pub(crate) trait PtrInterface
 where
@@ -1,16 +1,21 @@
 use crate::any_ref::inner::{AnyRefInner, MAX_REFCOUNT};
 use crate::any_ref::ptr_interface::PtrInterface;
 use crate::any_ref::weak::WeakAnyRef;
-use crate::mutex::{WatchGuardMut, WatchGuardRef};
-use crate::utils::is_dangling;
-use std::any::{Any, TypeId};
-use std::cell::UnsafeCell;
-use std::mem::ManuallyDrop;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::process::abort;
-use std::sync::atomic;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{fmt, hint, ptr};
+use crate::mutex::{
+    LockError, MappedWatchGuardMut, MappedWatchGuardRef, Mutex, OwnedWatchGuardMut,
+    OwnedWatchGuardRef,
+};
+use crate::utils::{abort, is_dangling};
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use core::any::{Any, TypeId};
+use core::cell::UnsafeCell;
+use core::mem::{self, ManuallyDrop};
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::sync::atomic;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::{fmt, hint, ptr};
 
 #[repr(transparent)]
 pub struct AnyRef {
@@ -39,6 +44,60 @@ impl AnyRef {
         unsafe { Self::from_inner(Box::leak(Box::new(AnyRefInner::new(value)))) }
     }
 
+    /// Creates a new `AnyRef` with the value returned by `data_fn`, which gets a weak
+    /// reference to the `AnyRef` being built, like `Arc::new_cyclic`.
+    ///
+    /// Upgrading the weak reference gives `None` until `new_cyclic` returns.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::{AnyRef, WeakAnyRef};
+    ///
+    /// struct Node {
+    ///     me: WeakAnyRef,
+    /// }
+    ///
+    /// let node = AnyRef::new_cyclic(|me| {
+    ///     assert!(me.upgrade().is_none());
+    ///     Node { me: me.clone() }
+    /// });
+    /// let me = node.as_ref::<Node>().me.upgrade().unwrap();
+    /// assert!(AnyRef::ptr_eq(&me, &node));
+    /// ```
+    pub fn new_cyclic<T, F>(data_fn: F) -> Self
+    where
+        T: Any + Sized,
+        F: FnOnce(&WeakAnyRef) -> T,
+    {
+        /// Drops the lock if `data_fn` panics, the weak reference frees the allocation.
+        struct DropLock(*mut Mutex);
+
+        impl Drop for DropLock {
+            fn drop(&mut self) {
+                unsafe { ptr::drop_in_place(self.0) }
+            }
+        }
+
+        // a placeholder until the value is written, with no strong reference
+        let mut inner = AnyRefInner::from_box(Box::new(()));
+        inner.set_type::<T>();
+        inner.strong = AtomicUsize::new(0);
+        let inner: *mut AnyRefInner = Box::leak(Box::new(inner));
+        let weak = unsafe { WeakAnyRef::from_inner_in(inner) };
+        let lock = DropLock(unsafe { &raw mut (*inner).lock });
+
+        let data = data_fn(&weak);
+
+        unsafe { *(*inner).data.get() = Box::new(data) };
+        mem::forget(lock);
+        // publishes the value to the upgrades, the implicit weak reference of the strong
+        // ones is the one `weak` holds
+        unsafe { (*inner).strong.store(1, Release) };
+        mem::forget(weak);
+
+        unsafe { Self::from_inner_in(inner) }
+    }
+
     /// Attempts to extract the inner value if there is exactly one strong reference.
     ///
     /// # Example
@@ -95,7 +154,7 @@ impl AnyRef {
     pub fn map<T, U: 'static, F>(self, func: F) -> AnyRef
     where
         T: Any,
-        F: FnOnce(WatchGuardRef<'_, T>) -> U,
+        F: FnOnce(MappedWatchGuardRef<'_, T>) -> U,
     {
         let ptr = self.as_ref::<T>();
         AnyRef::new(func(ptr))
@@ -111,10 +170,10 @@ impl AnyRef {
     /// unsafe { assert_eq!(*ptr, 50); }
     /// ```
     pub unsafe fn as_cast_ptr<T: Any>(&self) -> *const T {
-        if self.inner().type_id != TypeId::of::<T>() {
+        if self.inner().type_id() != TypeId::of::<T>() {
             panic!(
                 "AnyRef: wrong cast in as_ref::<{}>()",
-                std::any::type_name::<T>()
+                core::any::type_name::<T>()
             );
         }
         let ptr = self.as_ptr();
@@ -260,7 +319,7 @@ impl AnyRef {
     }
 
     pub fn type_name(&self) -> &'static str {
-        self.inner().type_name
+        self.inner().type_name()
     }
 }
 
@@ -278,16 +337,25 @@ impl PtrInterface for AnyRef {
 }
 
 impl AnyRef {
-    pub fn try_downcast_ref<U: Any>(&self) -> Option<WatchGuardRef<'_, U>> {
-        if self.inner().type_id == TypeId::of::<U>() {
+    /// Takes the group lock and gives a guard on the value if it is a `U`.
+    ///
+    /// The value can be swapped by [`AnyRef::replace`] once the lock is released, so the
+    /// guard holds it until dropped:
+    /// ```compile_fail
+    /// use castbox::AnyRef;
+    ///
+    /// let a = AnyRef::new(String::from("text"));
+    /// let mut s = a.try_downcast_ref::<String>().unwrap();
+    /// s.unlocked(|| a.replace(1u8));
+    /// ```
+    pub fn try_downcast_ref<U: Any>(&self) -> Option<MappedWatchGuardRef<'_, U>> {
+        if self.inner().type_id() == TypeId::of::<U>() {
             let lock = self.inner().lock.clone();
             lock.lock_group();
 
-            let data = self.inner().get_ref();
-            let data = data.downcast_ref::<U>();
-
-            match data {
-                Some(t) => Some(WatchGuardRef::new(t, lock)),
+            match self.inner().downcast_ptr::<U>() {
+                // SAFETY: the group lock is held
+                Some(t) => Some(unsafe { MappedWatchGuardRef::from_ptr(t, lock) }),
                 None => {
                     lock.unlock_group();
                     None
@@ -298,14 +366,14 @@ impl AnyRef {
         }
     }
 
-    pub fn try_downcast_mut<U: Any>(&self) -> Option<WatchGuardMut<'_, U>> {
-        if self.inner().type_id == TypeId::of::<U>() {
+    pub fn try_downcast_mut<U: Any>(&self) -> Option<MappedWatchGuardMut<'_, U>> {
+        if self.inner().type_id() == TypeId::of::<U>() {
             let lock = self.inner().lock.clone();
             lock.lock_exclusive();
 
-            let data = self.inner().get_mut_ref().downcast_mut::<U>();
-            match data {
-                Some(t) => Some(WatchGuardMut::new(t, lock)),
+            match self.inner().downcast_ptr::<U>() {
+                // SAFETY: the exclusive lock is held
+                Some(t) => Some(unsafe { MappedWatchGuardMut::from_ptr(t, lock) }),
                 None => {
                     lock.unlock_exclusive();
                     None
@@ -316,14 +384,154 @@ impl AnyRef {
         }
     }
 
-    pub fn as_ref<U: Any>(&self) -> WatchGuardRef<'_, U> {
+    /// Like [`AnyRef::try_downcast_ref`], but doesn't wait for a writer either: fails with
+    /// [`LockError::TypeMismatch`] if the value is not a `U` and with
+    /// [`LockError::WouldBlock`] if it is locked for writing.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// use castbox::mutex::LockError;
+    ///
+    /// let a = AnyRef::new(1u8);
+    /// assert_eq!(a.try_lock_downcast_ref::<u8>().unwrap(), 1);
+    /// assert_eq!(a.try_lock_downcast_ref::<i8>().unwrap_err(), LockError::TypeMismatch);
+    ///
+    /// let w = a.as_mut::<u8>();
+    /// assert_eq!(a.try_lock_downcast_ref::<u8>().unwrap_err(), LockError::WouldBlock);
+    /// ```
+    pub fn try_lock_downcast_ref<U: Any>(&self) -> Result<MappedWatchGuardRef<'_, U>, LockError> {
+        if self.inner().type_id() != TypeId::of::<U>() {
+            return Err(LockError::TypeMismatch);
+        }
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_group() {
+            return Err(LockError::WouldBlock);
+        }
+
+        match self.inner().downcast_ptr::<U>() {
+            // SAFETY: the group lock is held
+            Some(t) => Ok(unsafe { MappedWatchGuardRef::from_ptr(t, lock) }),
+            None => {
+                lock.unlock_group();
+                Err(LockError::TypeMismatch)
+            }
+        }
+    }
+
+    /// Like [`AnyRef::try_downcast_mut`], but doesn't wait for the other guards either:
+    /// fails with [`LockError::TypeMismatch`] if the value is not a `U` and with
+    /// [`LockError::WouldBlock`] if it is locked.
+    pub fn try_lock_downcast_mut<U: Any>(&self) -> Result<MappedWatchGuardMut<'_, U>, LockError> {
+        if self.inner().type_id() != TypeId::of::<U>() {
+            return Err(LockError::TypeMismatch);
+        }
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_exclusive() {
+            return Err(LockError::WouldBlock);
+        }
+
+        match self.inner().downcast_ptr::<U>() {
+            // SAFETY: the exclusive lock is held
+            Some(t) => Ok(unsafe { MappedWatchGuardMut::from_ptr(t, lock) }),
+            None => {
+                lock.unlock_exclusive();
+                Err(LockError::TypeMismatch)
+            }
+        }
+    }
+
+    /// Like [`AnyRef::try_downcast_ref`], but the guard holds a clone of the `AnyRef`
+    /// instead of borrowing it, so it can outlive `self`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// let g = AnyRef::new(7u8).downcast_owned::<u8>().unwrap();
+    /// assert_eq!(*g, 7);
+    /// ```
+    pub fn downcast_owned<U: Any>(&self) -> Option<OwnedWatchGuardRef<Self, U>> {
+        if self.inner().type_id() != TypeId::of::<U>() {
+            return None;
+        }
+        let lock = self.inner().lock.clone();
+        lock.lock_group();
+
+        match self.inner().downcast_ptr::<U>() {
+            // SAFETY: the value lives as long as the cloned `AnyRef`
+            Some(t) => Some(unsafe { OwnedWatchGuardRef::new(self.clone(), t, lock) }),
+            None => {
+                lock.unlock_group();
+                None
+            }
+        }
+    }
+
+    /// Like [`AnyRef::try_downcast_mut`], but the guard holds a clone of the `AnyRef`
+    /// instead of borrowing it, so it can outlive `self`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// let a = AnyRef::new(7u8);
+    /// let mut g = a.downcast_mut_owned::<u8>().unwrap();
+    /// *g += 1;
+    /// drop(g);
+    /// assert_eq!(a.as_ref::<u8>(), 8);
+    /// ```
+    pub fn downcast_mut_owned<U: Any>(&self) -> Option<OwnedWatchGuardMut<Self, U>> {
+        if self.inner().type_id() != TypeId::of::<U>() {
+            return None;
+        }
+        let lock = self.inner().lock.clone();
+        lock.lock_exclusive();
+
+        match self.inner().downcast_ptr::<U>() {
+            // SAFETY: the value lives as long as the cloned `AnyRef`
+            Some(t) => Some(unsafe { OwnedWatchGuardMut::new(self.clone(), t, lock) }),
+            None => {
+                lock.unlock_exclusive();
+                None
+            }
+        }
+    }
+
+    /// Runs `f` on the value under the group lock, released as soon as `f` returns.
+    /// Returns `None` without calling `f` if the value is not a `U`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// let a = AnyRef::new(String::from("abc"));
+    /// assert_eq!(a.with::<String, _>(|s| s.len()), Some(3));
+    /// assert_eq!(a.with::<u8, _>(|n| *n), None);
+    /// ```
+    pub fn with<U: Any, R>(&self, f: impl FnOnce(&U) -> R) -> Option<R> {
+        self.try_downcast_ref::<U>().map(|data| f(&data))
+    }
+
+    /// Runs `f` on the value under the exclusive lock, released as soon as `f` returns.
+    /// Returns `None` without calling `f` if the value is not a `U`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// let a = AnyRef::new(1u8);
+    /// a.with_mut::<u8, _>(|n| *n += 1);
+    /// assert_eq!(a.as_ref::<u8>(), 2);
+    /// ```
+    pub fn with_mut<U: Any, R>(&self, f: impl FnOnce(&mut U) -> R) -> Option<R> {
+        self.try_downcast_mut::<U>().map(|mut data| f(&mut data))
+    }
+
+    pub fn as_ref<U: Any>(&self) -> MappedWatchGuardRef<'_, U> {
         match self.try_downcast_ref::<U>() {
             Some(data) => data,
             None => panic!("Downcast failed"),
         }
     }
 
-    pub fn as_mut<U: Any>(&self) -> WatchGuardMut<'_, U> {
+    pub fn as_mut<U: Any>(&self) -> MappedWatchGuardMut<'_, U> {
         match self.try_downcast_mut::<U>() {
             Some(data) => data,
             None => panic!("Downcast mut failed"),
@@ -382,14 +590,36 @@ impl AnyRef {
     /// let a = AnyRef::fill(a, 123);
     /// assert_eq!(a.as_ref::<i32>(), 123);
     /// ```
-    pub fn fill<T: 'static>(mut this: Self, value: T) -> Self {
-        let ref_inner = &mut *this.inner_mut();
-        ref_inner.lock.lock_exclusive();
-        ref_inner.data = UnsafeCell::new(Box::new(value));
-        ref_inner.type_id = TypeId::of::<T>();
-        ref_inner.lock.unlock_exclusive();
+    pub fn fill<T: 'static>(this: Self, value: T) -> Self {
+        this.replace(value);
         this
     }
+
+    /// Replaces the value, of any type, with `value` under the exclusive lock, returning
+    /// the old one. Clones never see the new type paired with the old value.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::AnyRef;
+    /// let a = AnyRef::new(1u8);
+    /// let b = a.clone();
+    /// let old = b.replace("one");
+    /// assert_eq!(old.downcast_ref::<u8>(), Some(&1));
+    /// assert_eq!(*a.as_ref::<&str>(), "one");
+    /// assert!(a.try_downcast_ref::<u8>().is_none());
+    /// ```
+    pub fn replace<T: Any>(&self, value: T) -> Box<dyn Any> {
+        let value: Box<dyn Any> = Box::new(value);
+        let inner = self.inner();
+
+        inner.lock.lock_exclusive();
+        // SAFETY: the exclusive lock keeps every guard away from the value
+        let old = unsafe { mem::replace(&mut *inner.data.get(), value) };
+        inner.set_type::<T>();
+        inner.lock.unlock_exclusive();
+
+        old
+    }
 }
 
 impl Drop for AnyRef {
@@ -475,7 +705,7 @@ impl fmt::Debug for AnyRef {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let inner = self.inner();
         f.debug_struct("AnyRef")
-            .field("type", &inner.type_name)
+            .field("type", &inner.type_name())
             .field("S", &inner.strong)
             .field("W", &inner.weak)
             .finish()
@@ -1,13 +1,12 @@
 use crate::any_ref::inner::{AnyRefInner, MAX_REFCOUNT};
 use crate::any_ref::ptr_interface::PtrInterface;
 use crate::any_ref::strong::AnyRef;
-use crate::utils::is_dangling;
-use std::alloc::{Layout, dealloc};
-use std::num::NonZeroUsize;
-use std::process::abort;
-use std::ptr;
-use std::sync::atomic;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::utils::{abort, is_dangling};
+use alloc::alloc::{Layout, dealloc};
+use core::num::NonZeroUsize;
+use core::ptr;
+use core::sync::atomic;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 #[repr(transparent)]
 pub struct WeakAnyRef {
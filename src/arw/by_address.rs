@@ -0,0 +1,99 @@
+use crate::arw::ptr_interface::PtrInterface;
+use crate::arw::{Arw, WeakArw};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Deref, DerefMut};
+
+/// Compares, orders and hashes an [`Arw`] or a [`WeakArw`] by the address of its
+/// allocation instead of its value, without locking it.
+///
+/// Handles to the same allocation are equal whatever their value, and the value can
+/// change while used as a key. A `WeakArw` keeps the allocation, so its address is not
+/// reused while it is alive; all the empty `WeakArw` are equal.
+///
+/// # Example
+/// ```
+/// use castbox::{Arw, ByAddress};
+/// use std::collections::HashSet;
+///
+/// let a = Arw::new(vec![1]);
+/// let mut seen = HashSet::new();
+/// assert!(seen.insert(ByAddress(a.clone())));
+/// assert!(!seen.insert(ByAddress(a.clone())));
+/// assert!(seen.insert(ByAddress(Arw::new(vec![1]))));
+///
+/// a.as_mut().push(2);
+/// assert!(seen.contains(&ByAddress(a.clone())));
+///
+/// let w = ByAddress(a.downgrade());
+/// assert_eq!(w, ByAddress(a.downgrade()));
+/// ```
+#[derive(Clone, Default)]
+#[repr(transparent)]
+pub struct ByAddress<P>(pub P);
+
+impl<P> Deref for ByAddress<P> {
+    type Target = P;
+
+    #[inline]
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+
+impl<P> DerefMut for ByAddress<P> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut P {
+        &mut self.0
+    }
+}
+
+macro_rules! impl_by_address {
+    ($($handle:ident),*) => {$(
+        impl<T: ?Sized, L> ByAddress<$handle<T, L>> {
+            #[inline]
+            fn addr(&self) -> *const () {
+                self.0.get_mut_inner_ptr() as *const ()
+            }
+        }
+
+        impl<T: ?Sized, L> PartialEq for ByAddress<$handle<T, L>> {
+            #[inline]
+            fn eq(&self, other: &Self) -> bool {
+                self.addr() == other.addr()
+            }
+        }
+
+        impl<T: ?Sized, L> Eq for ByAddress<$handle<T, L>> {}
+
+        impl<T: ?Sized, L> PartialOrd for ByAddress<$handle<T, L>> {
+            #[inline]
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl<T: ?Sized, L> Ord for ByAddress<$handle<T, L>> {
+            #[inline]
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.addr().cmp(&other.addr())
+            }
+        }
+
+        impl<T: ?Sized, L> Hash for ByAddress<$handle<T, L>> {
+            #[inline]
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.addr().hash(state);
+            }
+        }
+
+        impl<T: ?Sized, L> fmt::Debug for ByAddress<$handle<T, L>> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple("ByAddress").field(&self.addr()).finish()
+            }
+        }
+    )*};
+}
+
+impl_by_address!(Arw, WeakArw);
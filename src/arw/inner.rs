@@ -1,33 +1,65 @@
-use crate::mutex::Mutex;
-use std::any::Any;
-use std::cell::UnsafeCell;
-use std::ptr::NonNull;
-use std::sync::atomic::AtomicUsize;
+use crate::mutex::{Mutex, Version};
+use crate::utils::memory_layout_for_t;
+use alloc::alloc::{Layout, alloc, handle_alloc_error};
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::AtomicUsize;
 
 /// Max number of reference that an any_ref could have
 pub(super) const MAX_REFCOUNT: usize = isize::MAX as usize;
 
 /// Actually the main worker
-pub(crate) struct ArwInner<T: Sized> {
+///
+/// `repr(C)` keeps the value last, after a header laid out as in `ArwInner<()>`, so unsized
+/// values can be allocated with `memory_layout_for_t`.
+#[repr(C)]
+pub(crate) struct ArwInner<T: ?Sized> {
     pub(crate) lock: Mutex,
     pub(crate) strong: AtomicUsize,
     pub(crate) weak: AtomicUsize,
+    /// changes made through the write guards, for the watchers
+    pub(crate) version: Version,
     pub(crate) val: UnsafeCell<T>,
 }
 
 impl<T> ArwInner<T> {
     /// Constructs a new `ArwInner` from a concrete value.
-    pub(crate) fn new(val: T) -> Self
-    where
-        T: Any,
-    {
+    pub(crate) fn new(val: T) -> Self {
         Self {
             val: UnsafeCell::new(val),
             lock: Mutex::new(),
             strong: AtomicUsize::new(1),
             weak: AtomicUsize::new(1),
+            version: Version::new(),
         }
     }
+}
+
+impl<T: ?Sized> ArwInner<T> {
+    /// Allocates an `ArwInner` for a value of `value_layout` and initializes its header,
+    /// the value is left uninitialized. The returned pointer takes the metadata of `meta`.
+    pub(crate) unsafe fn allocate_for(value_layout: Layout, meta: *const T) -> *mut ArwInner<T> {
+        let layout = memory_layout_for_t::<ArwInner<()>>(value_layout);
+        let mem = unsafe { alloc(layout) };
+        if mem.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        let inner = set_data_ptr(meta as *mut ArwInner<T>, mem);
+        unsafe {
+            ptr::write(&raw mut (*inner).lock, Mutex::new());
+            ptr::write(&raw mut (*inner).strong, AtomicUsize::new(1));
+            ptr::write(&raw mut (*inner).weak, AtomicUsize::new(1));
+            ptr::write(&raw mut (*inner).version, Version::new());
+        }
+        inner
+    }
+
+    /// Address of the value of `inner`, which may be uninitialized or already dropped.
+    #[inline]
+    pub(crate) unsafe fn val_ptr(inner: *mut ArwInner<T>) -> *mut T {
+        unsafe { (&raw mut (*inner).val) as *mut T }
+    }
 
     #[inline(always)]
     fn internal_get(&self) -> *mut T {
@@ -44,6 +76,15 @@ impl<T> ArwInner<T> {
     }
 }
 
+/// Replaces the address of `ptr` with `data`, keeping the metadata of `ptr` and the
+/// provenance of `data`.
+#[inline]
+pub(crate) fn set_data_ptr<T: ?Sized, U>(mut ptr: *mut T, data: *mut U) -> *mut T {
+    // SAFETY: the address is the first word of every pointer, thin or fat
+    unsafe { ptr::write(&mut ptr as *mut *mut T as *mut *mut u8, data as *mut u8) };
+    ptr
+}
+
 impl<T: Default> Default for ArwInner<T> {
     fn default() -> Self {
         Self {
@@ -51,6 +92,7 @@ impl<T: Default> Default for ArwInner<T> {
             lock: Mutex::new(),
             strong: AtomicUsize::new(1),
             weak: AtomicUsize::new(1),
+            version: Version::new(),
         }
     }
 }
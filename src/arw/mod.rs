@@ -1,7 +1,13 @@
+mod by_address;
 mod inner;
+mod optimistic;
 mod ptr_interface;
 mod strong;
+mod watcher;
 mod weak;
 
+pub use by_address::ByAddress;
+pub use optimistic::OptimisticRead;
 pub use strong::Arw;
+pub use watcher::ArwWatcher;
 pub use weak::WeakArw;
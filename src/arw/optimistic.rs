@@ -0,0 +1,91 @@
+use crate::arw::Arw;
+use crate::mutex::Backoff;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+/// Types that [`Arw::read_optimistic`] can copy out without the lock.
+///
+/// The optimistic read copies the bytes of the value while a writer may be changing them,
+/// and only looks at the copy as a `T` once it knows that no writer did. The copy is then
+/// borrowed by the closure and forgotten, it is never dropped.
+///
+/// Implemented for every `Copy` type, other types can opt in.
+///
+/// # Safety
+/// A bitwise copy of a value must be usable through a shared reference on its own, also
+/// after the original has been changed or dropped by a writer: the type must not own or
+/// borrow anything that a writer could free or mutate through the original, like the heap
+/// buffer of a `Box` or a `Vec`.
+///
+/// # Example
+/// ```
+/// use castbox::{Arw, OptimisticRead};
+///
+/// // not `Copy`, but only plain data
+/// struct Stats {
+///     hits: u64,
+///     misses: u64,
+/// }
+///
+/// // SAFETY: `Stats` owns nothing, a copy of its bytes is a value on its own
+/// unsafe impl OptimisticRead for Stats {}
+///
+/// let stats = Arw::new(Stats { hits: 3, misses: 1 });
+/// assert_eq!(stats.read_optimistic(|s| s.hits + s.misses), 4);
+/// ```
+///
+/// Owning types can't be read this way:
+/// ```compile_fail
+/// use castbox::Arw;
+/// let name = Arw::new(String::from("fast"));
+/// name.read_optimistic(|n| n.len());
+/// ```
+pub unsafe trait OptimisticRead {}
+
+// SAFETY: a copy of a `Copy` value is as good as the original
+unsafe impl<T: Copy> OptimisticRead for T {}
+
+impl<T: OptimisticRead> Arw<T> {
+    /// Runs `f` on a copy of the value taken without the lock, like a
+    /// [`SeqLock`](crate::SeqLock) read: the copy is kept only if no write guard was held
+    /// meanwhile, otherwise it retries, and after a few failed attempts falls back to
+    /// [`Arw::with`] under the group lock.
+    ///
+    /// A torn copy is never used as a `T`, and `f` runs once on a consistent value, see
+    /// [`OptimisticRead`].
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let bounds = Arw::new((0u32, 10u32));
+    /// bounds.with_mut(|b| *b = (5, 50));
+    /// assert_eq!(bounds.read_optimistic(|&(lo, hi)| hi - lo), 45);
+    /// ```
+    pub fn read_optimistic<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let version = &self.inner().version;
+        let backoff = Backoff::new();
+
+        while !backoff.is_yielding() {
+            let Some(seq) = version.read_begin() else {
+                break;
+            };
+
+            // SAFETY: read as `MaybeUninit`, the bytes may be torn by a concurrent writer and
+            // need not be a valid `T`: they are only assumed so once the version validates
+            let copy =
+                unsafe { ptr::read_volatile(self.inner().val.get().cast::<MaybeUninit<T>>()) };
+            if version.read_validate(seq) {
+                // SAFETY: no writer touched the value while it was copied, and the copy is
+                // a value on its own by the `OptimisticRead` contract. It is only borrowed,
+                // the original keeps ownership of anything it would drop.
+                return f(unsafe { copy.assume_init_ref() });
+            }
+            backoff.spin();
+        }
+
+        self.with(f)
+    }
+}
@@ -1,9 +1,9 @@
 use crate::arw::inner::ArwInner;
 use crate::utils::is_dangling;
-use std::mem::offset_of;
-use std::ptr;
+use core::mem::offset_of;
+use core::ptr;
 
-pub(crate) trait PtrInterface<T>
+pub(crate) trait PtrInterface<T: ?Sized>
 where
     Self: Sized,
 {
@@ -16,11 +16,17 @@ where
         unsafe { Self::from_inner_in(ptr) }
     }
 
-    unsafe fn read_data(&self) -> T {
+    unsafe fn read_data(&self) -> T
+    where
+        T: Sized,
+    {
         unsafe { ptr::read(self.as_ptr()) }
     }
 
-    fn as_ptr(&self) -> *const T {
+    fn as_ptr(&self) -> *const T
+    where
+        T: Sized,
+    {
         let ptr: *mut ArwInner<T> = self.get_mut_inner_ptr();
 
         if is_dangling(ptr) {
@@ -44,7 +50,10 @@ where
     }
 
     #[inline]
-    unsafe fn from_raw_in(ptr: *const T) -> Self {
+    unsafe fn from_raw_in(ptr: *const T) -> Self
+    where
+        T: Sized,
+    {
         let inner_ptr = if is_dangling(ptr) {
             // This is a dangling Weak.
             ptr as *mut ArwInner<T>
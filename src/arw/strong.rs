@@ -1,28 +1,45 @@
-use crate::arw::inner::{ArwInner, MAX_REFCOUNT};
+use crate::arw::inner::{ArwInner, MAX_REFCOUNT, set_data_ptr};
 use crate::arw::ptr_interface::PtrInterface;
-use crate::arw::WeakArw;
-use crate::mutex::{WatchGuardMut, WatchGuardRef};
-use crate::utils::is_dangling;
-use std::any::Any;
-use std::cell::UnsafeCell;
-use std::mem::ManuallyDrop;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::process::abort;
-use std::sync::atomic;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{fmt, hint, ptr};
+use crate::arw::{ArwWatcher, WeakArw};
+use crate::level::{Level, LockToken, Unleveled};
+use crate::mutex::{
+    LockError, Mutex, OwnedWatchGuardMut, OwnedWatchGuardRef, WatchGuardMut, WatchGuardRef,
+};
+use crate::utils::{abort, is_dangling};
+use alloc::alloc::{Layout, dealloc};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop};
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::sync::atomic;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+#[cfg(feature = "std")]
+use core::time::Duration;
+use core::cmp::Ordering;
+use core::hash::{Hash, Hasher};
+use core::{fmt, hint, ptr};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// `L` places the `Arw` in the compile-time lock hierarchy, see [`crate::level`]: leveled
+/// values are only locked through the `*_leveled` methods.
 #[repr(transparent)]
-pub struct Arw<T: Sized> {
+pub struct Arw<T: ?Sized, L = Unleveled> {
     ptr: *const ArwInner<T>,
+    level: PhantomData<fn() -> L>,
 }
 
-unsafe impl<T: Sized + Sync + Send> Send for Arw<T> {}
-unsafe impl<T: Sized + Sync + Send> Sync for Arw<T> {}
+unsafe impl<T: ?Sized + Sync + Send, L> Send for Arw<T, L> {}
+unsafe impl<T: ?Sized + Sync + Send, L> Sync for Arw<T, L> {}
 
-impl<T: Sized> UnwindSafe for Arw<T> {}
-impl<T: Sized> RefUnwindSafe for Arw<T> {}
-impl<T> Arw<T> {
+impl<T: ?Sized, L> UnwindSafe for Arw<T, L> {}
+impl<T: ?Sized, L> RefUnwindSafe for Arw<T, L> {}
+
+impl<T: ?Sized> Arw<T> {
     /// Creates a new `Arw` containing the given value.
     ///
     /// # Example
@@ -33,60 +50,60 @@ impl<T> Arw<T> {
     /// ```
     pub fn new(value: T) -> Self
     where
-        T: Any,
+        T: Any + Sized,
     {
         unsafe { Self::from_inner(Box::leak(Box::new(ArwInner::new(value)))) }
     }
 
-    /// Attempts to extract the inner value if there is exactly one strong reference.
+    /// Creates a new `Arw` with the value returned by `data_fn`, which gets a weak
+    /// reference to the allocation being built, like `Arc::new_cyclic`.
+    ///
+    /// Upgrading the weak reference gives `None` until `new_cyclic` returns.
     ///
     /// # Example
     /// ```
-    /// use castbox::Arw;
-    /// let a = Arw::new(123i32);
-    /// let value = Arw::try_unwrap(a).unwrap();
-    /// assert_eq!(value, 123i32);
+    /// use castbox::{Arw, WeakArw};
+    ///
+    /// struct Node {
+    ///     me: WeakArw<Node>,
+    /// }
+    ///
+    /// let node = Arw::new_cyclic(|me| {
+    ///     assert!(me.upgrade().is_none());
+    ///     Node { me: me.clone() }
+    /// });
+    /// assert!(Arw::ptr_eq(&node.as_ref().me.upgrade().unwrap(), &node));
     /// ```
-    pub fn try_unwrap(this: Self) -> Result<T, Self> {
-        this.inner().lock.lock_exclusive();
-        if this
-            .inner()
-            .strong
-            .compare_exchange(1, 0, Acquire, Relaxed)
-            .is_err()
-        {
-            this.inner().lock.unlock_exclusive();
-            return Err(this);
-        }
-
-        atomic::fence(Acquire);
-
-        let this = ManuallyDrop::new(this);
-        let elem: T = unsafe { this.read_data() };
-
-        // Make a weak pointer to clean up the implicit strong-weak reference
-        let _weak = WeakArw { ptr: this.ptr };
+    pub fn new_cyclic<F>(data_fn: F) -> Self
+    where
+        T: Any + Sized,
+        F: FnOnce(&WeakArw<T>) -> T,
+    {
+        /// Drops the lock if `data_fn` panics, the weak reference frees the allocation.
+        struct DropLock(*mut Mutex);
 
-        unsafe { ptr::drop_in_place(&mut (*this.get_mut_inner_ptr()).val) }
-        unsafe { ptr::drop_in_place(&mut (*this.get_mut_inner_ptr()).lock) }
+        impl Drop for DropLock {
+            fn drop(&mut self) {
+                unsafe { ptr::drop_in_place(self.0) }
+            }
+        }
 
-        Ok(elem)
-    }
+        // no strong reference until the value is written
+        let inner = unsafe { ArwInner::allocate_for(Layout::new::<T>(), ptr::null::<T>()) };
+        unsafe { (*inner).strong.store(0, Relaxed) };
+        let weak = unsafe { WeakArw::from_inner_in(inner) };
+        let lock = DropLock(unsafe { &raw mut (*inner).lock });
 
-    fn inner(&self) -> &ArwInner<T> {
-        // This unsafety is ok because while this Arw is alive we're guaranteed
-        // that the inner pointer is valid.
-        let ptr: *const ArwInner<T> = self.ptr;
-        unsafe { &*ptr }
-    }
+        let data = data_fn(&weak);
 
-    fn inner_mut(&self) -> &mut ArwInner<T> {
-        let ptr: *mut ArwInner<T> = self.get_mut_inner_ptr();
-        unsafe { &mut *ptr }
-    }
+        unsafe { ptr::write(ArwInner::val_ptr(inner), data) };
+        mem::forget(lock);
+        // publishes the value to the upgrades, the implicit weak reference of the strong
+        // ones is the one `weak` holds
+        unsafe { (*inner).strong.store(1, Release) };
+        mem::forget(weak);
 
-    pub fn is_locked(&self) -> bool {
-        self.inner().lock.is_locked_exclusive()
+        unsafe { Self::from_inner_in(inner) }
     }
 
     #[inline]
@@ -112,7 +129,8 @@ impl<T> Arw<T> {
         let lock = self.inner().lock.clone();
         lock.lock_group();
 
-        WatchGuardRef::new(self.inner().get_ref(), lock)
+        // SAFETY: the group lock is held
+        unsafe { WatchGuardRef::from_ptr(self.inner().val.get(), lock) }
     }
 
     /// Returns a mutable reference to the inner value of type `T`.
@@ -132,7 +150,376 @@ impl<T> Arw<T> {
         let lock = self.inner().lock.clone();
         lock.lock_exclusive();
 
-        WatchGuardMut::new(self.inner().get_mut_ref(), lock)
+        self.write_guard(lock)
+    }
+
+    /// Like [`Arw::as_ref`], but fails with [`LockError::WouldBlock`] instead of waiting
+    /// for a writer.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::LockError;
+    ///
+    /// let a = Arw::new(1);
+    /// let w = a.as_mut();
+    /// assert_eq!(a.try_read().unwrap_err(), LockError::WouldBlock);
+    /// drop(w);
+    /// assert_eq!(*a.try_read().unwrap(), 1);
+    /// ```
+    pub fn try_read(&self) -> Result<WatchGuardRef<'_, T>, LockError> {
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_group() {
+            return Err(LockError::WouldBlock);
+        }
+
+        // SAFETY: the group lock is held
+        Ok(unsafe { WatchGuardRef::from_ptr(self.inner().val.get(), lock) })
+    }
+
+    /// Like [`Arw::as_mut`], but fails with [`LockError::WouldBlock`] instead of waiting
+    /// for the other guards.
+    pub fn try_write(&self) -> Result<WatchGuardMut<'_, T>, LockError> {
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_exclusive() {
+            return Err(LockError::WouldBlock);
+        }
+
+        Ok(self.write_guard(lock))
+    }
+
+    /// Like [`Arw::as_ref`], but fails with [`LockError::TimedOut`] if a writer still holds
+    /// the value after `timeout`.
+    #[cfg(feature = "std")]
+    pub fn read_timeout(&self, timeout: Duration) -> Result<WatchGuardRef<'_, T>, LockError> {
+        let lock = self.inner().lock.clone();
+        if !lock.lock_group_timeout(timeout) {
+            return Err(LockError::TimedOut);
+        }
+
+        // SAFETY: the group lock is held
+        Ok(unsafe { WatchGuardRef::from_ptr(self.inner().val.get(), lock) })
+    }
+
+    /// Like [`Arw::as_mut`], but fails with [`LockError::TimedOut`] if other guards still
+    /// hold the value after `timeout`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::LockError;
+    /// use std::time::Duration;
+    ///
+    /// let a = Arw::new(1);
+    /// let r = a.as_ref();
+    /// let res = a.write_timeout(Duration::from_millis(10));
+    /// assert_eq!(res.unwrap_err(), LockError::TimedOut);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_timeout(&self, timeout: Duration) -> Result<WatchGuardMut<'_, T>, LockError> {
+        let lock = self.inner().lock.clone();
+        if !lock.lock_exclusive_timeout(timeout) {
+            return Err(LockError::TimedOut);
+        }
+
+        Ok(self.write_guard(lock))
+    }
+
+    /// Like [`Arw::as_ref`], but the guard holds a clone of the `Arw` instead of borrowing
+    /// it, so it can outlive `self`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let g = Arw::new(7).read_owned();
+    /// assert_eq!(*g, 7);
+    /// ```
+    pub fn read_owned(&self) -> OwnedWatchGuardRef<Self, T> {
+        let lock = self.inner().lock.clone();
+        lock.lock_group();
+
+        // SAFETY: the value lives as long as the cloned `Arw`
+        unsafe { OwnedWatchGuardRef::new(self.clone(), self.inner().val.get(), lock) }
+    }
+
+    /// Like [`Arw::as_mut`], but the guard holds a clone of the `Arw` instead of borrowing
+    /// it, so it can outlive `self`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let mut g = Arw::new(7).write_owned();
+    /// *g += 1;
+    /// assert_eq!(*g, 8);
+    /// ```
+    pub fn write_owned(&self) -> OwnedWatchGuardMut<Self, T> {
+        let lock = self.inner().lock.clone();
+        lock.lock_exclusive();
+
+        // SAFETY: the value lives as long as the cloned `Arw`
+        unsafe {
+            OwnedWatchGuardMut::versioned(
+                self.clone(),
+                self.inner().val.get(),
+                lock,
+                &self.inner().version,
+            )
+        }
+    }
+
+    /// Runs `f` on the value under the group lock, released as soon as `f` returns.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(vec![1, 2, 3]);
+    /// assert_eq!(a.with(|v| v.len()), 3);
+    /// ```
+    pub fn with<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        f(&self.as_ref())
+    }
+
+    /// Runs `f` on the value under the exclusive lock, released as soon as `f` returns.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(vec![1]);
+    /// a.with_mut(|v| v.push(2));
+    /// assert_eq!(*a.as_ref(), vec![1, 2]);
+    /// ```
+    pub fn with_mut<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        f(&mut self.as_mut())
+    }
+
+    /// Returns a watcher of the changes made to the value, counting each write guard
+    /// released since now: from [`Arw::as_mut`], [`Arw::with_mut`], [`Arw::replace`] and
+    /// the other writing methods.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(0);
+    /// let w = a.subscribe();
+    /// a.replace(1);
+    /// assert!(w.has_changed());
+    /// ```
+    pub fn subscribe(&self) -> ArwWatcher<T> {
+        ArwWatcher::new(self.clone())
+    }
+
+    /// Attempts to extract the inner value if there is exactly one strong reference.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(123i32);
+    /// let value = Arw::try_unwrap(a).unwrap();
+    /// assert_eq!(value, 123i32);
+    /// ```
+    pub fn try_unwrap(this: Self) -> Result<T, Self>
+    where
+        T: Sized,
+    {
+        Self::unwrap_unique(this)
+    }
+
+    /// Returns a mutable reference to the value without locking, first cloning it into a
+    /// new allocation if other `Arw` or `WeakArw` share it.
+    ///
+    /// The other handles keep the old value, the weak ones can't upgrade anymore once
+    /// its last `Arw` is gone.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let mut a = Arw::new(vec![1]);
+    /// let b = a.clone();
+    /// a.make_mut().push(2);
+    ///
+    /// assert_eq!(*a.as_ref(), vec![1, 2]);
+    /// assert_eq!(*b.as_ref(), vec![1]);
+    /// assert!(!Arw::ptr_eq(&a, &b));
+    /// ```
+    pub fn make_mut(&mut self) -> &mut T
+    where
+        T: Clone,
+    {
+        self.make_unique()
+    }
+
+    pub unsafe fn from_raw(ptr: *const T) -> Self
+    where
+        T: Sized,
+    {
+        unsafe { Self::from_raw_in(ptr) }
+    }
+}
+
+impl<T: ?Sized, L: Level> Arw<T, L> {
+    /// Creates a new `Arw` at level `L` of the lock hierarchy, see [`crate::level`].
+    pub fn new_leveled(value: T) -> Self
+    where
+        T: Any + Sized,
+    {
+        unsafe { Self::from_inner(Box::leak(Box::new(ArwInner::new(value)))) }
+    }
+
+    /// Locks the value for reading with the token of a lower level, returning the token
+    /// of this level along with the guard.
+    pub fn as_ref_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> (WatchGuardRef<'a, T>, LockToken<'a, L>) {
+        let token = token.advance();
+        let lock = self.inner().lock.clone();
+        lock.lock_group();
+
+        // SAFETY: the group lock is held
+        let guard = unsafe { WatchGuardRef::from_ptr(self.inner().val.get(), lock) };
+        (guard, token)
+    }
+
+    /// Locks the value for writing with the token of a lower level, returning the token
+    /// of this level along with the guard.
+    pub fn as_mut_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> (WatchGuardMut<'a, T>, LockToken<'a, L>) {
+        let token = token.advance();
+        let lock = self.inner().lock.clone();
+        lock.lock_exclusive();
+
+        (self.write_guard(lock), token)
+    }
+
+    /// Like [`Arw::as_ref_leveled`], but fails with [`LockError::WouldBlock`] instead of
+    /// waiting if a write guard is held.
+    pub fn try_read_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> Result<(WatchGuardRef<'a, T>, LockToken<'a, L>), LockError> {
+        let token = token.advance();
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_group() {
+            return Err(LockError::WouldBlock);
+        }
+
+        // SAFETY: the group lock is held
+        let guard = unsafe { WatchGuardRef::from_ptr(self.inner().val.get(), lock) };
+        Ok((guard, token))
+    }
+
+    /// Like [`Arw::as_mut_leveled`], but fails with [`LockError::WouldBlock`] instead of
+    /// waiting if any guard is held.
+    pub fn try_write_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> Result<(WatchGuardMut<'a, T>, LockToken<'a, L>), LockError> {
+        let token = token.advance();
+        let lock = self.inner().lock.clone();
+        if !lock.try_lock_exclusive() {
+            return Err(LockError::WouldBlock);
+        }
+
+        Ok((self.write_guard(lock), token))
+    }
+
+    /// Like [`Arw::try_unwrap`], with the token of a lower level as it may wait for the
+    /// lock. The unleveled method isn't available on leveled handles:
+    /// ```compile_fail
+    /// use castbox::Arw;
+    /// use castbox::level::Level;
+    ///
+    /// enum Config {}
+    /// impl Level for Config {
+    ///     const LEVEL: u32 = 10;
+    /// }
+    ///
+    /// let a = Arw::<_, Config>::new_leveled(1);
+    /// let _ = Arw::try_unwrap(a);
+    /// ```
+    pub fn try_unwrap_leveled<P: Level>(this: Self, token: &mut LockToken<'_, P>) -> Result<T, Self>
+    where
+        T: Sized,
+    {
+        let _token = token.advance::<L>();
+        Self::unwrap_unique(this)
+    }
+
+    /// Like [`Arw::make_mut`], with the token of a lower level as it may wait for the lock
+    /// to clone a shared value.
+    pub fn make_mut_leveled<P: Level>(&mut self, token: &mut LockToken<'_, P>) -> &mut T
+    where
+        T: Clone,
+    {
+        let _token = token.advance::<L>();
+        self.make_unique()
+    }
+}
+
+impl<T: ?Sized, L> Arw<T, L> {
+    /// Extracts the value if `this` is the only strong reference, the caller is allowed to
+    /// take the lock.
+    fn unwrap_unique(this: Self) -> Result<T, Self>
+    where
+        T: Sized,
+    {
+        this.inner().lock.lock_exclusive();
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Acquire, Relaxed)
+            .is_err()
+        {
+            this.inner().lock.unlock_exclusive();
+            return Err(this);
+        }
+
+        atomic::fence(Acquire);
+
+        let this = ManuallyDrop::new(this);
+        let elem: T = unsafe { this.read_data() };
+
+        // Make a weak pointer to clean up the implicit strong-weak reference
+        let _weak = WeakArw::<T, L> {
+            ptr: this.ptr,
+            level: PhantomData,
+        };
+
+        // the value was moved out above, only the lock is left to drop
+        unsafe { ptr::drop_in_place(&mut (*this.get_mut_inner_ptr()).lock) }
+
+        Ok(elem)
+    }
+
+    pub(super) fn inner(&self) -> &ArwInner<T> {
+        // This unsafety is ok because while this Arw is alive we're guaranteed
+        // that the inner pointer is valid.
+        let ptr: *const ArwInner<T> = self.ptr;
+        unsafe { &*ptr }
+    }
+
+    /// Guard for the exclusive `lock` already taken, reporting the change to the watchers.
+    #[inline]
+    fn write_guard(&self, lock: Mutex) -> WatchGuardMut<'_, T> {
+        // SAFETY: `lock` is held exclusively
+        unsafe { WatchGuardMut::versioned(self.inner().val.get(), lock, &self.inner().version) }
+    }
+
+    fn inner_mut(&self) -> &mut ArwInner<T> {
+        let ptr: *mut ArwInner<T> = self.get_mut_inner_ptr();
+        unsafe { &mut *ptr }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner().lock.is_locked_exclusive()
     }
 
     /// Returns `true` if the `Arw` is the only strong reference to the value.
@@ -174,6 +561,49 @@ impl<T> Arw<T> {
         }
     }
 
+    /// Returns a mutable reference to the value without locking, if this is the only
+    /// reference to it, see [`Arw::is_unique`].
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let mut a = Arw::new(1);
+    /// *a.get_mut().unwrap() += 1;
+    ///
+    /// let b = a.clone();
+    /// assert!(a.get_mut().is_none());
+    /// drop(b);
+    /// assert_eq!(a.get_mut(), Some(&mut 2));
+    /// ```
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if Self::is_unique(self) {
+            // SAFETY: no other handle exists and `&mut self` rules out guards borrowing this
+            Some(unsafe { &mut *self.inner().val.get() })
+        } else {
+            None
+        }
+    }
+
+    /// Makes the value unique by cloning it if shared, the caller is allowed to take the
+    /// lock.
+    fn make_unique(&mut self) -> &mut T
+    where
+        T: Clone,
+    {
+        if !Self::is_unique(self) {
+            // the other handles may be writing, clone under the group lock
+            let lock = &self.inner().lock;
+            lock.lock_group();
+            let value = self.inner().get_ref().clone();
+            lock.unlock_group();
+
+            *self = unsafe { Self::from_inner(Box::leak(Box::new(ArwInner::new(value)))) };
+        }
+
+        // SAFETY: as in `get_mut`, the value is now unique
+        unsafe { &mut *self.inner().val.get() }
+    }
+
     /// Convert into a weak reference
     /// # Example
     ///
@@ -182,7 +612,7 @@ impl<T> Arw<T> {
     /// let five = Arw::new(5);
     /// let weak_five = Arw::downgrade(&five);
     /// ```
-    pub fn downgrade(&self) -> WeakArw<T> {
+    pub fn downgrade(&self) -> WeakArw<T, L> {
         // This Relaxed is OK because we're checking the value in the CAS
         // below.
         let mut cur = self.inner().weak.load(Relaxed);
@@ -213,7 +643,10 @@ impl<T> Arw<T> {
                 Ok(_) => {
                     // Make sure we do not create a dangling Weak
                     debug_assert!(!is_dangling(self.inner()));
-                    return WeakArw { ptr: self.ptr };
+                    return WeakArw {
+                        ptr: self.ptr,
+                        level: PhantomData,
+                    };
                 }
                 Err(old) => cur = old,
             }
@@ -256,7 +689,16 @@ impl<T> Arw<T> {
         ptr::addr_eq(this.get_mut_inner_ptr(), other.get_mut_inner_ptr())
     }
 
-    pub fn into_raw(self) -> *const T {
+    /// Address of the allocation, the same for all the handles to it.
+    #[inline]
+    pub(crate) fn addr(this: &Self) -> usize {
+        this.ptr.addr()
+    }
+
+    pub fn into_raw(self) -> *const T
+    where
+        T: Sized,
+    {
         // prevent auto drop
         let this = ManuallyDrop::new(self);
 
@@ -268,13 +710,89 @@ impl<T> Arw<T> {
 
         data_ptr
     }
+}
 
-    pub unsafe fn from_raw(ptr: *const T) -> Self {
-        unsafe { Self::from_raw_in(ptr) }
+impl<T, L> Arw<T, L> {
+    /// Converts into an `Arw` of an unsized type the value coerces to, like a trait object
+    /// or a slice, sharing allocation and lock with the other handles.
+    ///
+    /// `coerce` does the coercion, like `|v| v as &[T]` or `|v| v as &dyn Trait`.
+    ///
+    /// # Safety
+    /// `coerce` must return the value it is given, unsized to `U` by a coercion of `&T` to
+    /// `&U`: the metadata of the reference becomes the one of the whole handle, and the value
+    /// is dropped and accessed as a `U` from then on. A reference to a field, or to another
+    /// value of the same address and size, like one cast through a raw pointer or
+    /// transmuted, is undefined behavior.
+    ///
+    /// # Panics
+    /// Panics if `coerce` returns a reference that doesn't span the whole value, which only
+    /// catches some of the violations of the contract above.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use std::fmt::Debug;
+    ///
+    /// let a = Arw::new([1, 2, 3]);
+    /// // SAFETY: plain unsizing coercions of the value
+    /// let s = unsafe { Arw::unsize(a.clone(), |v| v as &[i32]) };
+    /// assert_eq!(s.as_ref().len(), 3);
+    ///
+    /// let d = unsafe { Arw::unsize(a, |v| v as &dyn Debug) };
+    /// assert_eq!(format!("{:?}", &*d.as_ref()), "[1, 2, 3]");
+    /// ```
+    pub unsafe fn unsize<U: ?Sized, F>(this: Self, coerce: F) -> Arw<U, L>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let value: *const T = this.inner().val.get();
+        let coerced: *const U = coerce(unsafe { &*value });
+        let whole = ptr::addr_eq(coerced, value)
+            && unsafe { size_of_val(&*coerced) == size_of::<T>() }
+            && unsafe { align_of_val(&*coerced) == align_of::<T>() };
+        assert!(whole, "Arw::unsize must be given back the whole value");
+
+        let this = ManuallyDrop::new(this);
+        // the pointer to the value carries the metadata, the handle the provenance
+        let inner = set_data_ptr(coerced as *mut ArwInner<U>, this.ptr as *mut u8);
+        unsafe { Arw::from_inner_in(inner) }
     }
 }
 
-impl<T> PtrInterface<T> for Arw<T> {
+impl<T: ?Sized> Arw<T> {
+    /// Moves the value out of `b` into a new `Arw`, unsized values like `Box<dyn Trait>`
+    /// or `Box<[T]>` included, without a second indirection.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use std::fmt::Display;
+    ///
+    /// let a: Arw<dyn Display> = Arw::from_box(Box::new(5));
+    /// assert_eq!(a.as_ref().to_string(), "5");
+    /// ```
+    pub fn from_box(b: Box<T>) -> Self {
+        let value_layout = Layout::for_value::<T>(&b);
+        let src = Box::into_raw(b);
+
+        unsafe {
+            let inner = ArwInner::allocate_for(value_layout, src);
+            ptr::copy_nonoverlapping(
+                src as *const u8,
+                ArwInner::val_ptr(inner) as *mut u8,
+                value_layout.size(),
+            );
+            // the value has been moved, only the box allocation is left to free
+            if value_layout.size() != 0 {
+                dealloc(src as *mut u8, value_layout);
+            }
+            Self::from_inner_in(inner)
+        }
+    }
+}
+
+impl<T: ?Sized, L> PtrInterface<T> for Arw<T, L> {
     #[inline]
     fn get_mut_inner_ptr(&self) -> *mut ArwInner<T> {
         self.ptr as *mut ArwInner<T>
@@ -283,17 +801,20 @@ impl<T> PtrInterface<T> for Arw<T> {
     #[inline]
     unsafe fn from_inner_in(ptr: *mut ArwInner<T>) -> Self {
         debug_assert!(!ptr.is_null());
-        Self { ptr }
+        Self {
+            ptr,
+            level: PhantomData,
+        }
     }
 }
 
-impl<T> Clone for Arw<T> {
+impl<T: ?Sized, L> Clone for Arw<T, L> {
     /// Makes a clone of the `Arw` pointer.
     ///
     /// This creates another pointer to the same allocation, increasing the
     /// strong reference count.
     #[inline]
-    fn clone(&self) -> Arw<T> {
+    fn clone(&self) -> Arw<T, L> {
         // Using a relaxed ordering is alright here, as knowledge of the
         // original reference prevents other threads from erroneously deleting
         // the object.
@@ -305,8 +826,8 @@ impl<T> Clone for Arw<T> {
     }
 }
 
-impl<T: Default> Default for Arw<T> {
-    fn default() -> Arw<T> {
+impl<T: Default, L> Default for Arw<T, L> {
+    fn default() -> Arw<T, L> {
         unsafe {
             Self::from_inner(Box::leak(Box::write(
                 Box::new_uninit(),
@@ -327,18 +848,70 @@ impl<T: Sized> Arw<T> {
     /// assert_eq!(a.as_ref(), 123);
     /// ```
     pub fn fill(this: Self, value: T) -> Self {
-        let ref_inner = &mut *this.inner_mut();
-        ref_inner.lock.lock_exclusive();
-        ref_inner.val = UnsafeCell::new(value);
-        ref_inner.lock.unlock_exclusive();
+        this.replace(value);
         this
     }
+
+    /// Replaces the value under the exclusive lock, returning the old one.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(1);
+    /// let b = a.clone();
+    /// assert_eq!(b.replace(2), 1);
+    /// assert_eq!(a.as_ref(), 2);
+    /// ```
+    pub fn replace(&self, value: T) -> T {
+        mem::replace(&mut *self.as_mut(), value)
+    }
+
+    /// Takes the value under the exclusive lock, leaving `T::default()` in its place.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(vec![1, 2]);
+    /// assert_eq!(a.take(), vec![1, 2]);
+    /// assert!(a.as_ref().is_empty());
+    /// ```
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        mem::take(&mut *self.as_mut())
+    }
+
+    /// Swaps the values of `this` and `other`, holding both exclusive locks.
+    ///
+    /// The locks are taken in address order, so concurrent swaps of the same pair in
+    /// opposite directions don't deadlock.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new("a");
+    /// let b = Arw::new("b");
+    /// Arw::swap(&a, &b);
+    /// assert_eq!((*a.as_ref(), *b.as_ref()), ("b", "a"));
+    /// ```
+    pub fn swap(this: &Self, other: &Self) {
+        if Arw::ptr_eq(this, other) {
+            return;
+        }
+        let (first, second) = if this.ptr.addr() < other.ptr.addr() {
+            (this, other)
+        } else {
+            (other, this)
+        };
+
+        let mut first = first.as_mut();
+        let mut second = second.as_mut();
+        mem::swap(&mut *first, &mut *second);
+    }
 }
 
-impl<T> Drop for Arw<T>
-where
-    T: Sized,
-{
+impl<T: ?Sized, L> Drop for Arw<T, L> {
     fn drop(&mut self) {
         // Because `fetch_sub` is already atomic, we do not need to synchronize
         // with other threads unless we are going to delete the object. This
@@ -349,7 +922,10 @@ where
 
         atomic::fence(Acquire);
 
-        let _weak = WeakArw { ptr: self.ptr };
+        let _weak = WeakArw::<T, L> {
+            ptr: self.ptr,
+            level: PhantomData,
+        };
 
         unsafe { ptr::drop_in_place(&mut (*self.get_mut_inner_ptr()).lock) }
 
@@ -417,19 +993,217 @@ impl<T: 'static> From<Box<T>> for Arw<Box<T>> {
     }
 }
 
-impl<T> fmt::Debug for Arw<T> {
+impl<T> From<Vec<T>> for Arw<[T]> {
+    /// Moves the elements of `v` into a new `Arw<[T]>`, a single allocation holding
+    /// counters, lock and elements.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a: Arw<[u8]> = Arw::from(vec![1, 2, 3]);
+    /// a.as_mut()[0] = 10;
+    /// assert_eq!(*a.as_ref(), [10, 2, 3]);
+    /// ```
+    fn from(mut v: Vec<T>) -> Self {
+        let len = v.len();
+        let layout = Layout::array::<T>(len).expect("slice too large for an Arw");
+
+        unsafe {
+            let meta = ptr::slice_from_raw_parts(ptr::null::<T>(), len);
+            let inner = ArwInner::allocate_for(layout, meta);
+            ptr::copy_nonoverlapping(v.as_ptr(), ArwInner::val_ptr(inner) as *mut T, len);
+            // the elements have been moved, only the buffer is left to free
+            v.set_len(0);
+            Self::from_inner_in(inner)
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arw<[T]> {
+    /// Clones the elements of `v` into a new `Arw<[T]>`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a: Arw<[i32]> = Arw::from(&[1, 2][..]);
+    /// assert_eq!(a.as_ref().len(), 2);
+    /// ```
+    #[inline]
+    fn from(v: &[T]) -> Self {
+        Arw::from(v.to_vec())
+    }
+}
+
+impl From<String> for Arw<str> {
+    /// Moves the text of `s` into a new `Arw<str>`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a: Arw<str> = Arw::from(String::from("hello"));
+    /// assert_eq!(&*a.as_ref(), "hello");
+    /// ```
+    fn from(s: String) -> Self {
+        let bytes = ManuallyDrop::new(Arw::<[u8]>::from(s.into_bytes()));
+        // SAFETY: `str` has the layout of `[u8]` and the bytes come from a `String`
+        unsafe { Self::from_inner_in(bytes.ptr as *mut ArwInner<str>) }
+    }
+}
+
+impl Arw<str> {
+    /// Copies `s` into a new `Arw<str>`.
+    ///
+    /// Not a `From<&str>` impl, which would make `Arw::from("..")` ambiguous with the
+    /// `Arw<String>` conversion.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::copy_from_str("hello");
+    /// assert_eq!(a.as_ref().len(), 5);
+    /// ```
+    pub fn copy_from_str(s: &str) -> Self {
+        let bytes = ManuallyDrop::new(Arw::<[u8]>::from(s.as_bytes()));
+        // SAFETY: `str` has the layout of `[u8]` and the bytes come from a `str`
+        unsafe { Self::from_inner_in(bytes.ptr as *mut ArwInner<str>) }
+    }
+}
+
+impl<T: ?Sized> Arw<T> {
+    /// Runs `f` on the values of `this` and `other` under their group locks.
+    ///
+    /// The locks are taken in address order like in [`Arw::swap`], and only once if both
+    /// share the allocation: a second group lock could queue behind a writer waiting for
+    /// the first one.
+    fn with_pair<R, F>(this: &Self, other: &Self, f: F) -> R
+    where
+        F: FnOnce(&T, &T) -> R,
+    {
+        if Arw::ptr_eq(this, other) {
+            return this.with(|value| f(value, value));
+        }
+        if this.ptr.addr() < other.ptr.addr() {
+            let this = this.as_ref();
+            f(&this, &other.as_ref())
+        } else {
+            let other = other.as_ref();
+            f(&this.as_ref(), &other)
+        }
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for Arw<T> {
+    /// Compares the values under their group locks, see [`ByAddress`](crate::ByAddress)
+    /// to compare the allocations instead.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(5);
+    /// assert!(a == Arw::new(5));
+    /// assert!(a == a.clone());
+    /// ```
+    fn eq(&self, other: &Self) -> bool {
+        Arw::with_pair(self, other, |a, b| a == b)
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Arw<T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Arw<T> {
+    /// Compares the values under their group locks.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Arw::with_pair(self, other, |a, b| a.partial_cmp(b))
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Arw<T> {
+    /// Compares the values under their group locks.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let mut v = vec![Arw::new(3), Arw::new(1), Arw::new(2)];
+    /// v.sort();
+    /// assert_eq!(v, [Arw::new(1), Arw::new(2), Arw::new(3)]);
+    /// ```
+    fn cmp(&self, other: &Self) -> Ordering {
+        Arw::with_pair(self, other, |a, b| a.cmp(b))
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for Arw<T> {
+    /// Hashes the value under the group lock.
+    ///
+    /// Changing the value of an `Arw` used as a key changes its hash, like for any other
+    /// interior mutable key.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.with(|value| value.hash(state));
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Arw<T> {
+    /// Formats the value under the group lock.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// assert_eq!(Arw::new(7).to_string(), "7");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with(|value| fmt::Display::fmt(value, f))
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, L> fmt::Debug for Arw<T, L> {
+    /// Shows the value, or `<locked>` while it is locked for writing, along with the
+    /// reference counts.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(1);
+    /// assert_eq!(format!("{a:?}"), "Arw { data: 1, S: 1, W: 1 }");
+    /// let _g = a.as_mut();
+    /// assert_eq!(format!("{a:?}"), "Arw { data: <locked>, S: 1, W: 1 }");
+    /// ```
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let inner = self.inner();
-        f.debug_struct("Arw")
-            .field("S", &inner.strong)
-            .field("W", &inner.weak)
-            .field("locked", &inner.lock.is_locked_exclusive())
-            .finish()
+        let mut d = f.debug_struct("Arw");
+        // never blocks, so it doesn't need a token for leveled values
+        let lock = inner.lock.clone();
+        if lock.try_lock_group() {
+            // SAFETY: the group lock is held
+            d.field("data", &&*unsafe { WatchGuardRef::from_ptr(inner.val.get(), lock) });
+        } else {
+            d.field("data", &format_args!("<locked>"));
+        }
+        d.field("S", &inner.strong).field("W", &inner.weak).finish()
     }
 }
 
-impl<T> fmt::Pointer for Arw<T> {
+impl<T: ?Sized, L> fmt::Pointer for Arw<T, L> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Pointer::fmt(&self.inner().val.get(), f)
     }
 }
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized + Serialize> Serialize for Arw<T> {
+    /// Serializes the value under the group lock.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.with(|value| value.serialize(serializer))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: ?Sized> Deserialize<'de> for Arw<T>
+where
+    Box<T>: Deserialize<'de>,
+{
+    /// Deserializes a new `Arw`: handles sharing an allocation when serialized are read
+    /// back as separate ones, unless written with [`crate::serde::shared`].
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Box::<T>::deserialize(deserializer).map(Arw::from_box)
+    }
+}
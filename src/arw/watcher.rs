@@ -0,0 +1,149 @@
+use crate::arw::Arw;
+use crate::mutex::WatchGuardRef;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Receives the changes made to an [`Arw`] through its write guards, see [`Arw::subscribe`].
+///
+/// The watcher remembers the last change it has seen: [`ArwWatcher::has_changed`] tells if
+/// a newer one happened and [`ArwWatcher::changed`] waits for it. A guard released without
+/// writing can opt out with
+/// [`WatchGuardMut::mark_unchanged`](crate::mutex::WatchGuardMut::mark_unchanged).
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use castbox::Arw;
+/// use std::thread;
+///
+/// let config = Arw::new(String::from("slow"));
+/// let mut watcher = config.subscribe();
+///
+/// let h = thread::spawn(move || {
+///     watcher.changed();
+///     watcher.borrow_and_update().clone()
+/// });
+/// *config.as_mut() = String::from("fast");
+/// assert_eq!(h.join().unwrap(), "fast");
+/// # }
+/// ```
+pub struct ArwWatcher<T: ?Sized> {
+    arw: Arw<T>,
+    /// version of the last change seen
+    seen: usize,
+}
+
+impl<T: ?Sized> ArwWatcher<T> {
+    pub(crate) fn new(arw: Arw<T>) -> Self {
+        let seen = arw.inner().version.watch();
+        Self { arw, seen }
+    }
+
+    /// Returns `true` if the value changed since it was last seen.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// let a = Arw::new(1);
+    /// let mut w = a.subscribe();
+    /// assert!(!w.has_changed());
+    /// *a.as_mut() += 1;
+    /// assert!(w.has_changed());
+    /// assert_eq!(*w.borrow_and_update(), 2);
+    /// assert!(!w.has_changed());
+    /// ```
+    #[inline]
+    pub fn has_changed(&self) -> bool {
+        self.arw.inner().version.get() != self.seen
+    }
+
+    /// Locks the value for reading, without marking it as seen.
+    #[inline]
+    pub fn borrow(&self) -> WatchGuardRef<'_, T> {
+        self.arw.as_ref()
+    }
+
+    /// Locks the value for reading and marks it as seen.
+    pub fn borrow_and_update(&mut self) -> WatchGuardRef<'_, T> {
+        let guard = self.arw.as_ref();
+        // read under the lock: a change not counted yet is reported again, never missed
+        self.seen = self.arw.inner().version.get();
+        guard
+    }
+
+    /// Marks the current value as seen, without locking it.
+    #[inline]
+    pub fn mark_seen(&mut self) {
+        self.seen = self.arw.inner().version.get();
+    }
+
+    /// Blocks until the value changes from the last one seen, and marks it as seen. Returns
+    /// at once if it already did.
+    #[cfg(feature = "std")]
+    pub fn changed(&mut self) {
+        self.changed_until(None);
+    }
+
+    /// Like [`ArwWatcher::changed`], but gives up after `timeout`, returning `false`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use std::time::Duration;
+    ///
+    /// let a = Arw::new(1);
+    /// let mut w = a.subscribe();
+    /// assert!(!w.changed_timeout(Duration::from_millis(10)));
+    /// a.with_mut(|v| *v = 2);
+    /// assert!(w.changed_timeout(Duration::from_millis(10)));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn changed_timeout(&mut self, timeout: Duration) -> bool {
+        self.changed_until(Some(Instant::now() + timeout))
+    }
+
+    #[cfg(feature = "std")]
+    fn changed_until(&mut self, deadline: Option<Instant>) -> bool {
+        let version = &self.arw.inner().version;
+        if !version.wait_past(self.seen, deadline) {
+            return false;
+        }
+        self.seen = version.get();
+        true
+    }
+
+    /// The `Arw` being watched.
+    #[inline]
+    pub fn arw(&self) -> &Arw<T> {
+        &self.arw
+    }
+}
+
+impl<T: ?Sized> Clone for ArwWatcher<T> {
+    /// Makes a watcher of the same value, with the same changes seen.
+    fn clone(&self) -> Self {
+        self.arw.inner().version.watch();
+        Self {
+            arw: self.arw.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for ArwWatcher<T> {
+    fn drop(&mut self) {
+        self.arw.inner().version.unwatch();
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for ArwWatcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArwWatcher")
+            .field("seen", &self.seen)
+            .field("changed", &self.has_changed())
+            .finish()
+    }
+}
@@ -1,27 +1,31 @@
 use crate::arw::inner::{ArwInner, MAX_REFCOUNT};
 use crate::arw::ptr_interface::PtrInterface;
 use crate::arw::Arw;
-use crate::utils::is_dangling;
-use std::alloc::{dealloc, Layout};
-use std::num::NonZeroUsize;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::process::abort;
-use std::ptr;
-use std::sync::atomic;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use crate::level::Unleveled;
+use crate::utils::{abort, is_dangling};
+use alloc::alloc::{Layout, dealloc};
+use core::marker::PhantomData;
+use core::num::NonZeroUsize;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::ptr;
+use core::sync::atomic;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[repr(transparent)]
-pub struct WeakArw<T: Sized> {
+pub struct WeakArw<T: ?Sized, L = Unleveled> {
     pub(crate) ptr: *const ArwInner<T>,
+    pub(crate) level: PhantomData<fn() -> L>,
 }
 
-unsafe impl<T: Sized + Sync + Send> Send for WeakArw<T> {}
-unsafe impl<T: Sized + Sync + Send> Sync for WeakArw<T> {}
+unsafe impl<T: ?Sized + Sync + Send, L> Send for WeakArw<T, L> {}
+unsafe impl<T: ?Sized + Sync + Send, L> Sync for WeakArw<T, L> {}
 
-impl<T> UnwindSafe for WeakArw<T> {}
-impl<T> RefUnwindSafe for WeakArw<T> {}
+impl<T: ?Sized, L> UnwindSafe for WeakArw<T, L> {}
+impl<T: ?Sized, L> RefUnwindSafe for WeakArw<T, L> {}
 
-impl<T> WeakArw<T> {
+impl<T, L> WeakArw<T, L> {
     /// Constructs a new `WeakARW`, without allocating any memory.
     /// Calling [`upgrade`] on the return value always gives [`None`].
     ///
@@ -31,13 +35,18 @@ impl<T> WeakArw<T> {
     /// assert!(empty.upgrade().is_none());
     /// ```
     #[inline]
-    pub const fn new() -> WeakArw<T> {
+    pub const fn new() -> WeakArw<T, L> {
         let ptr: *const ArwInner<T> = ptr::without_provenance(NonZeroUsize::MAX.get());
         let ptr = ptr as *mut ArwInner<T>;
         // SAFETY: we know `addr` is non-zero.
-        WeakArw { ptr }
+        WeakArw {
+            ptr,
+            level: PhantomData,
+        }
     }
+}
 
+impl<T: ?Sized, L> WeakArw<T, L> {
     /// Attempts to upgrade the weak reference to a strong one.
     /// Returns `None` if the value has been dropped.
     ///
@@ -49,7 +58,7 @@ impl<T> WeakArw<T> {
     /// drop(a);
     /// assert!(w.upgrade().is_none());
     /// ```
-    pub fn upgrade(&self) -> Option<Arw<T>> {
+    pub fn upgrade(&self) -> Option<Arw<T, L>> {
         #[inline]
         fn checked_increment(n: usize) -> Option<usize> {
             if n == 0 {
@@ -121,7 +130,7 @@ impl<T> WeakArw<T> {
     }
 }
 
-impl<T> Clone for WeakArw<T> {
+impl<T: ?Sized, L> Clone for WeakArw<T, L> {
     /// Clones the weak reference, incrementing the weak count.
     ///
     /// # Example
@@ -131,7 +140,7 @@ impl<T> Clone for WeakArw<T> {
     /// let w1 = a.downgrade();
     /// let w2 = w1.clone();
     /// ```
-    fn clone(&self) -> WeakArw<T> {
+    fn clone(&self) -> WeakArw<T, L> {
         if let Some(inner) = self.inner() {
             let old_size = inner.weak.fetch_add(1, Relaxed);
 
@@ -140,11 +149,14 @@ impl<T> Clone for WeakArw<T> {
             }
         }
 
-        Self { ptr: self.ptr }
+        Self {
+            ptr: self.ptr,
+            level: PhantomData,
+        }
     }
 }
 
-impl<T> Default for WeakArw<T> {
+impl<T, L> Default for WeakArw<T, L> {
     /// Constructs a new `Weak<T>`, without allocating memory.
     /// Calling [`upgrade`] on the return value always
     /// gives [`None`].
@@ -155,12 +167,12 @@ impl<T> Default for WeakArw<T> {
     /// let empty: WeakArw<String> = Default::default();
     /// assert!(empty.upgrade().is_none());
     /// ```
-    fn default() -> WeakArw<T> {
+    fn default() -> WeakArw<T, L> {
         WeakArw::new()
     }
 }
 
-impl<T> PtrInterface<T> for WeakArw<T> {
+impl<T: ?Sized, L> PtrInterface<T> for WeakArw<T, L> {
     #[inline]
     fn get_mut_inner_ptr(&self) -> *mut ArwInner<T> {
         self.ptr as *mut ArwInner<T>
@@ -169,11 +181,14 @@ impl<T> PtrInterface<T> for WeakArw<T> {
     #[inline]
     unsafe fn from_inner_in(ptr: *mut ArwInner<T>) -> Self {
         debug_assert!(!ptr.is_null());
-        Self { ptr }
+        Self {
+            ptr,
+            level: PhantomData,
+        }
     }
 }
 
-impl<T> Drop for WeakArw<T> {
+impl<T: ?Sized, L> Drop for WeakArw<T, L> {
     fn drop(&mut self) {
         let inner = if let Some(inner) = self.inner() {
             inner
@@ -183,7 +198,8 @@ impl<T> Drop for WeakArw<T> {
         if inner.weak.fetch_sub(1, Release) == 1 {
             atomic::fence(Acquire);
 
-            let layout = Layout::new::<ArwInner<T>>();
+            // the value is dropped, only its size and alignment are read
+            let layout = Layout::for_value(inner);
             let ptr = self.ptr as *mut u8;
 
             unsafe {
@@ -192,3 +208,19 @@ impl<T> Drop for WeakArw<T> {
         }
     }
 }
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized + Serialize> Serialize for WeakArw<T> {
+    /// Serializes the value as `Some`, or `None` if it has been dropped.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.upgrade().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for WeakArw<T> {
+    /// Reads the value and drops it: nothing else holds it, so the result never upgrades.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Option::<T>::deserialize(deserializer).map(|_| WeakArw::new())
+    }
+}
@@ -0,0 +1,345 @@
+//! C ABI, enabled by the `capi` feature.
+//!
+//! Exports a [`Mutex`], a byte buffer behind an [`Arw`] and an [`AtomicHashMap`] of byte
+//! strings as opaque handles, declared in `include/castbox.h`. Each handle is a reference:
+//! `*_clone` makes another one to the same lock, buffer or map, which can be handed to
+//! another thread or plugin, and `*_free` releases it.
+//!
+//! The functions don't unwind into C: a panic aborts the process.
+
+use crate::Arw;
+use crate::collections::AtomicHashMap;
+use crate::mutex::Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::{ptr, slice};
+
+/// A reference to a lock with exclusive and shared modes.
+pub struct CastboxMutex(Mutex);
+
+/// A reference to a byte buffer guarded by a lock.
+pub struct CastboxBytes(Arw<Vec<u8>>);
+
+/// A reference to a concurrent map from byte strings to byte strings.
+pub struct CastboxMap(AtomicHashMap<Vec<u8>, Vec<u8>>);
+
+/// A byte string allocated by castbox, released with `castbox_buf_free`.
+#[repr(C)]
+pub struct CastboxBuf {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Called with the contents of a buffer, which stay valid only during the call.
+pub type CastboxReadFn = extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize);
+
+/// Called with the contents of a buffer to update them in place, which stay valid only
+/// during the call.
+pub type CastboxWriteFn = extern "C" fn(ctx: *mut c_void, data: *mut u8, len: usize);
+
+impl CastboxBuf {
+    fn new(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+
+    const fn empty() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// Borrows `len` bytes at `data`, which may be null when `len` is 0.
+///
+/// # Safety
+/// `data` must be valid for reads of `len` bytes.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Releases a handle made by `Box::into_raw`, if not null.
+///
+/// # Safety
+/// `handle` must be null or a live handle, not used anymore afterwards.
+unsafe fn free<T>(handle: *mut T) {
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// Releases the bytes of `buf`. Does nothing for a buffer already released or left empty.
+///
+/// # Safety
+/// `buf` must be null or point to a buffer filled by castbox.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_buf_free(buf: *mut CastboxBuf) {
+    let Some(buf) = (unsafe { buf.as_mut() }) else {
+        return;
+    };
+    if !buf.data.is_null() {
+        let bytes = ptr::slice_from_raw_parts_mut(buf.data, buf.len);
+        drop(unsafe { Box::from_raw(bytes) });
+    }
+    *buf = CastboxBuf::empty();
+}
+
+/// Creates an unlocked mutex.
+#[unsafe(no_mangle)]
+pub extern "C" fn castbox_mutex_new() -> *mut CastboxMutex {
+    Box::into_raw(Box::new(CastboxMutex(Mutex::new())))
+}
+
+/// Makes another reference to the same lock.
+///
+/// # Safety
+/// `mutex` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_clone(mutex: *const CastboxMutex) -> *mut CastboxMutex {
+    let mutex = unsafe { &*mutex };
+    Box::into_raw(Box::new(CastboxMutex(mutex.0.clone())))
+}
+
+/// Releases a reference, the lock is freed with the last one. Does nothing if null.
+///
+/// # Safety
+/// `mutex` must be null or a live handle, not used anymore afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_free(mutex: *mut CastboxMutex) {
+    unsafe { free(mutex) }
+}
+
+/// Takes the lock exclusively, blocking until it is free.
+///
+/// # Safety
+/// `mutex` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_lock(mutex: *const CastboxMutex) {
+    unsafe { &*mutex }.0.lock_exclusive();
+}
+
+/// Takes the lock exclusively if it is free, returning whether it did.
+///
+/// # Safety
+/// `mutex` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_try_lock(mutex: *const CastboxMutex) -> bool {
+    unsafe { &*mutex }.0.try_lock_exclusive()
+}
+
+/// Releases the lock taken by `castbox_mutex_lock` or `castbox_mutex_try_lock`.
+///
+/// # Safety
+/// `mutex` must be a live handle, locked exclusively by the caller.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_unlock(mutex: *const CastboxMutex) {
+    unsafe { &*mutex }.0.unlock_exclusive();
+}
+
+/// Takes the lock shared with the other readers, blocking while it is held exclusively.
+///
+/// # Safety
+/// `mutex` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_lock_shared(mutex: *const CastboxMutex) {
+    unsafe { &*mutex }.0.lock_group();
+}
+
+/// Releases the lock taken by `castbox_mutex_lock_shared`.
+///
+/// # Safety
+/// `mutex` must be a live handle, locked shared by the caller.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_mutex_unlock_shared(mutex: *const CastboxMutex) {
+    unsafe { &*mutex }.0.unlock_group();
+}
+
+/// Creates a buffer holding a copy of the `len` bytes at `data`, which may be null when
+/// `len` is 0.
+///
+/// # Safety
+/// `data` must be valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_new(data: *const u8, len: usize) -> *mut CastboxBytes {
+    let value = unsafe { bytes(data, len) }.to_vec();
+    Box::into_raw(Box::new(CastboxBytes(Arw::new(value))))
+}
+
+/// Makes another reference to the same buffer.
+///
+/// # Safety
+/// `buf` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_clone(buf: *const CastboxBytes) -> *mut CastboxBytes {
+    let buf = unsafe { &*buf };
+    Box::into_raw(Box::new(CastboxBytes(buf.0.clone())))
+}
+
+/// Releases a reference, the buffer is freed with the last one. Does nothing if null.
+///
+/// # Safety
+/// `buf` must be null or a live handle, not used anymore afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_free(buf: *mut CastboxBytes) {
+    unsafe { free(buf) }
+}
+
+/// Returns the length of the buffer.
+///
+/// # Safety
+/// `buf` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_len(buf: *const CastboxBytes) -> usize {
+    unsafe { &*buf }.0.with(Vec::len)
+}
+
+/// Calls `read` with the contents of the buffer, under its shared lock.
+///
+/// # Safety
+/// `buf` must be a live handle, and `read` must not use it to write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_read(
+    buf: *const CastboxBytes,
+    read: CastboxReadFn,
+    ctx: *mut c_void,
+) {
+    unsafe { &*buf }
+        .0
+        .with(|value| read(ctx, value.as_ptr(), value.len()));
+}
+
+/// Calls `write` with the contents of the buffer, under its exclusive lock.
+///
+/// # Safety
+/// `buf` must be a live handle, and `write` must not use it.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_write(
+    buf: *const CastboxBytes,
+    write: CastboxWriteFn,
+    ctx: *mut c_void,
+) {
+    unsafe { &*buf }
+        .0
+        .with_mut(|value| write(ctx, value.as_mut_ptr(), value.len()));
+}
+
+/// Replaces the contents of the buffer with a copy of the `len` bytes at `data`, which
+/// may be null when `len` is 0.
+///
+/// # Safety
+/// `buf` must be a live handle and `data` valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_bytes_set(buf: *const CastboxBytes, data: *const u8, len: usize) {
+    let data = unsafe { bytes(data, len) };
+    unsafe { &*buf }.0.with_mut(|value| {
+        value.clear();
+        value.extend_from_slice(data);
+    });
+}
+
+/// Creates an empty map.
+#[unsafe(no_mangle)]
+pub extern "C" fn castbox_map_new() -> *mut CastboxMap {
+    Box::into_raw(Box::new(CastboxMap(AtomicHashMap::new())))
+}
+
+/// Makes another reference to the same map.
+///
+/// # Safety
+/// `map` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_clone(map: *const CastboxMap) -> *mut CastboxMap {
+    let map = unsafe { &*map };
+    Box::into_raw(Box::new(CastboxMap(map.0.clone())))
+}
+
+/// Releases a reference, the map is freed with the last one. Does nothing if null.
+///
+/// # Safety
+/// `map` must be null or a live handle, not used anymore afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_free(map: *mut CastboxMap) {
+    unsafe { free(map) }
+}
+
+/// Returns the number of entries.
+///
+/// # Safety
+/// `map` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_len(map: *const CastboxMap) -> usize {
+    unsafe { &*map }.0.len()
+}
+
+/// Inserts copies of the key and the value, replacing the value already stored for the key.
+///
+/// # Safety
+/// `map` must be a live handle, `key` valid for reads of `key_len` bytes and `value` of
+/// `value_len` bytes. Either may be null when its length is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_insert(
+    map: *const CastboxMap,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    let key = unsafe { bytes(key, key_len) }.to_vec();
+    let value = unsafe { bytes(value, value_len) }.to_vec();
+    unsafe { &*map }.0.insert(key, value);
+}
+
+/// Copies the value of the key into `out`, to be released with `castbox_buf_free`.
+/// Returns `false` and leaves `out` untouched if the key is missing.
+///
+/// # Safety
+/// `map` must be a live handle, `key` valid for reads of `key_len` bytes, or null when
+/// it is 0, and `out` valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_get(
+    map: *const CastboxMap,
+    key: *const u8,
+    key_len: usize,
+    out: *mut CastboxBuf,
+) -> bool {
+    let key = unsafe { bytes(key, key_len) };
+    match unsafe { &*map }.0.get_cloned(key) {
+        Some(value) => {
+            unsafe { out.write(CastboxBuf::new(value)) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes the key, moving its value into `out` unless it is null. Returns `false` if the
+/// key is missing.
+///
+/// # Safety
+/// `map` must be a live handle, `key` valid for reads of `key_len` bytes, or null when
+/// it is 0, and `out` null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn castbox_map_remove(
+    map: *const CastboxMap,
+    key: *const u8,
+    key_len: usize,
+    out: *mut CastboxBuf,
+) -> bool {
+    let key = unsafe { bytes(key, key_len) };
+    match unsafe { &*map }.0.remove(key) {
+        Some(value) => {
+            if !out.is_null() {
+                unsafe { out.write(CastboxBuf::new(value)) };
+            }
+            true
+        }
+        None => false,
+    }
+}
@@ -1,13 +1,24 @@
-use crate::mutex::{Backoff, Mutex, WatchGuardMut, WatchGuardRef};
-use std::borrow::Borrow;
+use crate::mutex::{Backoff, MappedWatchGuardMut, MappedWatchGuardRef, Mutex, OwnedWatchGuardRef};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, Hash};
+#[cfg(feature = "std")]
+use core::hash::BuildHasherDefault;
+#[cfg(feature = "std")]
 use std::collections::hash_map::DefaultHasher;
-use std::fmt;
-use std::hash::{Hash, Hasher};
-use std::mem::ManuallyDrop;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::ptr::{self, null_mut};
-use std::sync::atomic;
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use core::mem::ManuallyDrop;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::ptr::{self, null_mut};
+use core::sync::atomic;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+#[cfg(feature = "serde")]
+use serde::de::{MapAccess, Visitor};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 const BUCKET_AVAILABLE: bool = true;
 const BUCKET_UPDATING: bool = false;
@@ -67,24 +78,39 @@ impl<K, V> Bucket<K, V> {
     }
 }
 
-struct AtomicInner<K, V> {
+struct AtomicInner<K, V, S> {
     buckets: Vec<Bucket<K, V>>,
+    hash_builder: S,
     lock: Mutex,
     len: AtomicUsize,
     ref_count: AtomicUsize,
 }
 
+/// The hasher used by [`AtomicHashMap`] when none is supplied.
+#[cfg(feature = "std")]
+pub type DefaultHashBuilder = BuildHasherDefault<DefaultHasher>;
+
+#[cfg(feature = "std")]
+#[repr(transparent)]
+pub struct AtomicHashMap<K, V, S = DefaultHashBuilder> {
+    ptr: *const AtomicInner<K, V, S>,
+}
+
+/// Without `std` there is no default hasher, one must be supplied through
+/// [`AtomicHashMap::with_hasher`].
+#[cfg(not(feature = "std"))]
 #[repr(transparent)]
-pub struct AtomicHashMap<K, V> {
-    ptr: *const AtomicInner<K, V>,
+pub struct AtomicHashMap<K, V, S> {
+    ptr: *const AtomicInner<K, V, S>,
 }
 
-unsafe impl<K: Send, V: Send> Send for AtomicHashMap<K, V> {}
-unsafe impl<K: Send, V: Send> Sync for AtomicHashMap<K, V> {}
+unsafe impl<K: Send, V: Send, S: Send + Sync> Send for AtomicHashMap<K, V, S> {}
+unsafe impl<K: Send, V: Send, S: Send + Sync> Sync for AtomicHashMap<K, V, S> {}
 
-impl<K, V> UnwindSafe for AtomicHashMap<K, V> {}
-impl<K, V> RefUnwindSafe for AtomicHashMap<K, V> {}
+impl<K, V, S> UnwindSafe for AtomicHashMap<K, V, S> {}
+impl<K, V, S> RefUnwindSafe for AtomicHashMap<K, V, S> {}
 
+#[cfg(feature = "std")]
 impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
     /// Create a new AtomicHashMap with default buckets size
     pub fn new() -> Self {
@@ -93,9 +119,22 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
 
     /// Create a new AtomicHashMap with specified buckets size
     pub fn with_capacity(bucket_count: usize) -> Self {
+        Self::with_capacity_and_hasher(bucket_count, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> AtomicHashMap<K, V, S> {
+    /// Create a new AtomicHashMap with default buckets size which will use the given hash builder
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_capacity_and_hasher(DEFAULT_BUCKETS, hash_builder)
+    }
+
+    /// Create a new AtomicHashMap with specified buckets size which will use the given hash builder
+    pub fn with_capacity_and_hasher(bucket_count: usize, hash_builder: S) -> Self {
         let buckets = (0..bucket_count).map(|_| Bucket::new()).collect();
         let ptr = Box::into_raw(Box::new(AtomicInner {
             buckets,
+            hash_builder,
             len: AtomicUsize::new(0),
             ref_count: AtomicUsize::new(1),
             lock: Mutex::new(),
@@ -104,14 +143,12 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
     }
 
     #[inline(always)]
-    fn inner(&self) -> &AtomicInner<K, V> {
+    fn inner(&self) -> &AtomicInner<K, V, S> {
         unsafe { &*self.ptr }
     }
 
-    fn hash<Q: ?Sized + Hash>(key: &Q) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        hasher.finish()
+    fn hash<Q: ?Sized + Hash>(&self, key: &Q) -> u64 {
+        self.inner().hash_builder.hash_one(key)
     }
 
     pub fn insert(&self, key: K, value: V) {
@@ -151,7 +188,19 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
         self.inner().lock.unlock_group();
     }
 
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<WatchGuardRef<'_, V>>
+    /// Gives a guard on the value of the key, holding the group lock of its entry.
+    ///
+    /// The entry can be removed once the lock is released, so the guard holds it until
+    /// dropped:
+    /// ```compile_fail
+    /// use castbox::collections::AtomicHashMap;
+    ///
+    /// let map = AtomicHashMap::new();
+    /// map.insert(1, vec![1]);
+    /// let mut v = map.get(&1).unwrap();
+    /// v.unlocked(|| map.remove(&1));
+    /// ```
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<MappedWatchGuardRef<'_, V>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
@@ -167,7 +216,57 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
             unsafe {
                 if (*cur).key.borrow() == key {
                     bucket.ref_locked.lock_group();
-                    let w_ref = WatchGuardRef::new(&*(*cur).value, bucket.ref_locked.clone());
+                    let value = ptr::addr_of!((*cur).value).cast::<V>();
+                    let w_ref = MappedWatchGuardRef::from_ptr(value, bucket.ref_locked.clone());
+                    bucket.release();
+                    self.inner().lock.unlock_group();
+                    return Some(w_ref);
+                }
+                cur = (*cur).next.load(Ordering::Acquire);
+            }
+        }
+
+        bucket.release();
+        self.inner().lock.unlock_group();
+        None
+    }
+
+    /// Like [`AtomicHashMap::get`], but the guard holds a clone of the map instead of
+    /// borrowing it, so it can outlive `self`.
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use castbox::collections::AtomicHashMap;
+    ///
+    /// let map = AtomicHashMap::new();
+    /// map.insert("a", 1);
+    /// let g = map.get_owned("a").unwrap();
+    /// drop(map);
+    /// assert_eq!(*g, 1);
+    /// # }
+    /// ```
+    pub fn get_owned<Q>(&self, key: &Q) -> Option<OwnedWatchGuardRef<Self, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let bucket = self.find_bucket(key)?;
+
+        // handle iter locking
+        self.inner().lock.lock_group();
+        bucket.lock();
+
+        let mut cur = bucket.head.load(Ordering::Acquire);
+        while !cur.is_null() {
+            unsafe {
+                if (*cur).key.borrow() == key {
+                    bucket.ref_locked.lock_group();
+                    // SAFETY: the item is only freed under the exclusive bucket lock and
+                    // the buckets live as long as the cloned map
+                    let value = ptr::addr_of!((*cur).value).cast::<V>();
+                    let w_ref =
+                        OwnedWatchGuardRef::new(self.clone(), value, bucket.ref_locked.clone());
                     bucket.release();
                     self.inner().lock.unlock_group();
                     return Some(w_ref);
@@ -181,7 +280,7 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
         None
     }
 
-    pub fn get_mut<Q: ?Sized>(&self, key: &Q) -> Option<WatchGuardMut<'_, V>>
+    pub fn get_mut<Q: ?Sized>(&self, key: &Q) -> Option<MappedWatchGuardMut<'_, V>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
@@ -198,7 +297,8 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
             unsafe {
                 if (*cur).key.borrow() == key {
                     bucket.ref_locked.lock_exclusive();
-                    let w_ref = WatchGuardMut::new(&mut *(*cur).value, bucket.ref_locked.clone());
+                    let value = ptr::addr_of_mut!((*cur).value).cast::<V>();
+                    let w_ref = MappedWatchGuardMut::from_ptr(value, bucket.ref_locked.clone());
 
                     bucket.release();
                     self.inner().lock.unlock_group();
@@ -213,6 +313,60 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
         None
     }
 
+    /// Runs `f` on the value of `key` under its group lock, released as soon as `f`
+    /// returns. Returns `None` without calling `f` if the key is missing.
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use castbox::collections::AtomicHashMap;
+    /// let map = AtomicHashMap::new();
+    /// map.insert("a", vec![1, 2]);
+    /// assert_eq!(map.with("a", |v| v.len()), Some(2));
+    /// assert_eq!(map.with("b", |v| v.len()), None);
+    /// # }
+    /// ```
+    pub fn with<Q, R, F>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> R,
+    {
+        self.get(key).map(|value| f(&value))
+    }
+
+    /// Runs `f` on the value of `key` under its exclusive lock, released as soon as `f`
+    /// returns. Returns `None` without calling `f` if the key is missing.
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use castbox::collections::AtomicHashMap;
+    /// let map = AtomicHashMap::new();
+    /// map.insert("a", 1);
+    /// map.with_mut("a", |v| *v += 1);
+    /// assert_eq!(map.get_cloned("a"), Some(2));
+    /// # }
+    /// ```
+    pub fn with_mut<Q, R, F>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&mut V) -> R,
+    {
+        self.get_mut(key).map(|mut value| f(&mut value))
+    }
+
+    /// Returns a clone of the value of `key`, taken under its group lock.
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.with(key, V::clone)
+    }
+
     pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -265,7 +419,7 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
-        let h = Self::hash(key);
+        let h = self.hash(key);
         let bucket_idx = h as usize % self.inner().buckets.len();
         let bucket = &self.inner().buckets[bucket_idx];
 
@@ -277,7 +431,7 @@ impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
     }
 }
 
-impl<K, V> Clone for AtomicHashMap<K, V> {
+impl<K, V, S> Clone for AtomicHashMap<K, V, S> {
     fn clone(&self) -> Self {
         let inner = unsafe { &*self.ptr };
         inner.ref_count.fetch_add(1, Ordering::Relaxed);
@@ -285,7 +439,7 @@ impl<K, V> Clone for AtomicHashMap<K, V> {
     }
 }
 
-impl<K, V> Drop for AtomicHashMap<K, V> {
+impl<K, V, S> Drop for AtomicHashMap<K, V, S> {
     fn drop(&mut self) {
         let inner = unsafe { &*self.ptr };
         if inner.ref_count.fetch_sub(1, Ordering::Release) == 1 {
@@ -302,12 +456,12 @@ impl<K, V> Drop for AtomicHashMap<K, V> {
                 }
             }
 
-            unsafe { drop(Box::from_raw(self.ptr as *mut AtomicInner<K, V>)) };
+            unsafe { drop(Box::from_raw(self.ptr as *mut AtomicInner<K, V, S>)) };
         }
     }
 }
 
-impl<K, V> fmt::Debug for AtomicHashMap<K, V> {
+impl<K, V, S> fmt::Debug for AtomicHashMap<K, V, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let inner = unsafe { &*self.ptr };
         f.debug_struct("AtomicHashMap")
@@ -317,14 +471,17 @@ impl<K, V> fmt::Debug for AtomicHashMap<K, V> {
     }
 }
 
-pub struct Iter<'a, K, V> {
-    map: &'a AtomicHashMap<K, V>,
+/// An entry of the map, with the bucket holding it.
+type Entry<'a, K, V> = (&'a Bucket<K, V>, &'a Item<K, V>);
+
+pub struct Iter<'a, K, V, S> {
+    map: &'a AtomicHashMap<K, V, S>,
     bucket_idx: usize,
     current: *mut Item<K, V>,
 }
 
-impl<'a, K: Eq + Hash, V> Iter<'a, K, V> {
-    fn new(map: &'a AtomicHashMap<K, V>) -> Self {
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Iter<'a, K, V, S> {
+    fn new(map: &'a AtomicHashMap<K, V, S>) -> Self {
         let mut it = Iter {
             map,
             bucket_idx: 0,
@@ -344,13 +501,11 @@ impl<'a, K: Eq + Hash, V> Iter<'a, K, V> {
             }
         }
     }
-}
-
-impl<'a, K: Eq + Hash, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.bucket_idx >= self.map.inner().buckets.len() {
+    /// Moves to the next entry, returning it with its bucket.
+    fn next_entry(&mut self) -> Option<Entry<'a, K, V>> {
+        let map: &'a AtomicHashMap<K, V, S> = self.map;
+        if self.bucket_idx >= map.inner().buckets.len() {
             return None;
         }
 
@@ -359,13 +514,7 @@ impl<'a, K: Eq + Hash, V> Iterator for Iter<'a, K, V> {
             return None;
         }
 
-        let bucket = &self.map.inner().buckets[self.bucket_idx];
-        let backoff = Backoff::new();
-
-        while bucket.ref_locked.is_locked_exclusive() {
-            backoff.snooze();
-        }
-
+        let bucket = &map.inner().buckets[self.bucket_idx];
         let item = unsafe { &*cur_ptr };
         // move to next
         self.current = item.next.load(Ordering::Acquire);
@@ -373,21 +522,109 @@ impl<'a, K: Eq + Hash, V> Iterator for Iter<'a, K, V> {
             self.bucket_idx += 1;
             self.advance_bucket();
         }
+        Some((bucket, item))
+    }
+
+    /// Like `next`, but the value is returned under the group lock of its bucket, held
+    /// until the guard is dropped.
+    #[cfg(feature = "serde")]
+    fn next_locked(&mut self) -> Option<(&'a K, MappedWatchGuardRef<'a, V>)> {
+        let (bucket, item) = self.next_entry()?;
+        bucket.ref_locked.lock_group();
+        let value = ptr::addr_of!(item.value).cast::<V>();
+        // SAFETY: the map lock held by the iterator keeps the entry alive, and the group lock
+        // keeps the writers of the value out until the guard releases it
+        let value = unsafe { MappedWatchGuardRef::from_ptr(value, bucket.ref_locked.clone()) };
+        Some((&item.key, value))
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Iterator for Iter<'a, K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (bucket, item) = self.next_entry()?;
+        let backoff = Backoff::new();
+
+        while bucket.ref_locked.is_locked_exclusive() {
+            backoff.snooze();
+        }
+
         Some((&item.key, &*item.value))
     }
 }
 
-impl<K: Eq + Hash, V> AtomicHashMap<K, V> {
-    pub fn iter(&self) -> Iter<'_, K, V> {
+impl<K: Eq + Hash, V, S: BuildHasher> AtomicHashMap<K, V, S> {
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
         self.inner().lock.lock_exclusive();
         Iter::new(self)
     }
 }
 
-impl<'a, K, V> Drop for Iter<'a, K, V> {
+impl<'a, K, V, S> Drop for Iter<'a, K, V, S> {
     fn drop(&mut self) {
         unsafe {
             (&*self.map.ptr).lock.unlock_exclusive();
         }
     }
 }
+
+#[cfg(feature = "serde")]
+impl<K, V, S> Serialize for AtomicHashMap<K, V, S>
+where
+    K: Eq + Hash + Serialize,
+    V: Serialize,
+    S: BuildHasher,
+{
+    /// Serializes the entries as a map. Like [`AtomicHashMap::iter`], it holds the map
+    /// lock, so the entries are a snapshot, and each value is serialized under the group lock
+    /// of its entry, waiting for the guards of [`AtomicHashMap::get_mut`] on it.
+    ///
+    /// The map lock stays held while waiting: a long-lived `get_mut` guard stalls every
+    /// insert and remove on the whole map, not only on its entry, until it is dropped and
+    /// the serialization is done.
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let mut iter = self.iter();
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        while let Some((key, value)) = iter.next_locked() {
+            map.serialize_entry(key, &*value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V, S> Deserialize<'de> for AtomicHashMap<K, V, S>
+where
+    K: Eq + Hash + Deserialize<'de>,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    /// Inserts the entries of a map into a new one, later duplicates replacing earlier ones.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MapVisitor<K, V, S>(core::marker::PhantomData<(K, V, S)>);
+
+        impl<'de, K, V, S> Visitor<'de> for MapVisitor<K, V, S>
+        where
+            K: Eq + Hash + Deserialize<'de>,
+            V: Deserialize<'de>,
+            S: BuildHasher + Default,
+        {
+            type Value = AtomicHashMap<K, V, S>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let result = AtomicHashMap::with_hasher(S::default());
+                while let Some((key, value)) = map.next_entry()? {
+                    result.insert(key, value);
+                }
+                Ok(result)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(core::marker::PhantomData))
+    }
+}
@@ -1,10 +1,17 @@
 use crate::mutex::Backoff;
-use std::mem::ManuallyDrop;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::ptr::null_mut;
-use std::sync::atomic;
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
-use std::{fmt, ptr};
+use alloc::boxed::Box;
+use core::mem::ManuallyDrop;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::ptr::null_mut;
+use core::sync::atomic;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use core::{fmt, ptr};
+#[cfg(feature = "serde")]
+use serde::de::{SeqAccess, Visitor};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeSeq;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 const AVAILABLE: bool = true;
 const UPDATING: bool = false;
@@ -229,8 +236,67 @@ impl<T> Drop for AtomicVec<T> {
 impl<T> fmt::Debug for AtomicVec<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AtomicVec")
-            .field("type", &std::any::type_name::<T>())
+            .field("type", &core::any::type_name::<T>())
             .field("len", &self.len())
             .finish()
     }
 }
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for AtomicVec<T> {
+    /// Serializes the items as a sequence, from the oldest. Pushes and pops wait until it
+    /// is done, so the sequence is a snapshot.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// Releases the vec also when the serializer fails or panics.
+        struct Release<'a, T>(&'a AtomicVec<T>);
+
+        impl<T> Drop for Release<'_, T> {
+            fn drop(&mut self) {
+                self.0.release();
+            }
+        }
+
+        self.lock();
+        let _release = Release(self);
+
+        // `len` lags behind pops, count the linked items instead
+        let head = self.inner().head.load(Ordering::Acquire);
+        let items = || {
+            core::iter::successors(unsafe { head.as_ref() }, |item| unsafe {
+                item.next.load(Ordering::Acquire).as_ref()
+            })
+        };
+
+        let mut seq = serializer.serialize_seq(Some(items().count()))?;
+        for item in items() {
+            seq.serialize_element(&*item.value)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for AtomicVec<T> {
+    /// Pushes the items of a sequence, in order, into a new vec.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VecVisitor<T>(core::marker::PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for VecVisitor<T> {
+            type Value = AtomicVec<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a sequence")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let vec = AtomicVec::new();
+                while let Some(value) = seq.next_element()? {
+                    vec.push(value);
+                }
+                Ok(vec)
+            }
+        }
+
+        deserializer.deserialize_seq(VecVisitor(core::marker::PhantomData))
+    }
+}
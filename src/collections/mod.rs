@@ -2,4 +2,6 @@ mod atomic_vec;
 mod atomic_hashmap;
 
 pub use atomic_vec::AtomicVec;
-pub use atomic_hashmap::AtomicHashMap;
\ No newline at end of file
+pub use atomic_hashmap::AtomicHashMap;
+#[cfg(feature = "std")]
+pub use atomic_hashmap::DefaultHashBuilder;
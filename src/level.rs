@@ -0,0 +1,184 @@
+//! Compile-time lock hierarchy.
+//!
+//! A leveled lock, like `Arw<T, L>` or `Mutex<L>`, can only be acquired by handing over the
+//! [`LockToken`] of a lower level, which stays borrowed while the token of level `L` handed
+//! back, or the guard holding it, is alive. Taking locks out of order fails to compile, so a
+//! whole class of deadlocks is ruled out. Levels are plain marker types, the tokens are zero
+//! sized.
+//!
+//! Each thread holds at most one [`Root`] token at a time: [`LockToken::root`] panics while
+//! another one is alive on the same thread, so the hierarchy can't be restarted from a
+//! second root with locks still held. Without the `std` feature there is no thread local
+//! storage and a single root may be alive in the whole program.
+//!
+//! # Example
+//! ```
+//! use castbox::Arw;
+//! use castbox::level::{Level, LockToken};
+//!
+//! enum Config {}
+//! impl Level for Config {
+//!     const LEVEL: u32 = 10;
+//! }
+//!
+//! enum Stats {}
+//! impl Level for Stats {
+//!     const LEVEL: u32 = 20;
+//! }
+//!
+//! let config = Arw::<_, Config>::new_leveled(String::from("fast"));
+//! let stats = Arw::<_, Stats>::new_leveled(0u64);
+//!
+//! let mut root = LockToken::root();
+//! let (cfg, mut token) = config.as_ref_leveled(&mut root);
+//! let (mut hits, _) = stats.as_mut_leveled(&mut token);
+//! *hits += cfg.len() as u64;
+//! ```
+//!
+//! Taking `config` while holding `stats` doesn't compile:
+//! ```compile_fail
+//! use castbox::Arw;
+//! use castbox::level::{Level, LockToken};
+//!
+//! enum Config {}
+//! impl Level for Config {
+//!     const LEVEL: u32 = 10;
+//! }
+//!
+//! enum Stats {}
+//! impl Level for Stats {
+//!     const LEVEL: u32 = 20;
+//! }
+//!
+//! let config = Arw::<_, Config>::new_leveled(String::from("fast"));
+//! let stats = Arw::<_, Stats>::new_leveled(0u64);
+//!
+//! let mut root = LockToken::root();
+//! let (_hits, mut token) = stats.as_mut_leveled(&mut root);
+//! let (_cfg, _) = config.as_ref_leveled(&mut token);
+//! ```
+
+use core::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::cell::Cell;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// whether a root token is alive on this thread
+    static ROOT_TAKEN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// whether a root token is alive, there are no threads to tell apart without `std`
+#[cfg(not(feature = "std"))]
+static ROOT_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Marks the root token as taken, returns `false` if it already was.
+#[cfg(feature = "std")]
+fn take_root() -> bool {
+    !ROOT_TAKEN.with(|taken| taken.replace(true))
+}
+
+#[cfg(not(feature = "std"))]
+fn take_root() -> bool {
+    !ROOT_TAKEN.swap(true, Ordering::Acquire)
+}
+
+#[cfg(feature = "std")]
+fn release_root() {
+    // the thread local may already be destroyed when a token is dropped by another one
+    let _ = ROOT_TAKEN.try_with(|taken| taken.set(false));
+}
+
+#[cfg(not(feature = "std"))]
+fn release_root() {
+    ROOT_TAKEN.store(false, Ordering::Release);
+}
+
+/// A position in the lock hierarchy, locks are taken in increasing `LEVEL` order.
+pub trait Level {
+    const LEVEL: u32;
+}
+
+/// The bottom of the hierarchy, the level of the token every thread starts from.
+pub enum Root {}
+
+impl Level for Root {
+    const LEVEL: u32 = 0;
+}
+
+/// The default level of `Arw` and `Mutex`: these locks are out of the hierarchy and are
+/// acquired without tokens.
+pub enum Unleveled {}
+
+/// Proof that the current thread may take locks above level `L`.
+///
+/// A token is neither `Send` nor `Clone`: the token of a lower level is mutably borrowed by
+/// the one of the lock taken with it, until the latter is dropped.
+pub struct LockToken<'a, L: Level> {
+    _lower: PhantomData<&'a mut ()>,
+    _level: PhantomData<fn() -> L>,
+    /// not `Send`: the hierarchy is tracked per thread
+    _thread: PhantomData<*const ()>,
+}
+
+impl LockToken<'static, Root> {
+    /// Creates the root token, before taking any leveled lock.
+    ///
+    /// # Panics
+    /// If a root token is already alive on this thread: it has to be dropped first, which
+    /// needs all the locks taken from it to be released. A leaked root is never given back.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::level::LockToken;
+    ///
+    /// let root = LockToken::root();
+    /// assert!(std::panic::catch_unwind(LockToken::root).is_err());
+    ///
+    /// drop(root);
+    /// let _root = LockToken::root();
+    /// ```
+    #[inline]
+    pub fn root() -> Self {
+        assert!(
+            take_root(),
+            "a root LockToken is already alive on this thread"
+        );
+        Self {
+            _lower: PhantomData,
+            _level: PhantomData,
+            _thread: PhantomData,
+        }
+    }
+}
+
+impl<L: Level> LockToken<'_, L> {
+    /// Borrows this token to get one for the higher level `H`, failing to compile if `H` is
+    /// not above `L`.
+    #[inline(always)]
+    pub(crate) fn advance<H: Level>(&mut self) -> LockToken<'_, H> {
+        const {
+            assert!(
+                L::LEVEL < H::LEVEL,
+                "locks must be acquired in increasing level order"
+            )
+        };
+        LockToken {
+            _lower: PhantomData,
+            _level: PhantomData,
+            _thread: PhantomData,
+        }
+    }
+}
+
+impl<L: Level> Drop for LockToken<'_, L> {
+    #[inline]
+    fn drop(&mut self) {
+        // only `root` makes tokens of level 0, `advance` always goes above it
+        if L::LEVEL == Root::LEVEL {
+            release_root();
+        }
+    }
+}
@@ -1,4 +1,6 @@
-#![doc = include_str!("../README.md")]
+// the README examples spawn threads and use the default hasher
+#![cfg_attr(feature = "std", doc = include_str!("../README.md"))]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(dead_code)]
 #![doc(test(
     no_crate_inject,
@@ -14,7 +16,10 @@
     unreachable_pub,
 )]
 
+extern crate alloc;
+
 mod any_ref;
+pub mod level;
 pub mod mutex;
 pub mod utils;
 
@@ -23,7 +28,14 @@ pub mod collections;
 #[cfg(test)]
 mod test;
 mod arw;
+mod seq_lock;
+// the impls only need `alloc`, the sharing tracked by the module is per thread
+#[cfg(all(feature = "serde", feature = "std"))]
+pub mod serde;
+#[cfg(feature = "capi")]
+pub mod capi;
 
 pub use any_ref::{AnyRef, WeakAnyRef};
-pub use arw::{Arw, WeakArw};
+pub use arw::{Arw, ArwWatcher, ByAddress, OptimisticRead, WeakArw};
+pub use seq_lock::SeqLock;
 
@@ -1,6 +1,8 @@
 use core::cell::Cell;
 use core::fmt;
-use std::{hint, thread};
+use core::hint;
+#[cfg(feature = "std")]
+use std::thread;
 
 const SPIN_LIMIT: u32 = 6;
 const YIELD_LIMIT: u32 = 10;
@@ -62,7 +64,13 @@ impl Backoff {
                 hint::spin_loop();
             }
         } else {
+            #[cfg(feature = "std")]
             thread::yield_now();
+
+            #[cfg(not(feature = "std"))]
+            for _ in 0..1 << SPIN_LIMIT {
+                hint::spin_loop();
+            }
         }
 
         if self.step.get() <= YIELD_LIMIT {
@@ -71,9 +79,12 @@ impl Backoff {
     }
 
     /// Returns `true` if exponential backoff has completed and blocking the thread is advised.
+    ///
+    /// Without `std` there is no way to block the thread, so the backoff never completes and
+    /// callers keep spinning.
     #[inline]
     pub(crate) fn is_completed(&self) -> bool {
-        self.step.get() > YIELD_LIMIT
+        cfg!(feature = "std") && self.step.get() > YIELD_LIMIT
     }
 
     /// Returns `true` if exponential backoff has completed the spinning threshold.
@@ -0,0 +1,227 @@
+use crate::mutex::Backoff;
+use crate::mutex::parking::{self, DEFAULT_UNPARK_TOKEN, ParkResult};
+use alloc::boxed::Box;
+use core::fmt;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::sync::atomic;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicU8, AtomicUsize};
+use core::time::Duration;
+use std::time::Instant;
+
+type State = u8;
+
+/// no signal pending
+const UNSET: State = 0;
+/// signaled
+const SET: State = 1;
+
+/// How an [`Event`] behaves once a waiter has been released.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EventMode {
+    /// The event stays set, releasing every waiter, until [`Event::reset`] is called.
+    ManualReset,
+    /// The event releases a single waiter and then automatically goes back to unset.
+    AutoReset,
+}
+
+struct InnerEvent {
+    state: AtomicU8,
+    mode: EventMode,
+    ref_count: AtomicUsize,
+}
+
+/// A reference counted signal that threads can wait on.
+///
+/// In [`EventMode::ManualReset`] mode a `set` releases all the current and future waiters until
+/// the event is `reset`; in [`EventMode::AutoReset`] mode every `set` releases exactly one waiter.
+///
+/// # Example
+/// ```
+/// use castbox::mutex::Event;
+/// use std::thread;
+///
+/// let loaded = Event::manual();
+/// let l = loaded.clone();
+/// let h = thread::spawn(move || l.wait());
+///
+/// loaded.set();
+/// h.join().unwrap();
+/// assert!(loaded.is_set());
+/// ```
+#[repr(transparent)]
+pub struct Event {
+    ptr: *const InnerEvent,
+}
+
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
+impl UnwindSafe for Event {}
+impl RefUnwindSafe for Event {}
+
+impl Event {
+    pub fn new(mode: EventMode) -> Self {
+        let ptr = Box::into_raw(Box::new(InnerEvent {
+            state: AtomicU8::new(UNSET),
+            mode,
+            ref_count: AtomicUsize::new(1),
+        }));
+        if ptr.is_null() {
+            panic!("Happened an invalid allocation for Event");
+        }
+        Self { ptr }
+    }
+
+    /// Creates a new unset manual-reset `Event`.
+    #[inline]
+    pub fn manual() -> Self {
+        Self::new(EventMode::ManualReset)
+    }
+
+    /// Creates a new unset auto-reset `Event`.
+    #[inline]
+    pub fn auto() -> Self {
+        Self::new(EventMode::AutoReset)
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &InnerEvent {
+        unsafe { &*self.ptr }
+    }
+
+    /// Key of the parking lot queue of the waiters.
+    #[inline]
+    fn park_key(&self) -> usize {
+        self.ptr.addr()
+    }
+
+    #[inline]
+    pub fn mode(&self) -> EventMode {
+        self.inner().mode
+    }
+
+    #[inline]
+    pub fn is_set(&self) -> bool {
+        self.inner().state.load(Acquire) == SET
+    }
+
+    pub fn get_ref_count(&self) -> usize {
+        self.inner().ref_count.load(Acquire)
+    }
+
+    /// Signals the event, waking up the parked waiters.
+    pub fn set(&self) {
+        if self.inner().state.swap(SET, Release) == SET {
+            return;
+        }
+
+        match self.inner().mode {
+            EventMode::ManualReset => {
+                parking::unpark_all(self.park_key(), DEFAULT_UNPARK_TOKEN);
+            }
+            EventMode::AutoReset => {
+                parking::unpark_one(self.park_key(), |_| DEFAULT_UNPARK_TOKEN);
+            }
+        }
+    }
+
+    /// Puts the event back in the unset state.
+    #[inline]
+    pub fn reset(&self) {
+        self.inner().state.store(UNSET, Release);
+    }
+
+    /// Blocks the current thread until the event is set.
+    pub fn wait(&self) {
+        self.wait_deadline(None);
+    }
+
+    /// Blocks the current thread until the event is set or `timeout` expires.
+    /// Returns `false` if the wait timed out.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Event;
+    /// use std::time::Duration;
+    ///
+    /// let e = Event::auto();
+    /// assert!(!e.wait_timeout(Duration::from_millis(10)));
+    /// e.set();
+    /// assert!(e.wait_timeout(Duration::from_millis(10)));
+    /// assert!(!e.is_set());
+    /// ```
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_deadline(Some(Instant::now() + timeout))
+    }
+
+    #[inline]
+    fn try_acquire(&self) -> bool {
+        let inner = self.inner();
+        match inner.mode {
+            EventMode::ManualReset => inner.state.load(Acquire) == SET,
+            EventMode::AutoReset => inner
+                .state
+                .compare_exchange(SET, UNSET, Acquire, Relaxed)
+                .is_ok(),
+        }
+    }
+
+    fn wait_deadline(&self, deadline: Option<Instant>) -> bool {
+        let backoff = Backoff::new();
+
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+
+            // the state is checked again with the queue locked, so a `set` can't be missed
+            let res = parking::park(
+                self.park_key(),
+                || self.inner().state.load(Relaxed) != SET,
+                |_| {},
+                deadline,
+            );
+
+            if res == ParkResult::TimedOut {
+                // a last chance, the event may have been set right after the deadline
+                return self.try_acquire();
+            }
+        }
+    }
+}
+
+impl Clone for Event {
+    fn clone(&self) -> Self {
+        self.inner().ref_count.fetch_add(1, Relaxed);
+        Event { ptr: self.ptr }
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        if self.inner().ref_count.fetch_sub(1, Release) == 1 {
+            atomic::fence(Acquire);
+
+            let ptr = self.ptr as *mut InnerEvent;
+            unsafe { drop(Box::from_raw(ptr)) };
+        }
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner();
+        f.debug_struct("Event")
+            .field("mode", &inner.mode)
+            .field("set", &(inner.state.load(Relaxed) == SET))
+            .field("waiters", &parking::waiters(self.park_key()))
+            .field("ref", &inner.ref_count.load(Relaxed))
+            .finish()
+    }
+}
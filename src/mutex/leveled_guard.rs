@@ -0,0 +1,81 @@
+use crate::level::{Level, LockToken};
+use crate::mutex::{Mutex, MutexType};
+use core::fmt::{Debug, Formatter};
+
+/// A leveled [`Mutex`] held in `mode`, together with the [`LockToken`] of its level.
+///
+/// The token of the lower level stays borrowed while the guard is alive, and the lock is
+/// released when the guard is dropped. Locks of higher levels are taken with
+/// [`LeveledGuard::token`].
+///
+/// # Example
+/// ```
+/// use castbox::level::{Level, LockToken};
+/// use castbox::mutex::Mutex;
+///
+/// enum Io {}
+/// impl Level for Io {
+///     const LEVEL: u32 = 1;
+/// }
+///
+/// enum Log {}
+/// impl Level for Log {
+///     const LEVEL: u32 = 2;
+/// }
+///
+/// let io = Mutex::<Io>::new_leveled();
+/// let log = Mutex::<Log>::new_leveled();
+///
+/// let mut root = LockToken::root();
+/// let mut io_guard = io.lock_exclusive_leveled(&mut root);
+/// let log_guard = log.lock_group_leveled(io_guard.token());
+/// assert!(io.is_locked_exclusive() && log.is_locked_group());
+///
+/// drop(log_guard);
+/// drop(io_guard);
+/// assert!(!io.is_locked() && !log.is_locked());
+/// ```
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct LeveledGuard<'a, L: Level> {
+    lock: &'a Mutex<L>,
+    mode: MutexType,
+    token: LockToken<'a, L>,
+}
+
+impl<'a, L: Level> LeveledGuard<'a, L> {
+    /// Adopts `lock`, already held by the caller in `mode`.
+    pub(crate) fn new(lock: &'a Mutex<L>, mode: MutexType, token: LockToken<'a, L>) -> Self {
+        Self { lock, mode, token }
+    }
+
+    /// How the guard holds its lock.
+    #[inline]
+    pub fn mode(&self) -> MutexType {
+        self.mode
+    }
+
+    /// The token of this level, to take the locks of the higher ones.
+    #[inline]
+    pub fn token(&mut self) -> &mut LockToken<'a, L> {
+        &mut self.token
+    }
+}
+
+impl<L: Level> Drop for LeveledGuard<'_, L> {
+    #[inline]
+    fn drop(&mut self) {
+        match self.mode {
+            MutexType::Exclusive => self.lock.unlock_exclusive(),
+            MutexType::Group => self.lock.unlock_group(),
+        }
+    }
+}
+
+impl<L: Level> Debug for LeveledGuard<'_, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LeveledGuard")
+            .field("mode", &self.mode)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
@@ -0,0 +1,24 @@
+use core::fmt;
+
+/// Why a non-blocking or timed access didn't return a guard.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LockError {
+    /// The lock is held in a conflicting mode, taking it would block.
+    WouldBlock,
+    /// The lock was still held in a conflicting mode when the timeout expired.
+    TimedOut,
+    /// The value is not of the requested type.
+    TypeMismatch,
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockError::WouldBlock => "the lock is held, taking it would block",
+            LockError::TimedOut => "timed out waiting for the lock",
+            LockError::TypeMismatch => "the value is not of the requested type",
+        })
+    }
+}
+
+impl core::error::Error for LockError {}
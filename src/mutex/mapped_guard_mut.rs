@@ -0,0 +1,176 @@
+use crate::mutex::{Mutex, Version};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+/// An exclusive lock guard for data that may move once the lock is released, like a
+/// component made by [`WatchGuardMut::map`](crate::mutex::WatchGuardMut::map).
+///
+/// Unlike [`WatchGuardMut`](crate::mutex::WatchGuardMut) it holds the lock until dropped:
+/// it has no `unlocked` nor `bump`, after which the data could have been reallocated or
+/// freed.
+///
+/// # Example
+/// ```
+/// use castbox::Arw;
+/// use castbox::mutex::{MappedWatchGuardMut, WatchGuardMut};
+///
+/// let a = Arw::new((String::from("limits"), vec![10u32, 20]));
+/// {
+///     let limits = WatchGuardMut::map(a.as_mut(), |c| &mut c.1);
+///     let mut second = MappedWatchGuardMut::map(limits, |l| &mut l[1]);
+///     *second += 5;
+/// }
+/// assert_eq!(a.as_ref().1, vec![10, 25]);
+/// ```
+///
+/// The lock can't be handed over while the guard is alive:
+/// ```compile_fail
+/// use castbox::Arw;
+/// use castbox::mutex::WatchGuardMut;
+///
+/// let a = Arw::new(vec![1, 2, 3]);
+/// let mut first = WatchGuardMut::map(a.as_mut(), |v| &mut v[0]);
+/// first.bump();
+/// ```
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MappedWatchGuardMut<'a, T: ?Sized> {
+    data: NonNull<T>,
+    lock: Mutex,
+    /// tracks the writes to the data, if set
+    version: Option<&'a Version>,
+    /// reports the guard as a change to the watchers of `version` once released
+    changed: bool,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'mutex, T: ?Sized> MappedWatchGuardMut<'mutex, T> {
+    /// Creates a guard for the data at `ptr`, guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held exclusively and `ptr` must stay valid for reads and writes for
+    /// `'mutex` while it is held.
+    pub(crate) unsafe fn from_ptr(ptr: *mut T, lock: Mutex) -> MappedWatchGuardMut<'mutex, T> {
+        // SAFETY: as for this function
+        unsafe { Self::from_parts(ptr, lock, None, false) }
+    }
+
+    /// Like [`MappedWatchGuardMut::from_ptr`], for a write already recorded in `version`:
+    /// it ends when the guard is released, reported as a change if `changed` is set.
+    ///
+    /// # Safety
+    /// As for [`MappedWatchGuardMut::from_ptr`].
+    pub(crate) unsafe fn from_parts(
+        ptr: *mut T,
+        lock: Mutex,
+        version: Option<&'mutex Version>,
+        changed: bool,
+    ) -> MappedWatchGuardMut<'mutex, T> {
+        Self {
+            // SAFETY: the caller hands a valid pointer
+            data: unsafe { NonNull::new_unchecked(ptr) },
+            lock,
+            version,
+            changed,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked_exclusive()
+    }
+
+    /// Makes a guard for a component of the locked data, keeping the same lock held.
+    ///
+    /// This is an associated function, to not shadow a `map` method of `T`.
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedWatchGuardMut<'mutex, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // if `f` panics `this` is still around to release the lock
+        let mut this = this;
+        let data: *mut U = f(&mut this);
+        let (version, changed) = (this.version, this.changed);
+        // SAFETY: the component lives as long as the lock is held, which moves to the new guard
+        unsafe { MappedWatchGuardMut::from_parts(data, Self::take_lock(this), version, changed) }
+    }
+
+    /// Like [`MappedWatchGuardMut::map`], but gives back the original guard if `f` returns
+    /// `None`.
+    pub fn try_map<U: ?Sized, F>(this: Self, f: F) -> Result<MappedWatchGuardMut<'mutex, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let mut this = this;
+        let data: *mut U = match f(&mut this) {
+            Some(data) => data,
+            None => return Err(this),
+        };
+        let (version, changed) = (this.version, this.changed);
+        // SAFETY: as for `map`
+        Ok(unsafe {
+            MappedWatchGuardMut::from_parts(data, Self::take_lock(this), version, changed)
+        })
+    }
+
+    /// Moves the lock out of the guard without releasing it.
+    #[inline]
+    fn take_lock(this: Self) -> Mutex {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: `this` is never dropped, so the lock is moved out exactly once
+        unsafe { ptr::read(&this.lock) }
+    }
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedWatchGuardMut<'_, T> {}
+unsafe impl<T: ?Sized + Send> Send for MappedWatchGuardMut<'_, T> {}
+
+impl<T: ?Sized> Deref for MappedWatchGuardMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the exclusive lock is held for the whole life of the guard
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MappedWatchGuardMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the exclusive lock is held for the whole life of the guard
+        unsafe { self.data.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedWatchGuardMut<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(version) = self.version {
+            version.end_write();
+        }
+        self.lock.unlock_exclusive();
+        match self.version {
+            Some(version) if self.changed => version.bump(),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, T, U> PartialEq<U> for MappedWatchGuardMut<'a, T>
+where
+    T: PartialEq<U> + ?Sized,
+{
+    fn eq(&self, other: &U) -> bool {
+        **self == *other
+    }
+}
+
+impl<'a, T: Debug + ?Sized> Debug for MappedWatchGuardMut<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MappedWatchGuardMut")
+            .field("data", &&**self)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
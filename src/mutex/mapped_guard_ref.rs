@@ -0,0 +1,130 @@
+use crate::mutex::Mutex;
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
+
+/// A group lock guard for data that may move once the lock is released, like a component
+/// made by [`WatchGuardRef::map`](crate::mutex::WatchGuardRef::map).
+///
+/// Unlike [`WatchGuardRef`](crate::mutex::WatchGuardRef) it holds the lock until dropped:
+/// it has no `unlocked`, after which the data could have been reallocated or freed.
+///
+/// # Example
+/// ```
+/// use castbox::Arw;
+/// use castbox::mutex::{MappedWatchGuardRef, WatchGuardRef};
+///
+/// let a = Arw::new((String::from("limits"), vec![10u32, 20]));
+/// let limits = WatchGuardRef::map(a.as_ref(), |c| &c.1);
+/// let second = MappedWatchGuardRef::map(limits, |l| &l[1]);
+/// assert_eq!(*second, 20);
+/// ```
+///
+/// The lock can't be released while the guard is alive:
+/// ```compile_fail
+/// use castbox::Arw;
+/// use castbox::mutex::WatchGuardRef;
+///
+/// let a = Arw::new(vec![1, 2, 3]);
+/// let mut first = WatchGuardRef::map(a.as_ref(), |v| &v[0]);
+/// first.unlocked(|| a.clone());
+/// ```
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MappedWatchGuardRef<'a, T: ?Sized> {
+    data: NonNull<T>,
+    lock: Mutex,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'mutex, T: ?Sized> MappedWatchGuardRef<'mutex, T> {
+    /// Creates a guard for the data at `ptr`, guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held in group mode and `ptr` must stay valid for reads for `'mutex`
+    /// while it is held.
+    pub(crate) unsafe fn from_ptr(ptr: *const T, lock: Mutex) -> MappedWatchGuardRef<'mutex, T> {
+        Self {
+            // SAFETY: the caller hands a valid pointer
+            data: unsafe { NonNull::new_unchecked(ptr as *mut T) },
+            lock,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked_exclusive()
+    }
+
+    /// Makes a guard for a component of the locked data, keeping the same lock held.
+    ///
+    /// This is an associated function, to not shadow a `map` method of `T`.
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedWatchGuardRef<'mutex, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        // if `f` panics `this` is still around to release the lock
+        let data: *const U = f(&this);
+        // SAFETY: the component lives as long as the lock is held, which moves to the new guard
+        unsafe { MappedWatchGuardRef::from_ptr(data, Self::take_lock(this)) }
+    }
+
+    /// Like [`MappedWatchGuardRef::map`], but gives back the original guard if `f` returns
+    /// `None`.
+    pub fn try_map<U: ?Sized, F>(this: Self, f: F) -> Result<MappedWatchGuardRef<'mutex, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(&this).map(|data| data as *const U) {
+            // SAFETY: as for `map`
+            Some(data) => Ok(unsafe { MappedWatchGuardRef::from_ptr(data, Self::take_lock(this)) }),
+            None => Err(this),
+        }
+    }
+
+    /// Moves the lock out of the guard without releasing it.
+    #[inline]
+    fn take_lock(this: Self) -> Mutex {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: `this` is never dropped, so the lock is moved out exactly once
+        unsafe { ptr::read(&this.lock) }
+    }
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedWatchGuardRef<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Send for MappedWatchGuardRef<'_, T> {}
+
+impl<T: ?Sized> Deref for MappedWatchGuardRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the group lock is held for the whole life of the guard
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedWatchGuardRef<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.unlock_group();
+    }
+}
+
+impl<'a, T, U> PartialEq<U> for MappedWatchGuardRef<'a, T>
+where
+    T: PartialEq<U> + ?Sized,
+{
+    fn eq(&self, other: &U) -> bool {
+        **self == *other
+    }
+}
+
+impl<'a, T: Debug + ?Sized> Debug for MappedWatchGuardRef<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MappedWatchGuardRef")
+            .field("data", &&**self)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
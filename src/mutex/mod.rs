@@ -1,11 +1,40 @@
 mod backoff;
+#[cfg(feature = "std")]
+mod event;
+mod leveled_guard;
+mod lock_error;
+mod mapped_guard_mut;
+mod mapped_guard_ref;
 mod mutex;
+mod owned_guard_mut;
+mod owned_guard_ref;
+#[cfg(feature = "std")]
+mod parking;
+#[cfg(feature = "std")]
+mod phaser;
+mod queue;
+mod version;
+#[cfg(feature = "std")]
+mod watchdog;
 mod watch_guard_mut;
 mod watch_guard_ref;
 mod watch_guard;
 
 pub(crate) use backoff::Backoff;
+pub(crate) use version::Version;
+#[cfg(feature = "std")]
+pub use event::*;
+pub use leveled_guard::LeveledGuard;
+pub use lock_error::*;
+pub use mapped_guard_mut::*;
+pub use mapped_guard_ref::*;
 pub use mutex::*;
+pub use owned_guard_mut::*;
+pub use owned_guard_ref::*;
+#[cfg(feature = "std")]
+pub use phaser::*;
+#[cfg(feature = "std")]
+pub use watchdog::{StallReport, Watchdog};
 pub use watch_guard_mut::*;
 pub use watch_guard_ref::*;
-pub use watch_guard::*;
\ No newline at end of file
+pub use watch_guard::*;
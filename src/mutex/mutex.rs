@@ -1,17 +1,65 @@
-use crate::collections::AtomicVec;
-use crate::mutex::Backoff;
-use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::sync::atomic;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::sync::atomic::{AtomicU8, AtomicUsize};
-use std::thread::Thread;
-use std::{fmt, hint, thread};
-
-enum MutexType {
+use crate::level::{Level, LockToken, Unleveled};
+use crate::mutex::{Backoff, LeveledGuard, Version};
+#[cfg(feature = "std")]
+use crate::mutex::parking::{self, DEFAULT_UNPARK_TOKEN, ParkResult, UnparkToken};
+use crate::mutex::queue::{self, QueueNode};
+#[cfg(feature = "std")]
+use crate::mutex::watchdog;
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::ptr::null_mut;
+use core::sync::atomic;
+#[cfg(feature = "std")]
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize};
+#[cfg(feature = "std")]
+use core::time::Duration;
+use core::{fmt, hint};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// The two ways a [`Mutex`] can be held.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MutexType {
     Exclusive,
     Group,
 }
 
+/// How the waiters of a [`Mutex`] compete for the lock.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MutexBackend {
+    /// Every waiter spins on the lock state with an exponential backoff, then parks.
+    #[default]
+    Standard,
+    /// Waiters line up in an MCS queue and spin on their own node, so only the head of the
+    /// queue touches the lock state. Fairer and cheaper on the caches with many cores
+    /// contending, at the price of a slower uncontended path.
+    ///
+    /// Group locks are handed out in arrival order, so a thread taking the group lock
+    /// again while an exclusive waiter is queued deadlocks.
+    Queued,
+}
+
+/// parked bits: which kind of waiters are in the parking lot for this mutex
+const PARKED_EXCLUSIVE: u8 = 1;
+const PARKED_GROUP: u8 = 2;
+
+/// flags bits: the queued backend, fixed at creation, and the adaptive spinning switch
+const FLAG_QUEUED: u8 = 1;
+const FLAG_ADAPTIVE: u8 = 2;
+
+/// unpark token telling a parked exclusive waiter that it has been handed the lock
+#[cfg(feature = "std")]
+const TOKEN_HANDOFF: UnparkToken = 1;
+
+/// reads of the lock word before `Mutex::snapshot` gives up waiting for a stable one
+const SNAPSHOT_ATTEMPTS: u32 = 16;
+
+/// upper bound of the adaptive spinning, in `spin_loop` hints
+const MAX_ADAPTIVE_SPINS: u32 = 200;
+
 /// Layout packing (AtomicUsize):
 /// lower 3 bit: state
 /// remaining bits: locked counter (shifted right by SHIFT_LOCKED)
@@ -30,13 +78,58 @@ const LOCKED_GROUP: State = 3;
 /// a dirty state
 const DIRTY: State = 4;
 
+/// What a [`Mutex`] is doing, see [`MutexState`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LockState {
+    Unlocked,
+    /// Held by a single thread.
+    Exclusive,
+    /// Held by one or more group members.
+    Group,
+    /// Released by the last group member, free for the next exclusive or group locker.
+    Dirty,
+}
+
+/// A snapshot of a [`Mutex`], taken by [`Mutex::snapshot`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MutexState {
+    pub state: LockState,
+    /// Group members holding the lock, or already registered to get it as soon as the
+    /// current holder releases it.
+    pub group_holders: usize,
+    /// Threads parked waiting for the exclusive lock.
+    pub parked_exclusive: usize,
+    /// Threads parked waiting for the group lock.
+    pub parked_group: usize,
+    pub ref_count: usize,
+}
+
+/// The waiters are kept in the global parking lot keyed by the address of the inner mutex,
+/// so a mutex is three words: the state word, holding the state and the parked, flags and
+/// spins bytes, the group counter and the reference count.
+///
+/// The group counter can't share the state word: group waiters count themselves in it
+/// before looking at the state, and the state is stored and swapped on its own while the
+/// counter moves, see `acquire_group` and `unlock_group`.
 struct InnerMutex {
     state: AtomicU8,
-    ref_count: AtomicUsize,
-    parking_e: AtomicVec<Thread>,
-    parking_g: AtomicVec<Thread>,
+    #[cfg(feature = "std")]
+    parked: AtomicU8,
+    /// `FLAG_*` bits
+    flags: AtomicU8,
+    /// moving estimate of the spins worth doing before parking, see `acquire_adaptive`
+    spins: AtomicU8,
     locked: AtomicUsize,
-    wake_deadlock: AtomicU8,
+    ref_count: AtomicUsize,
+}
+
+/// The allocation of a mutex of the queued backend, the only one needing a queue: the
+/// handles point to `inner`, at its start.
+#[repr(C)]
+struct QueuedInnerMutex {
+    inner: InnerMutex,
+    /// last waiter of the MCS queue
+    queue: AtomicPtr<QueueNode>,
 }
 
 /*
@@ -65,31 +158,200 @@ impl InnerMutex {
     }
 }*/
 
+/// A reference counted lock with exclusive and group modes.
+///
+/// `L` places the mutex in the compile-time lock hierarchy, see [`crate::level`]: leveled
+/// mutexes are only locked through the `*_leveled` methods.
 #[repr(transparent)]
-pub struct Mutex {
+pub struct Mutex<L = Unleveled> {
     ptr: *const InnerMutex,
+    level: PhantomData<fn() -> L>,
 }
 
-unsafe impl Send for Mutex {}
-unsafe impl Sync for Mutex {}
+unsafe impl<L> Send for Mutex<L> {}
+unsafe impl<L> Sync for Mutex<L> {}
 
-impl UnwindSafe for Mutex {}
-impl RefUnwindSafe for Mutex {}
+impl<L> UnwindSafe for Mutex<L> {}
+impl<L> RefUnwindSafe for Mutex<L> {}
 
 impl Mutex {
     pub fn new() -> Self {
-        let ptr = Box::into_raw(Box::new(InnerMutex {
+        Self::with_backend(MutexBackend::Standard)
+    }
+
+    /// Creates a new `Mutex` whose waiters are handled by `backend`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::{Mutex, MutexBackend};
+    ///
+    /// let m = Mutex::with_backend(MutexBackend::Queued);
+    /// m.lock_group();
+    /// assert!(m.is_locked_group());
+    /// m.unlock_group();
+    /// ```
+    pub fn with_backend(backend: MutexBackend) -> Self {
+        Self::alloc(backend)
+    }
+
+    pub fn lock_exclusive(&self) {
+        self.raw_lock_exclusive();
+    }
+
+    pub fn lock_group(&self) {
+        self.raw_lock_group();
+    }
+
+    pub fn try_lock_exclusive(&self) -> bool {
+        self.raw_try_lock_exclusive()
+    }
+
+    /// Joins the group lock only if it can be done without waiting.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Mutex;
+    ///
+    /// let m = Mutex::new();
+    /// assert!(m.try_lock_group());
+    /// assert!(m.try_lock_group());
+    /// assert!(!m.try_lock_exclusive());
+    /// m.unlock_group();
+    /// m.unlock_group();
+    /// ```
+    pub fn try_lock_group(&self) -> bool {
+        self.raw_try_lock_group()
+    }
+
+    /// Locks exclusively, giving up after `timeout`, returns whether the lock was taken.
+    ///
+    /// The waiter doesn't line up in the queue of the [`MutexBackend::Queued`] backend.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Mutex;
+    /// use std::time::Duration;
+    ///
+    /// let m = Mutex::new();
+    /// m.lock_group();
+    /// assert!(!m.lock_exclusive_timeout(Duration::from_millis(10)));
+    /// m.unlock_group();
+    /// assert!(m.lock_exclusive_timeout(Duration::from_millis(10)));
+    /// m.unlock_exclusive();
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn lock_exclusive_timeout(&self, timeout: Duration) -> bool {
+        self.raw_lock_exclusive_timeout(timeout)
+    }
+
+    /// Joins the group lock, giving up after `timeout`, returns whether the lock was taken.
+    ///
+    /// The waiter doesn't line up in the queue of the [`MutexBackend::Queued`] backend.
+    #[cfg(feature = "std")]
+    pub fn lock_group_timeout(&self, timeout: Duration) -> bool {
+        self.raw_lock_group_timeout(timeout)
+    }
+}
+
+impl<L: Level> Mutex<L> {
+    /// Creates a new `Mutex` at level `L` of the lock hierarchy.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::level::{Level, LockToken};
+    /// use castbox::mutex::Mutex;
+    ///
+    /// enum Io {}
+    /// impl Level for Io {
+    ///     const LEVEL: u32 = 1;
+    /// }
+    ///
+    /// let m = Mutex::<Io>::new_leveled();
+    /// let mut root = LockToken::root();
+    /// let guard = m.lock_exclusive_leveled(&mut root);
+    /// assert!(m.is_locked_exclusive());
+    /// drop(guard);
+    /// assert!(!m.is_locked());
+    /// ```
+    pub fn new_leveled() -> Self {
+        Self::alloc(MutexBackend::Standard)
+    }
+
+    /// Locks exclusively with the token of a lower level, returning a guard that holds the
+    /// token of this level and unlocks when dropped.
+    pub fn lock_exclusive_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> LeveledGuard<'a, L> {
+        let token = token.advance();
+        self.raw_lock_exclusive();
+        LeveledGuard::new(self, MutexType::Exclusive, token)
+    }
+
+    /// Locks as a group member with the token of a lower level, returning a guard that
+    /// holds the token of this level and unlocks when dropped.
+    pub fn lock_group_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> LeveledGuard<'a, L> {
+        let token = token.advance();
+        self.raw_lock_group();
+        LeveledGuard::new(self, MutexType::Group, token)
+    }
+
+    /// Locks exclusively with the token of a lower level if the lock is free, see
+    /// [`Mutex::lock_exclusive_leveled`].
+    pub fn try_lock_exclusive_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> Option<LeveledGuard<'a, L>> {
+        let token = token.advance();
+        self.raw_try_lock_exclusive()
+            .then(|| LeveledGuard::new(self, MutexType::Exclusive, token))
+    }
+
+    /// Locks as a group member with the token of a lower level if no exclusive lock is
+    /// held, see [`Mutex::lock_group_leveled`].
+    pub fn try_lock_group_leveled<'a, P: Level>(
+        &'a self,
+        token: &'a mut LockToken<'_, P>,
+    ) -> Option<LeveledGuard<'a, L>> {
+        let token = token.advance();
+        self.raw_try_lock_group()
+            .then(|| LeveledGuard::new(self, MutexType::Group, token))
+    }
+}
+
+impl<L> Mutex<L> {
+    fn alloc(backend: MutexBackend) -> Self {
+        let flags = match backend {
+            MutexBackend::Standard => FLAG_ADAPTIVE,
+            MutexBackend::Queued => FLAG_ADAPTIVE | FLAG_QUEUED,
+        };
+        let inner = InnerMutex {
             state: AtomicU8::new(UNLOCKED),
-            ref_count: AtomicUsize::new(1),
-            parking_e: AtomicVec::new(),
-            parking_g: AtomicVec::new(),
+            #[cfg(feature = "std")]
+            parked: AtomicU8::new(0),
+            flags: AtomicU8::new(flags),
+            spins: AtomicU8::new(0),
             locked: AtomicUsize::new(0),
-            wake_deadlock: AtomicU8::new(UNLOCKED),
-        }));
+            ref_count: AtomicUsize::new(1),
+        };
+        let ptr = match backend {
+            MutexBackend::Standard => Box::into_raw(Box::new(inner)),
+            MutexBackend::Queued => Box::into_raw(Box::new(QueuedInnerMutex {
+                inner,
+                queue: AtomicPtr::new(null_mut()),
+            }))
+            .cast::<InnerMutex>(),
+        };
         if ptr.is_null() {
             panic!("Happened an invalid allocation for Mutex");
         }
-        Self { ptr }
+        Self {
+            ptr,
+            level: PhantomData,
+        }
     }
 
     pub fn get_ref_count(&self) -> usize {
@@ -101,92 +363,281 @@ impl Mutex {
         unsafe { &*self.ptr }
     }
 
-    pub fn lock_exclusive(&self) {
+    #[inline]
+    pub fn backend(&self) -> MutexBackend {
+        if self.inner().flags.load(Relaxed) & FLAG_QUEUED != 0 {
+            MutexBackend::Queued
+        } else {
+            MutexBackend::Standard
+        }
+    }
+
+    /// The tail of the MCS queue, only for the queued backend.
+    #[inline]
+    fn queue(&self) -> &AtomicPtr<QueueNode> {
+        debug_assert_eq!(self.backend(), MutexBackend::Queued);
+        // SAFETY: mutexes of the queued backend are allocated as `QueuedInnerMutex`, with the
+        // provenance of the whole allocation kept by `ptr`
+        unsafe { &(*self.ptr.cast::<QueuedInnerMutex>()).queue }
+    }
+
+    /// Identity of the lock, shared by all the clones and stable while one of them is alive.
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.ptr.addr()
+    }
+
+    /// The same mutex out of the lock hierarchy.
+    #[inline(always)]
+    fn as_unleveled(&self) -> &Mutex {
+        // SAFETY: the level is a zero sized marker, the layout is the same
+        unsafe { &*(self as *const Self as *const Mutex) }
+    }
+
+    fn raw_lock_exclusive(&self) {
+        match self.backend() {
+            MutexBackend::Standard => self.acquire_exclusive(),
+            MutexBackend::Queued => self.queued(|| self.acquire_exclusive()),
+        }
+        self.held(MutexType::Exclusive);
+    }
+
+    fn raw_lock_group(&self) {
+        match self.backend() {
+            MutexBackend::Standard => self.acquire_group(),
+            MutexBackend::Queued => self.queued(|| self.acquire_group()),
+        }
+        self.held(MutexType::Group);
+    }
+
+    /// Runs `acquire` once this thread is the head of the MCS queue, then lets the next
+    /// waiter in: group waiters behind a group holder get in one after the other.
+    #[inline]
+    fn queued<F: FnOnce()>(&self, acquire: F) {
+        let queue = self.queue();
+        let node = QueueNode::new();
+        queue::wait_head(queue, &node);
+        acquire();
+        queue::pass_head(queue, &node);
+    }
+
+    fn acquire_exclusive(&self) {
+        if self.is_adaptive() {
+            self.acquire_adaptive(MutexType::Exclusive);
+        } else {
+            self.acquire_backoff(MutexType::Exclusive);
+        }
+    }
+
+    fn acquire_group(&self) {
+        // we add it here so that as soon as the lock is available we can proceed to execute
+        // all the multi lock group.
+        // SAFETY: The unlock will fetch_sub only when the internal state is on LOCKED_GROUP state
+        self.inner().locked.fetch_add(1, Release);
+
+        if self.is_adaptive() {
+            self.acquire_adaptive(MutexType::Group);
+        } else {
+            self.acquire_backoff(MutexType::Group);
+        }
+    }
+
+    /// Spins a fixed amount, then follows the `Backoff` schedule before parking.
+    fn acquire_backoff(&self, t: MutexType) {
         let backoff = Backoff::new();
-        let inner = self.inner();
 
         loop {
             // Spin first to speed things up if the lock is released quickly.
-            match self.spin(10) {
-                DIRTY => {
-                    // if the state is DIRTY and there are no other group waiting is safe to switch to LOCKED
-                    if inner.locked.load(Acquire) == 0 {
-                        if inner
-                            .state
-                            .compare_exchange(DIRTY, LOCKED, Acquire, Relaxed)
-                            .is_ok()
-                        {
-                            break;
-                        }
-                    }
-                }
-                _ => {
-                    if inner
-                        .state
-                        .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
-                        .is_ok()
-                    {
-                        break;
-                    }
-                }
+            if self.try_take(t, self.spin(10)) {
+                break;
             }
 
             if backoff.is_completed() {
-                self.suspend(MutexType::Exclusive);
+                if self.suspend(t) {
+                    break;
+                }
             } else {
                 backoff.snooze();
             }
         }
     }
 
-    pub fn lock_group(&self) {
+    /// Spins for a budget derived from the previous acquisitions, then parks.
+    ///
+    /// Like glibc adaptive mutexes the estimate is a moving average of the spins needed:
+    /// a waiter that gets the lock while spinning feeds how long it spun, one that parks
+    /// feeds the whole budget if the lock was released meanwhile but taken by someone else
+    /// (short critical sections, contention) or nothing if it was held all the time (long
+    /// critical sections, where spinning is wasted).
+    fn acquire_adaptive(&self, t: MutexType) {
         let inner = self.inner();
-        let backoff = Backoff::new();
-
-        // we add it here so that as soon as the lock is available we can proceed to execute
-        // all the multi lock group.
-        // SAFETY: The unlock will fetch_sub only when the internal state is on LOCKED_GROUP state
-        inner.locked.fetch_add(1, Release);
+        let estimate = inner.spins.load(Relaxed) as u32;
+        let budget = MAX_ADAPTIVE_SPINS.min(estimate * 2 + 10);
 
+        let mut spins = 0;
+        let mut contended = false;
+        let mut parked = false;
         loop {
-            // Spin first to speed things up if the lock is released quickly.
-            match self.spin(10) {
-                DIRTY => {
-                    if inner
-                        .state
-                        .compare_exchange(DIRTY, LOCKED_GROUP, Acquire, Relaxed)
-                        .is_ok()
-                    {
-                        self.wake_all(MutexType::Group);
-                        break;
-                    }
-                }
-                LOCKED_GROUP => {
-                    // fix data race
-                    if inner.state.load(Acquire) == LOCKED_GROUP {
-                        // if some thread are parked let's wake them up
-                        self.wake(MutexType::Group);
-                    }
+            let state = inner.state.load(Relaxed);
+            if self.try_take(t, state) {
+                break;
+            }
+            contended |= self.can_acquire(t);
+
+            if spins < budget {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                parked = true;
+                if self.suspend(t) {
                     break;
                 }
-                _ => {
-                    if inner
+            }
+        }
+
+        let observed = match (parked, contended) {
+            (false, _) => spins,
+            (true, true) => budget,
+            (true, false) => 0,
+        };
+        let next = estimate as i32 + (observed as i32 - estimate as i32) / 8;
+        inner.spins.store(next as u8, Relaxed);
+    }
+
+    /// Makes a single attempt to take a lock of type `t` from `state`, group waiters must
+    /// already be counted in `locked`.
+    #[inline]
+    fn try_take(&self, t: MutexType, state: State) -> bool {
+        let inner = self.inner();
+        match (t, state) {
+            (MutexType::Exclusive, DIRTY) => {
+                // if the state is DIRTY and there are no other group waiting is safe to switch to LOCKED
+                inner.locked.load(Acquire) == 0
+                    && inner
                         .state
-                        .compare_exchange(UNLOCKED, LOCKED_GROUP, Acquire, Relaxed)
+                        .compare_exchange(DIRTY, LOCKED, Acquire, Relaxed)
                         .is_ok()
-                    {
-                        // try to wake some thread that maybe are parked but are members of this group
-                        self.wake_all(MutexType::Group);
-                        break;
-                    }
+            }
+            (MutexType::Exclusive, _) => inner
+                .state
+                .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+                .is_ok(),
+            (MutexType::Group, DIRTY) => {
+                if inner
+                    .state
+                    .compare_exchange(DIRTY, LOCKED_GROUP, Acquire, Relaxed)
+                    .is_ok()
+                {
+                    self.wake_all(MutexType::Group);
+                    return true;
+                }
+                false
+            }
+            (MutexType::Group, LOCKED_GROUP) => {
+                // fix data race
+                if inner.state.load(Acquire) == LOCKED_GROUP {
+                    // if some thread are parked let's wake them up
+                    self.wake(MutexType::Group);
+                }
+                true
+            }
+            (MutexType::Group, _) => {
+                if inner
+                    .state
+                    .compare_exchange(UNLOCKED, LOCKED_GROUP, Acquire, Relaxed)
+                    .is_ok()
+                {
+                    // try to wake some thread that maybe are parked but are members of this group
+                    self.wake_all(MutexType::Group);
+                    return true;
                 }
+                false
             }
+        }
+    }
 
-            if backoff.is_completed() {
-                self.suspend(MutexType::Group);
-            } else {
-                backoff.snooze();
+    /// Enables or disables adaptive spinning, enabled by default.
+    ///
+    /// When disabled a waiter always spins a fixed amount and then backs off, yielding
+    /// to the scheduler a few times before parking.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Mutex;
+    ///
+    /// let m = Mutex::new();
+    /// assert!(m.is_adaptive());
+    /// m.set_adaptive(false);
+    /// assert!(!m.is_adaptive());
+    /// ```
+    #[inline]
+    pub fn set_adaptive(&self, enabled: bool) {
+        if enabled {
+            self.inner().flags.fetch_or(FLAG_ADAPTIVE, Relaxed);
+        } else {
+            self.inner().flags.fetch_and(!FLAG_ADAPTIVE, Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn is_adaptive(&self) -> bool {
+        self.inner().flags.load(Relaxed) & FLAG_ADAPTIVE != 0
+    }
+
+    /// Current moving estimate of the spins worth doing before parking.
+    #[inline]
+    pub(crate) fn spin_estimate(&self) -> u8 {
+        self.inner().spins.load(Relaxed)
+    }
+
+    /// Takes a snapshot of the mutex, for monitoring and assertions.
+    ///
+    /// The lock word is read again until two consecutive reads agree, so the snapshot
+    /// describes a single moment unless the mutex keeps changing hands. The parked counts
+    /// come from the parking lot and don't include threads still spinning.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::{LockState, Mutex};
+    ///
+    /// let m = Mutex::new();
+    /// m.lock_group();
+    /// m.lock_group();
+    ///
+    /// let s = m.snapshot();
+    /// assert_eq!(s.state, LockState::Group);
+    /// assert_eq!(s.group_holders, 2);
+    /// assert_eq!(s.parked_exclusive, 0);
+    /// ```
+    pub fn snapshot(&self) -> MutexState {
+        let inner = self.inner();
+        let backoff = Backoff::new();
+        let mut attempts = 1;
+
+        loop {
+            let state = inner.state.load(Acquire);
+            let locked = inner.locked.load(Acquire);
+            let (parked_exclusive, parked_group) = self.parked_counts();
+            let ref_count = inner.ref_count.load(Acquire);
+
+            let stable = inner.state.load(Acquire) == state && inner.locked.load(Acquire) == locked;
+            if stable || attempts == SNAPSHOT_ATTEMPTS {
+                return MutexState {
+                    state: match state {
+                        UNLOCKED => LockState::Unlocked,
+                        LOCKED => LockState::Exclusive,
+                        LOCKED_GROUP => LockState::Group,
+                        _ => LockState::Dirty,
+                    },
+                    group_holders: locked,
+                    parked_exclusive,
+                    parked_group,
+                    ref_count,
+                };
             }
+
+            attempts += 1;
+            backoff.spin();
         }
     }
 
@@ -227,6 +678,7 @@ impl Mutex {
     pub fn unlock_all_group(&self) {
         self.inner().locked.store(1, Release);
         self.unlock_group();
+        self.released_all(MutexType::Group);
     }
 
     pub fn unlock_group(&self) {
@@ -237,6 +689,7 @@ impl Mutex {
             panic!("Trying to unlock a non Locked Group {}", state);
         }
 
+        self.released(MutexType::Group);
         if inner.locked.fetch_sub(1, Release) == 1 {
             inner.state.store(DIRTY, Release);
 
@@ -256,6 +709,7 @@ impl Mutex {
         {
             panic!("Is not Locked or is a Locked Group.");
         }
+        self.released(MutexType::Exclusive);
 
         // if there are some thread suspended now we must wake them up
         if !self.wake(MutexType::Group) {
@@ -263,116 +717,423 @@ impl Mutex {
         }
     }
 
-    pub fn try_lock_exclusive(&self) -> bool {
-        if self.inner().locked.load(Acquire) == 0 {
-            return self
-                .inner()
-                .state
-                .compare_exchange(DIRTY, LOCKED, Acquire, Relaxed)
-                .is_ok();
+    /// Releases an exclusive lock handing it directly to the threads waiting for it, so the
+    /// current thread can't take it back before them.
+    ///
+    /// Group members waiting get the lock first, as with [`Mutex::unlock_exclusive`],
+    /// otherwise a parked exclusive waiter is woken up already owning the lock.
+    /// Without waiters it is a plain unlock.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Mutex;
+    ///
+    /// let m = Mutex::new();
+    /// m.lock_exclusive();
+    /// m.unlock_exclusive_fair();
+    /// assert!(!m.is_locked());
+    /// ```
+    pub fn unlock_exclusive_fair(&self) {
+        let inner = self.inner();
+        if inner.state.load(Relaxed) != LOCKED {
+            panic!("Is not Locked or is a Locked Group.");
         }
 
-        self.inner()
+        // group waiters are counted before they wait, they all get in with the state switched
+        if inner.locked.load(Acquire) > 0 {
+            self.released(MutexType::Exclusive);
+            inner.state.store(LOCKED_GROUP, Release);
+            self.wake_all(MutexType::Group);
+            return;
+        }
+
+        if !self.hand_off_exclusive() {
+            self.unlock_exclusive();
+        }
+    }
+
+    /// Lets the parked waiters take an exclusive lock held by the current thread, then takes
+    /// it back, see [`Mutex::unlock_exclusive_fair`]. Does nothing if nobody is parked.
+    ///
+    /// Useful to keep a long critical section from starving the other threads.
+    pub fn bump_exclusive(&self) {
+        if self.has_parked() || self.inner().locked.load(Relaxed) > 0 {
+            self.unlock_exclusive_fair();
+            self.raw_lock_exclusive();
+        }
+    }
+
+    fn raw_try_lock_exclusive(&self) -> bool {
+        let inner = self.inner();
+        // a DIRTY mutex is free for an exclusive lock only if no group member is waiting
+        let taken = inner
             .state
             .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
             .is_ok()
+            || (inner.locked.load(Acquire) == 0
+                && inner
+                    .state
+                    .compare_exchange(DIRTY, LOCKED, Acquire, Relaxed)
+                    .is_ok());
+
+        if taken {
+            self.held(MutexType::Exclusive);
+        }
+        taken
     }
 
-    #[inline]
-    fn suspend(&self, t: MutexType) {
-        if self
-            .inner()
-            .wake_deadlock
-            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
-            .is_err()
-        {
+    fn raw_try_lock_group(&self) -> bool {
+        let inner = self.inner();
+        inner.locked.fetch_add(1, Release);
+
+        let taken = self.try_take(MutexType::Group, inner.state.load(Relaxed));
+        if taken {
+            self.held(MutexType::Group);
+        } else {
+            self.abandon_group();
+        }
+        taken
+    }
+
+    #[cfg(feature = "std")]
+    fn raw_lock_exclusive_timeout(&self, timeout: Duration) -> bool {
+        let taken = self.acquire_until(MutexType::Exclusive, Instant::now() + timeout);
+        if taken {
+            self.held(MutexType::Exclusive);
+        }
+        taken
+    }
+
+    #[cfg(feature = "std")]
+    fn raw_lock_group_timeout(&self, timeout: Duration) -> bool {
+        self.inner().locked.fetch_add(1, Release);
+
+        let taken = self.acquire_until(MutexType::Group, Instant::now() + timeout);
+        if taken {
+            self.held(MutexType::Group);
+        } else {
+            self.abandon_group();
+        }
+        taken
+    }
+
+    /// Like `acquire_backoff`, but gives up at `deadline`. Group waiters must already be
+    /// counted in `locked`.
+    #[cfg(feature = "std")]
+    fn acquire_until(&self, t: MutexType, deadline: Instant) -> bool {
+        let backoff = Backoff::new();
+
+        loop {
+            if self.try_take(t, self.spin(10)) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            if backoff.is_completed() {
+                if self.suspend_until(t, Some(deadline)) == ParkResult::Unparked(TOKEN_HANDOFF) {
+                    return true;
+                }
+            } else {
+                backoff.snooze();
+            }
+        }
+    }
+
+    /// Uncounts a group waiter giving up, without the lock.
+    fn abandon_group(&self) {
+        let inner = self.inner();
+        if inner.locked.fetch_sub(1, Release) != 1 {
             return;
         }
-        let parking = match t {
-            MutexType::Exclusive => &self.inner().parking_e,
-            MutexType::Group => &self.inner().parking_g,
-        };
-        parking.push(thread::current());
-        self.inner().wake_deadlock.store(UNLOCKED, Release);
-        thread::park();
+
+        // a fair unlock may have switched to the group mode for us alone, leave it as
+        // `unlock_group` would; exclusive waiters may have parked seeing us counted.
+        let switched = inner
+            .state
+            .compare_exchange(LOCKED_GROUP, DIRTY, Release, Relaxed)
+            .is_ok();
+        if (switched || inner.state.load(Relaxed) == DIRTY) && !self.wake(MutexType::Exclusive) {
+            self.wake(MutexType::Group);
+        }
+    }
+
+    /// Checks if a lock of type `t` could be taken right now.
+    #[inline]
+    fn can_acquire(&self, t: MutexType) -> bool {
+        let inner = self.inner();
+        match (inner.state.load(Relaxed), t) {
+            (UNLOCKED, _) => true,
+            (DIRTY, MutexType::Group) | (LOCKED_GROUP, MutexType::Group) => true,
+            (DIRTY, MutexType::Exclusive) => inner.locked.load(Relaxed) == 0,
+            _ => false,
+        }
+    }
+
+    /// Key of the parking lot queue for waiters of type `t`.
+    #[inline]
+    fn park_key(&self, t: MutexType) -> usize {
+        match t {
+            MutexType::Exclusive => self.ptr.addr(),
+            MutexType::Group => self.ptr.addr() + 1,
+        }
+    }
+
+    #[inline]
+    fn parked_bit(t: MutexType) -> u8 {
+        match t {
+            MutexType::Exclusive => PARKED_EXCLUSIVE,
+            MutexType::Group => PARKED_GROUP,
+        }
+    }
+
+    /// Parks until woken up, returns `true` if the lock has been handed over by
+    /// [`Mutex::unlock_exclusive_fair`].
+    #[cfg(feature = "std")]
+    #[inline]
+    fn suspend(&self, t: MutexType) -> bool {
+        self.suspend_until(t, None) == ParkResult::Unparked(TOKEN_HANDOFF)
+    }
+
+    #[cfg(feature = "std")]
+    fn suspend_until(&self, t: MutexType, deadline: Option<Instant>) -> ParkResult {
+        let inner = self.inner();
+        let bit = Self::parked_bit(t);
+        let ticket = watchdog::is_enabled().then(|| watchdog::parked(self.as_unleveled(), t));
+
+        let res = parking::park(
+            self.park_key(t),
+            || {
+                inner.parked.fetch_or(bit, Relaxed);
+                // pairs with the fence in `wake`: either the unlocking thread sees the parked
+                // bit or we see the released state.
+                atomic::fence(SeqCst);
+                !self.can_acquire(t)
+            },
+            |last| {
+                if last {
+                    inner.parked.fetch_and(!bit, Relaxed);
+                }
+            },
+            deadline,
+        );
+
+        if let Some(ticket) = ticket {
+            watchdog::unparked(ticket);
+        }
+        res
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn parked_counts(&self) -> (usize, usize) {
+        (
+            parking::waiters(self.park_key(MutexType::Exclusive)),
+            parking::waiters(self.park_key(MutexType::Group)),
+        )
+    }
+
+    // The holders are only tracked for the watchdog, while it is running.
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn held(&self, t: MutexType) {
+        if watchdog::is_enabled() {
+            watchdog::acquired(self.id(), t);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn released(&self, t: MutexType) {
+        if watchdog::is_enabled() {
+            watchdog::released(self.id(), t);
+        }
     }
 
+    #[cfg(feature = "std")]
+    #[inline]
+    fn released_all(&self, t: MutexType) {
+        if watchdog::is_enabled() {
+            watchdog::released_all(self.id(), t);
+        }
+    }
+
+    #[cfg(feature = "std")]
     #[inline]
     fn wake_all(&self, t: MutexType) {
-        while self
-            .inner()
-            .wake_deadlock
-            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
-            .is_err()
-        {
-            hint::spin_loop();
+        let inner = self.inner();
+        let bit = Self::parked_bit(t);
+
+        atomic::fence(SeqCst);
+        if inner.parked.load(Relaxed) & bit == 0 {
+            return;
         }
-        let parking = match t {
-            MutexType::Exclusive => &self.inner().parking_e,
-            MutexType::Group => &self.inner().parking_g,
-        };
 
-        if let Some(thread) = parking.pop() {
-            thread.unpark();
-            // pre-release to improve performances
-            self.inner().wake_deadlock.store(UNLOCKED, Release);
-            while let Some(thread) = parking.pop() {
-                thread.unpark();
-            }
+        inner.parked.fetch_and(!bit, Relaxed);
+        parking::unpark_all(self.park_key(t), DEFAULT_UNPARK_TOKEN);
+    }
+
+    /// Wakes a parked exclusive waiter handing it the lock, which stays `LOCKED`.
+    #[cfg(feature = "std")]
+    fn hand_off_exclusive(&self) -> bool {
+        let inner = self.inner();
+
+        atomic::fence(SeqCst);
+        if inner.parked.load(Relaxed) & PARKED_EXCLUSIVE == 0 {
+            return false;
         }
-        self.inner().wake_deadlock.store(UNLOCKED, Release);
+
+        let res = parking::unpark_one(self.park_key(MutexType::Exclusive), |res| {
+            if !res.have_more {
+                inner.parked.fetch_and(!PARKED_EXCLUSIVE, Relaxed);
+            }
+            if res.unparked != 0 {
+                // the holder changes while the lock stays taken
+                self.released(MutexType::Exclusive);
+                TOKEN_HANDOFF
+            } else {
+                DEFAULT_UNPARK_TOKEN
+            }
+        });
+        res.unparked != 0
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn has_parked(&self) -> bool {
+        atomic::fence(SeqCst);
+        self.inner().parked.load(Relaxed) != 0
     }
 
+    #[cfg(feature = "std")]
     #[inline]
     fn wake(&self, t: MutexType) -> bool {
-        while self
-            .inner()
-            .wake_deadlock
-            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
-            .is_err()
-        {
-            hint::spin_loop();
+        let inner = self.inner();
+        let bit = Self::parked_bit(t);
+
+        atomic::fence(SeqCst);
+        if inner.parked.load(Relaxed) & bit == 0 {
+            return false;
         }
-        let parking = match t {
-            MutexType::Exclusive => &self.inner().parking_e,
-            MutexType::Group => &self.inner().parking_g,
-        };
-        let res = if let Some(thread) = parking.pop() {
-            thread.unpark();
-            true
-        } else {
-            false
-        };
-        self.inner().wake_deadlock.store(UNLOCKED, Release);
-        res
+
+        let res = parking::unpark_one(self.park_key(t), |res| {
+            if !res.have_more {
+                inner.parked.fetch_and(!bit, Relaxed);
+            }
+            DEFAULT_UNPARK_TOKEN
+        });
+        res.unparked != 0
+    }
+
+    // Without `std` there are no threads to park: `Backoff` never completes and the
+    // lock is spin-only, so there is never anyone to wake up.
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn suspend(&self, _t: MutexType) -> bool {
+        hint::spin_loop();
+        false
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn wake_all(&self, _t: MutexType) {}
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn parked_counts(&self) -> (usize, usize) {
+        (0, 0)
     }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn held(&self, _t: MutexType) {}
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn released(&self, _t: MutexType) {}
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn released_all(&self, _t: MutexType) {}
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn wake(&self, _t: MutexType) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn hand_off_exclusive(&self) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn has_parked(&self) -> bool {
+        false
+    }
+}
+
+/// Takes `lock` again in `mode` when dropped, also while unwinding.
+pub(crate) struct Relock<'a> {
+    pub(crate) lock: &'a Mutex,
+    pub(crate) mode: MutexType,
+    /// the writes of the guard being relocked, opened again with the lock
+    pub(crate) version: Option<&'a Version>,
 }
 
-impl Clone for Mutex {
+impl Drop for Relock<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        match self.mode {
+            MutexType::Exclusive => self.lock.lock_exclusive(),
+            MutexType::Group => self.lock.lock_group(),
+        }
+        if let Some(version) = self.version {
+            version.begin_write();
+        }
+    }
+}
+
+impl<L> Clone for Mutex<L> {
     fn clone(&self) -> Self {
         self.inner().ref_count.fetch_add(1, Relaxed);
-        Mutex { ptr: self.ptr }
+        Mutex {
+            ptr: self.ptr,
+            level: PhantomData,
+        }
     }
 }
 
-impl Drop for Mutex {
+impl<L> Drop for Mutex<L> {
     fn drop(&mut self) {
         if self.inner().ref_count.fetch_sub(1, Release) == 1 {
             atomic::fence(Acquire);
 
             let ptr = self.ptr as *mut InnerMutex;
-            unsafe { drop(Box::from_raw(ptr)) };
+            match self.backend() {
+                MutexBackend::Standard => unsafe { drop(Box::from_raw(ptr)) },
+                MutexBackend::Queued => unsafe {
+                    drop(Box::from_raw(ptr.cast::<QueuedInnerMutex>()))
+                },
+            }
         }
     }
 }
 
-impl fmt::Debug for Mutex {
+impl<L> fmt::Debug for Mutex<L> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let inner = self.inner();
         f.debug_struct("Mutex")
+            .field("backend", &self.backend())
             .field("locked", &(inner.state.load(Relaxed) != UNLOCKED))
             .field("group", &inner.state.load(Relaxed))
             .field("lockers", &inner.locked.load(Relaxed))
+            .field("spins", &inner.spins.load(Relaxed))
             .field("ref", &inner.ref_count.load(Relaxed))
             .finish()
     }
@@ -0,0 +1,130 @@
+use crate::mutex::{Mutex, Version};
+use core::fmt::{Debug, Formatter};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// An exclusive lock guard owning a clone of the handle `O` the data belongs to, so it
+/// borrows nothing and can be returned from functions or moved into other threads.
+///
+/// # Example
+/// ```
+/// use castbox::Arw;
+/// use std::thread;
+///
+/// let a = Arw::new(1);
+/// let mut g = a.write_owned();
+/// thread::spawn(move || *g += 1).join().unwrap();
+/// assert_eq!(*a.as_ref(), 2);
+/// ```
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct OwnedWatchGuardMut<O, T: ?Sized> {
+    data: *mut T,
+    lock: Mutex,
+    /// tracks the writes to the data, if set
+    version: Option<NonNull<Version>>,
+    /// reports the guard as a change to the watchers of `version` once released
+    changed: bool,
+    /// keeps the allocation of `data` and `version` alive
+    _owner: O,
+}
+
+impl<O, T: ?Sized> OwnedWatchGuardMut<O, T> {
+    /// Creates a guard for `data`, owned by `owner` and guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held exclusively and `data` must stay valid while `owner` is alive
+    /// and `lock` is held.
+    pub(crate) unsafe fn new(owner: O, data: *mut T, lock: Mutex) -> Self {
+        Self {
+            data,
+            lock,
+            version: None,
+            changed: false,
+            _owner: owner,
+        }
+    }
+
+    /// Like [`OwnedWatchGuardMut::new`], recording the writes to the data in `version`
+    /// until the guard is released.
+    ///
+    /// # Safety
+    /// As for `new`, and `version` must stay valid while `owner` is alive.
+    pub(crate) unsafe fn versioned(
+        owner: O,
+        data: *mut T,
+        lock: Mutex,
+        version: &Version,
+    ) -> Self {
+        version.begin_write();
+        Self {
+            data,
+            lock,
+            version: Some(NonNull::from(version)),
+            changed: true,
+            _owner: owner,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked_exclusive()
+    }
+
+    /// Doesn't report the guard as a change when released, like
+    /// [`WatchGuardMut::mark_unchanged`](crate::mutex::WatchGuardMut::mark_unchanged).
+    #[inline]
+    pub fn mark_unchanged(&mut self) {
+        self.changed = false;
+    }
+}
+
+unsafe impl<O: Send, T: ?Sized + Send> Send for OwnedWatchGuardMut<O, T> {}
+unsafe impl<O: Sync, T: ?Sized + Sync> Sync for OwnedWatchGuardMut<O, T> {}
+
+impl<O, T: ?Sized> Deref for OwnedWatchGuardMut<O, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<O, T: ?Sized> DerefMut for OwnedWatchGuardMut<O, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<O, T: ?Sized> Drop for OwnedWatchGuardMut<O, T> {
+    #[inline]
+    fn drop(&mut self) {
+        // the owner is dropped after, once the data is not in use anymore
+        // SAFETY: the owner keeps the version alive
+        let version = self.version.map(|version| unsafe { version.as_ref() });
+        if let Some(version) = version {
+            version.end_write();
+        }
+        self.lock.unlock_exclusive();
+        match version {
+            Some(version) if self.changed => version.bump(),
+            _ => {}
+        }
+    }
+}
+
+impl<O, T, U> PartialEq<U> for OwnedWatchGuardMut<O, T>
+where
+    T: PartialEq<U> + ?Sized,
+{
+    fn eq(&self, other: &U) -> bool {
+        **self == *other
+    }
+}
+
+impl<O, T: Debug + ?Sized> Debug for OwnedWatchGuardMut<O, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OwnedWatchGuardMut")
+            .field("data", &&**self)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
@@ -0,0 +1,83 @@
+use crate::mutex::Mutex;
+use core::fmt::{Debug, Formatter};
+use core::ops::Deref;
+
+/// A group lock guard owning a clone of the handle `O` the data belongs to, so it borrows
+/// nothing and can be returned from functions or moved into other threads.
+///
+/// # Example
+/// ```
+/// use castbox::Arw;
+/// use castbox::mutex::OwnedWatchGuardRef;
+/// use std::thread;
+///
+/// fn config() -> OwnedWatchGuardRef<Arw<String>, String> {
+///     Arw::new(String::from("fast")).read_owned()
+/// }
+///
+/// let g = config();
+/// thread::spawn(move || assert_eq!(*g, "fast")).join().unwrap();
+/// ```
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct OwnedWatchGuardRef<O, T: ?Sized> {
+    data: *const T,
+    lock: Mutex,
+    /// keeps the allocation of `data` alive
+    _owner: O,
+}
+
+impl<O, T: ?Sized> OwnedWatchGuardRef<O, T> {
+    /// Creates a guard for `data`, owned by `owner` and guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held in group mode and `data` must stay valid while `owner` is
+    /// alive and `lock` is held.
+    pub(crate) unsafe fn new(owner: O, data: *const T, lock: Mutex) -> Self {
+        Self {
+            data,
+            lock,
+            _owner: owner,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked_group()
+    }
+}
+
+unsafe impl<O: Send, T: ?Sized + Sync> Send for OwnedWatchGuardRef<O, T> {}
+unsafe impl<O: Sync, T: ?Sized + Sync> Sync for OwnedWatchGuardRef<O, T> {}
+
+impl<O, T: ?Sized> Deref for OwnedWatchGuardRef<O, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<O, T: ?Sized> Drop for OwnedWatchGuardRef<O, T> {
+    #[inline]
+    fn drop(&mut self) {
+        // the owner is dropped after, once the data is not in use anymore
+        self.lock.unlock_group();
+    }
+}
+
+impl<O, T, U> PartialEq<U> for OwnedWatchGuardRef<O, T>
+where
+    T: PartialEq<U> + ?Sized,
+{
+    fn eq(&self, other: &U) -> bool {
+        **self == *other
+    }
+}
+
+impl<O, T: Debug + ?Sized> Debug for OwnedWatchGuardRef<O, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OwnedWatchGuardRef")
+            .field("data", &&**self)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
@@ -0,0 +1,302 @@
+use crate::mutex::Backoff;
+use core::cell::Cell;
+use core::ptr::{self, null};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicBool, AtomicPtr};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+/// Number of buckets of the global table, must be a power of two.
+const BUCKETS_SHIFT: u32 = 8;
+const BUCKETS: usize = 1 << BUCKETS_SHIFT;
+
+/// Value passed from the unparking thread to the unparked one.
+pub(crate) type UnparkToken = usize;
+
+/// Token used when the unparker has nothing special to tell to the woken thread.
+pub(crate) const DEFAULT_UNPARK_TOKEN: UnparkToken = 0;
+
+/// Result of a [`park`] call.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ParkResult {
+    /// Woken up by an unpark call, with the token given by the unparker.
+    Unparked(UnparkToken),
+    /// The validation callback returned `false`, the thread never slept.
+    Invalid,
+    /// The deadline was reached before an unpark call.
+    TimedOut,
+}
+
+/// Result of an [`unpark_one`] call, also handed to its callback.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct UnparkResult {
+    /// Number of threads woken up.
+    pub(crate) unparked: usize,
+    /// Whether there are still threads parked on the same key.
+    pub(crate) have_more: bool,
+}
+
+/// A parked thread, living on the stack of the thread itself for the whole `park` call.
+struct Waiter {
+    key: usize,
+    thread: Thread,
+    /// next waiter of the bucket, only accessed under the bucket lock
+    next: Cell<*const Waiter>,
+    /// set by the unparker, only accessed under the bucket lock
+    token: Cell<UnparkToken>,
+    /// set by the unparker once the waiter has been removed from the bucket
+    unparked: AtomicBool,
+}
+
+#[repr(align(64))]
+struct Bucket {
+    locked: AtomicBool,
+    head: AtomicPtr<Waiter>,
+    tail: AtomicPtr<Waiter>,
+}
+
+impl Bucket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: Bucket = Bucket {
+        locked: AtomicBool::new(false),
+        head: AtomicPtr::new(ptr::null_mut()),
+        tail: AtomicPtr::new(ptr::null_mut()),
+    };
+
+    #[inline]
+    fn lock(&self) {
+        let backoff = Backoff::new();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Acquire, Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+    }
+
+    #[inline]
+    fn unlock(&self) {
+        self.locked.store(false, Release);
+    }
+
+    /// Appends `waiter` to the queue, the bucket must be locked.
+    fn push(&self, waiter: *const Waiter) {
+        let tail = self.tail.load(Relaxed);
+        if tail.is_null() {
+            self.head.store(waiter as *mut Waiter, Relaxed);
+        } else {
+            unsafe { (*tail).next.set(waiter) };
+        }
+        self.tail.store(waiter as *mut Waiter, Relaxed);
+    }
+
+    /// Unlinks `waiter`, whose predecessor is `prev`, the bucket must be locked.
+    fn unlink(&self, prev: *const Waiter, waiter: *const Waiter) {
+        let next = unsafe { (*waiter).next.get() };
+        if prev.is_null() {
+            self.head.store(next as *mut Waiter, Relaxed);
+        } else {
+            unsafe { (*prev).next.set(next) };
+        }
+        if ptr::eq(self.tail.load(Relaxed), waiter) {
+            self.tail.store(prev as *mut Waiter, Relaxed);
+        }
+    }
+
+    /// Checks if some waiter after `from` is parked on `key`, the bucket must be locked.
+    fn has_key_from(&self, mut from: *const Waiter, key: usize) -> bool {
+        while !from.is_null() {
+            unsafe {
+                if (*from).key == key {
+                    return true;
+                }
+                from = (*from).next.get();
+            }
+        }
+        false
+    }
+}
+
+// SAFETY: the waiters linked in a bucket are only accessed while holding its lock.
+unsafe impl Sync for Bucket {}
+
+static TABLE: [Bucket; BUCKETS] = [Bucket::NEW; BUCKETS];
+
+/// Locks and returns the bucket used for `key`.
+#[inline]
+fn lock_bucket(key: usize) -> &'static Bucket {
+    // fibonacci hashing, the low bits of an address are mostly alignment
+    let hash = key.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+    let bucket = &TABLE[hash >> (usize::BITS - BUCKETS_SHIFT)];
+    bucket.lock();
+    bucket
+}
+
+/// Parks the current thread on `key` until another thread calls [`unpark_one`] or
+/// [`unpark_all`] on the same key, or until `deadline` is reached.
+///
+/// `validate` is called with the bucket locked: returning `false` aborts the park, so a
+/// condition checked there can't be changed by an unparker without the thread being queued.
+/// `timed_out` is called with the bucket locked when the deadline is reached, telling if this
+/// was the last thread parked on `key`.
+pub(crate) fn park<V, T>(
+    key: usize,
+    validate: V,
+    timed_out: T,
+    deadline: Option<Instant>,
+) -> ParkResult
+where
+    V: FnOnce() -> bool,
+    T: FnOnce(bool),
+{
+    let bucket = lock_bucket(key);
+
+    if !validate() {
+        bucket.unlock();
+        return ParkResult::Invalid;
+    }
+
+    let waiter = Waiter {
+        key,
+        thread: thread::current(),
+        next: Cell::new(null()),
+        token: Cell::new(DEFAULT_UNPARK_TOKEN),
+        unparked: AtomicBool::new(false),
+    };
+    bucket.push(&waiter);
+    bucket.unlock();
+
+    loop {
+        // Acquire: synchronizes with the unparker, the waiter is not in the queue anymore
+        if waiter.unparked.load(Acquire) {
+            return ParkResult::Unparked(waiter.token.get());
+        }
+
+        match deadline {
+            None => thread::park(),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now < deadline {
+                    thread::park_timeout(deadline - now);
+                    continue;
+                }
+
+                let bucket = lock_bucket(key);
+                if waiter.unparked.load(Relaxed) {
+                    // unparked in the meantime
+                    bucket.unlock();
+                    return ParkResult::Unparked(waiter.token.get());
+                }
+
+                let mut prev: *const Waiter = null();
+                let mut cur: *const Waiter = bucket.head.load(Relaxed);
+                while !ptr::eq(cur, &waiter) {
+                    prev = cur;
+                    cur = unsafe { (*cur).next.get() };
+                }
+                bucket.unlink(prev, cur);
+
+                let head = bucket.head.load(Relaxed);
+                timed_out(!bucket.has_key_from(head, key));
+                bucket.unlock();
+                return ParkResult::TimedOut;
+            }
+        }
+    }
+}
+
+/// Wakes up the first thread parked on `key`.
+///
+/// `callback` is called with the bucket locked, before the thread is woken up, and returns
+/// the token to hand over to the woken thread.
+pub(crate) fn unpark_one<C>(key: usize, callback: C) -> UnparkResult
+where
+    C: FnOnce(UnparkResult) -> UnparkToken,
+{
+    let bucket = lock_bucket(key);
+
+    let mut prev: *const Waiter = null();
+    let mut cur: *const Waiter = bucket.head.load(Relaxed);
+    while !cur.is_null() {
+        if unsafe { (*cur).key } == key {
+            bucket.unlink(prev, cur);
+
+            let next = unsafe { (*cur).next.get() };
+            let result = UnparkResult {
+                unparked: 1,
+                have_more: bucket.has_key_from(next, key),
+            };
+            let token = callback(result);
+
+            // the waiter may leave as soon as `unparked` is set, so take the handle before
+            let thread = unsafe { (*cur).thread.clone() };
+            unsafe {
+                (*cur).token.set(token);
+                (*cur).unparked.store(true, Release);
+            }
+            bucket.unlock();
+
+            thread.unpark();
+            return result;
+        }
+        prev = cur;
+        cur = unsafe { (*cur).next.get() };
+    }
+
+    let result = UnparkResult {
+        unparked: 0,
+        have_more: false,
+    };
+    callback(result);
+    bucket.unlock();
+    result
+}
+
+/// Wakes up all the threads parked on `key`, returns how many have been woken up.
+pub(crate) fn unpark_all(key: usize, token: UnparkToken) -> usize {
+    let bucket = lock_bucket(key);
+
+    let mut threads = Vec::new();
+    let mut prev: *const Waiter = null();
+    let mut cur: *const Waiter = bucket.head.load(Relaxed);
+    while !cur.is_null() {
+        let next = unsafe { (*cur).next.get() };
+        if unsafe { (*cur).key } == key {
+            bucket.unlink(prev, cur);
+            unsafe {
+                threads.push((*cur).thread.clone());
+                (*cur).token.set(token);
+                (*cur).unparked.store(true, Release);
+            }
+        } else {
+            prev = cur;
+        }
+        cur = next;
+    }
+    bucket.unlock();
+
+    let count = threads.len();
+    for thread in threads {
+        thread.unpark();
+    }
+    count
+}
+
+/// Returns the number of threads currently parked on `key`.
+pub(crate) fn waiters(key: usize) -> usize {
+    let bucket = lock_bucket(key);
+
+    let mut count = 0;
+    let mut cur: *const Waiter = bucket.head.load(Relaxed);
+    while !cur.is_null() {
+        unsafe {
+            if (*cur).key == key {
+                count += 1;
+            }
+            cur = (*cur).next.get();
+        }
+    }
+    bucket.unlock();
+    count
+}
@@ -0,0 +1,349 @@
+use crate::mutex::Backoff;
+use crate::mutex::parking::{self, DEFAULT_UNPARK_TOKEN, ParkResult};
+use alloc::boxed::Box;
+use core::fmt;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::sync::atomic;
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::time::Duration;
+use std::time::Instant;
+
+/// Layout packing (AtomicU64):
+/// lower 16 bit: parties not yet arrived in the current phase
+/// next 16 bit: registered parties
+/// next 31 bit: phase number
+/// highest bit: terminated
+const UNARRIVED_MASK: u64 = 0xffff;
+const PARTIES_SHIFT: u32 = 16;
+const PARTIES_MASK: u64 = 0xffff << PARTIES_SHIFT;
+const PHASE_SHIFT: u32 = 32;
+const PHASE_MASK: u64 = 0x7fff_ffff;
+const TERMINATED: u64 = 1 << 63;
+
+/// Max number of parties registered at the same time.
+pub const MAX_PARTIES: usize = 0xffff;
+
+#[inline]
+fn unarrived_of(s: u64) -> usize {
+    (s & UNARRIVED_MASK) as usize
+}
+
+#[inline]
+fn parties_of(s: u64) -> usize {
+    ((s & PARTIES_MASK) >> PARTIES_SHIFT) as usize
+}
+
+#[inline]
+fn phase_of(s: u64) -> u32 {
+    ((s >> PHASE_SHIFT) & PHASE_MASK) as u32
+}
+
+#[inline]
+fn pack(phase: u32, parties: usize, unarrived: usize) -> u64 {
+    ((phase as u64 & PHASE_MASK) << PHASE_SHIFT)
+        | ((parties as u64) << PARTIES_SHIFT)
+        | unarrived as u64
+}
+
+struct InnerPhaser {
+    state: AtomicU64,
+    ref_count: AtomicUsize,
+}
+
+/// A reference counted, reusable barrier whose parties can join and leave at any phase,
+/// like Java's `Phaser`.
+///
+/// Every phase ends when all the registered parties have arrived: the phase number is then
+/// advanced and the waiters released. The phaser terminates when the last party
+/// deregisters, releasing every waiter for good.
+///
+/// # Example
+/// ```
+/// use castbox::mutex::Phaser;
+/// use std::thread;
+///
+/// let phaser = Phaser::new(1);
+/// let mut workers = Vec::new();
+/// for _ in 0..3 {
+///     let p = phaser.clone();
+///     p.register();
+///     workers.push(thread::spawn(move || {
+///         for _ in 0..2 {
+///             p.arrive_and_await_advance();
+///         }
+///         p.arrive_and_deregister();
+///     }));
+/// }
+///
+/// // the main thread takes part in the first two phases and then leaves
+/// assert_eq!(phaser.arrive_and_await_advance(), 1);
+/// assert_eq!(phaser.arrive_and_await_advance(), 2);
+/// phaser.arrive_and_deregister();
+///
+/// for w in workers {
+///     w.join().unwrap();
+/// }
+/// assert!(phaser.is_terminated());
+/// ```
+#[repr(transparent)]
+pub struct Phaser {
+    ptr: *const InnerPhaser,
+}
+
+unsafe impl Send for Phaser {}
+unsafe impl Sync for Phaser {}
+
+impl UnwindSafe for Phaser {}
+impl RefUnwindSafe for Phaser {}
+
+impl Phaser {
+    /// Creates a new `Phaser` at phase `0` with `parties` registered parties.
+    ///
+    /// # Panics
+    /// Panics if `parties` is greater than [`MAX_PARTIES`].
+    pub fn new(parties: usize) -> Self {
+        if parties > MAX_PARTIES {
+            panic!("Too many parties for a Phaser: {}", parties);
+        }
+
+        let ptr = Box::into_raw(Box::new(InnerPhaser {
+            state: AtomicU64::new(pack(0, parties, parties)),
+            ref_count: AtomicUsize::new(1),
+        }));
+        if ptr.is_null() {
+            panic!("Happened an invalid allocation for Phaser");
+        }
+        Self { ptr }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &InnerPhaser {
+        unsafe { &*self.ptr }
+    }
+
+    /// Key of the parking lot queue of the waiters.
+    #[inline]
+    fn park_key(&self) -> usize {
+        self.ptr.addr()
+    }
+
+    /// Current phase number, it wraps around after `2^31 - 1`.
+    #[inline]
+    pub fn phase(&self) -> u32 {
+        phase_of(self.inner().state.load(Acquire))
+    }
+
+    #[inline]
+    pub fn registered_parties(&self) -> usize {
+        parties_of(self.inner().state.load(Acquire))
+    }
+
+    /// Parties that have not arrived yet in the current phase.
+    #[inline]
+    pub fn unarrived_parties(&self) -> usize {
+        unarrived_of(self.inner().state.load(Acquire))
+    }
+
+    #[inline]
+    pub fn arrived_parties(&self) -> usize {
+        let s = self.inner().state.load(Acquire);
+        parties_of(s) - unarrived_of(s)
+    }
+
+    #[inline]
+    pub fn is_terminated(&self) -> bool {
+        self.inner().state.load(Acquire) & TERMINATED != 0
+    }
+
+    pub fn get_ref_count(&self) -> usize {
+        self.inner().ref_count.load(Acquire)
+    }
+
+    /// Adds a new unarrived party to the current phase, returns the phase number.
+    /// Does nothing on a terminated phaser.
+    ///
+    /// # Panics
+    /// Panics if [`MAX_PARTIES`] parties are already registered.
+    pub fn register(&self) -> u32 {
+        let inner = self.inner();
+        let mut s = inner.state.load(Relaxed);
+
+        loop {
+            if s & TERMINATED != 0 {
+                return phase_of(s);
+            }
+            if parties_of(s) == MAX_PARTIES {
+                panic!("Too many parties for a Phaser");
+            }
+
+            let next = pack(phase_of(s), parties_of(s) + 1, unarrived_of(s) + 1);
+            match inner.state.compare_exchange_weak(s, next, AcqRel, Relaxed) {
+                Ok(_) => return phase_of(s),
+                Err(cur) => s = cur,
+            }
+        }
+    }
+
+    /// Arrives at the current phase without waiting for the others, returns the arrival
+    /// phase number.
+    ///
+    /// # Panics
+    /// Panics if all the registered parties have already arrived.
+    pub fn arrive(&self) -> u32 {
+        self.do_arrive(false)
+    }
+
+    /// Arrives at the current phase and leaves the phaser, returns the arrival phase number.
+    /// The phaser terminates when its last party leaves.
+    ///
+    /// # Panics
+    /// Panics if all the registered parties have already arrived.
+    pub fn arrive_and_deregister(&self) -> u32 {
+        self.do_arrive(true)
+    }
+
+    /// Arrives at the current phase and waits for the others, returns the new phase number.
+    ///
+    /// # Panics
+    /// Panics if all the registered parties have already arrived.
+    pub fn arrive_and_await_advance(&self) -> u32 {
+        let phase = self.do_arrive(false);
+        self.await_advance(phase)
+    }
+
+    /// Like [`Phaser::arrive_and_await_advance`], but gives up waiting after `timeout`,
+    /// returning `None`. The arrival is counted anyway.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::mutex::Phaser;
+    /// use std::time::Duration;
+    ///
+    /// let p = Phaser::new(2);
+    /// assert_eq!(p.arrive_and_await_advance_timeout(Duration::from_millis(10)), None);
+    /// // the second party completes the phase
+    /// assert_eq!(p.arrive_and_await_advance_timeout(Duration::from_millis(10)), Some(1));
+    /// ```
+    pub fn arrive_and_await_advance_timeout(&self, timeout: Duration) -> Option<u32> {
+        let phase = self.do_arrive(false);
+        self.wait_deadline(phase, Some(Instant::now() + timeout))
+    }
+
+    /// Waits until the phaser leaves `phase`, returns the new phase number.
+    /// Returns immediately if the current phase is already another one or the phaser is
+    /// terminated.
+    pub fn await_advance(&self, phase: u32) -> u32 {
+        self.wait_deadline(phase, None)
+            .expect("a wait without deadline can't time out")
+    }
+
+    /// Like [`Phaser::await_advance`], but returns `None` if the phase didn't advance
+    /// within `timeout`.
+    pub fn await_advance_timeout(&self, phase: u32, timeout: Duration) -> Option<u32> {
+        self.wait_deadline(phase, Some(Instant::now() + timeout))
+    }
+
+    fn do_arrive(&self, deregister: bool) -> u32 {
+        let inner = self.inner();
+        let mut s = inner.state.load(Relaxed);
+
+        loop {
+            if s & TERMINATED != 0 {
+                return phase_of(s);
+            }
+
+            let phase = phase_of(s);
+            let unarrived = unarrived_of(s);
+            if unarrived == 0 {
+                panic!("Arrived at a Phaser with no unarrived parties");
+            }
+            let parties = parties_of(s) - deregister as usize;
+
+            let next = if unarrived > 1 {
+                pack(phase, parties, unarrived - 1)
+            } else if parties == 0 {
+                // the last party left
+                pack(phase.wrapping_add(1), 0, 0) | TERMINATED
+            } else {
+                pack(phase.wrapping_add(1), parties, parties)
+            };
+
+            // AcqRel: the work of the phase happens before the advance seen by the waiters
+            match inner.state.compare_exchange_weak(s, next, AcqRel, Relaxed) {
+                Ok(_) => {
+                    if unarrived == 1 {
+                        parking::unpark_all(self.park_key(), DEFAULT_UNPARK_TOKEN);
+                    }
+                    return phase;
+                }
+                Err(cur) => s = cur,
+            }
+        }
+    }
+
+    /// Waits for the phaser to leave `phase`, returns `None` if `deadline` is reached first.
+    fn wait_deadline(&self, phase: u32, deadline: Option<Instant>) -> Option<u32> {
+        let inner = self.inner();
+        let backoff = Backoff::new();
+        let advanced = |s: u64| phase_of(s) != phase || s & TERMINATED != 0;
+
+        loop {
+            let s = inner.state.load(Acquire);
+            if advanced(s) {
+                return Some(phase_of(s));
+            }
+
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+
+            // the phase is checked again with the queue locked, so an advance can't be missed
+            let res = parking::park(
+                self.park_key(),
+                || !advanced(inner.state.load(Relaxed)),
+                |_| {},
+                deadline,
+            );
+
+            if res == ParkResult::TimedOut {
+                // a last chance, the phase may have advanced right after the deadline
+                let s = inner.state.load(Acquire);
+                return advanced(s).then(|| phase_of(s));
+            }
+        }
+    }
+}
+
+impl Clone for Phaser {
+    fn clone(&self) -> Self {
+        self.inner().ref_count.fetch_add(1, Relaxed);
+        Phaser { ptr: self.ptr }
+    }
+}
+
+impl Drop for Phaser {
+    fn drop(&mut self) {
+        if self.inner().ref_count.fetch_sub(1, Release) == 1 {
+            atomic::fence(Acquire);
+
+            let ptr = self.ptr as *mut InnerPhaser;
+            unsafe { drop(Box::from_raw(ptr)) };
+        }
+    }
+}
+
+impl fmt::Debug for Phaser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.inner().state.load(Relaxed);
+        f.debug_struct("Phaser")
+            .field("phase", &phase_of(s))
+            .field("parties", &parties_of(s))
+            .field("unarrived", &unarrived_of(s))
+            .field("terminated", &(s & TERMINATED != 0))
+            .field("waiters", &parking::waiters(self.park_key()))
+            .field("ref", &self.inner().ref_count.load(Relaxed))
+            .finish()
+    }
+}
@@ -0,0 +1,116 @@
+use crate::mutex::Backoff;
+#[cfg(feature = "std")]
+use crate::mutex::parking::{self, DEFAULT_UNPARK_TOKEN};
+use core::hint;
+use core::ptr::null_mut;
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicPtr, AtomicU8};
+
+/// spinning on its own node
+const WAITING: u8 = 0;
+/// sleeping in the parking lot, keyed by the node address
+const PARKED: u8 = 1;
+/// first of the queue, allowed to compete for the lock
+const HEAD: u8 = 2;
+
+/// A waiter of an MCS queue, it lives on the stack of the waiting thread.
+pub(crate) struct QueueNode {
+    next: AtomicPtr<QueueNode>,
+    status: AtomicU8,
+}
+
+impl QueueNode {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(null_mut()),
+            status: AtomicU8::new(WAITING),
+        }
+    }
+
+    #[inline]
+    fn as_ptr(&self) -> *mut QueueNode {
+        self as *const QueueNode as *mut QueueNode
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn park(&self) {
+        parking::park(
+            self.as_ptr().addr(),
+            || {
+                self.status
+                    .compare_exchange(WAITING, PARKED, Relaxed, Relaxed)
+                    .is_ok()
+            },
+            |_| {},
+            None,
+        );
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline]
+    fn park(&self) {
+        hint::spin_loop();
+    }
+}
+
+/// Appends `node` to the queue ending in `tail` and waits until it becomes its head.
+///
+/// Every waiter spins on its own node, so the lock word is only touched by the head.
+pub(crate) fn wait_head(tail: &AtomicPtr<QueueNode>, node: &QueueNode) {
+    let prev = tail.swap(node.as_ptr(), AcqRel);
+    if prev.is_null() {
+        return;
+    }
+
+    // SAFETY: `prev` can't leave the queue before it sees our node linked, see `pass_head`
+    unsafe { (*prev).next.store(node.as_ptr(), Release) };
+
+    let backoff = Backoff::new();
+    while node.status.load(Acquire) != HEAD {
+        if backoff.is_completed() {
+            node.park();
+        } else {
+            backoff.snooze();
+        }
+    }
+}
+
+/// Removes the head `node` from the queue ending in `tail`, promoting its successor.
+pub(crate) fn pass_head(tail: &AtomicPtr<QueueNode>, node: &QueueNode) {
+    let mut next = node.next.load(Acquire);
+    if next.is_null() {
+        if tail
+            .compare_exchange(node.as_ptr(), null_mut(), AcqRel, Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        // a successor swapped the tail, wait until it links itself
+        loop {
+            next = node.next.load(Acquire);
+            if !next.is_null() {
+                break;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // the successor may return as soon as it is the head, only its address is used after
+    let key = next.addr();
+    if unsafe { (*next).status.swap(HEAD, AcqRel) } == PARKED {
+        unpark(key);
+    }
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn unpark(key: usize) {
+    parking::unpark_one(key, |_| DEFAULT_UNPARK_TOKEN);
+}
+
+// without `std` nobody parks, so the status never becomes `PARKED`
+#[cfg(not(feature = "std"))]
+#[inline]
+fn unpark(_key: usize) {}
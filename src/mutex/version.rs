@@ -0,0 +1,121 @@
+#[cfg(feature = "std")]
+use crate::mutex::Backoff;
+#[cfg(feature = "std")]
+use crate::mutex::parking::{self, DEFAULT_UNPARK_TOKEN, ParkResult};
+use core::sync::atomic;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Counts the changes made to a value and wakes up the threads waiting for the next one.
+///
+/// It also tracks the exclusive writers like a [`SeqLock`](crate::SeqLock), so the value
+/// can be read without the lock and validated afterwards.
+pub(crate) struct Version {
+    /// even: stable, odd: a writer holds the value
+    seq: AtomicUsize,
+    /// changes reported to the watchers
+    count: AtomicUsize,
+    /// live watchers, writers skip the wake up while there are none
+    watchers: AtomicUsize,
+}
+
+impl Version {
+    pub(crate) const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            count: AtomicUsize::new(0),
+            watchers: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self) -> usize {
+        self.count.load(SeqCst)
+    }
+
+    /// Marks the value as being written, called with the exclusive lock held.
+    #[inline]
+    pub(crate) fn begin_write(&self) {
+        let seq = self.seq.load(Relaxed);
+        self.seq.store(seq.wrapping_add(1), Relaxed);
+        // orders the odd sequence before the writes to the value
+        atomic::fence(Release);
+    }
+
+    /// Marks the value as stable again, called before releasing the exclusive lock.
+    #[inline]
+    pub(crate) fn end_write(&self) {
+        self.seq.fetch_add(1, Release);
+    }
+
+    /// Starts an optimistic read, `None` if a writer holds the value.
+    #[inline]
+    pub(crate) fn read_begin(&self) -> Option<usize> {
+        let seq = self.seq.load(Acquire);
+        (seq & 1 == 0).then_some(seq)
+    }
+
+    /// Tells if no writer touched the value since `read_begin` returned `seq`.
+    #[inline]
+    pub(crate) fn read_validate(&self, seq: usize) -> bool {
+        // orders the reads of the value before the sequence check
+        atomic::fence(Acquire);
+        self.seq.load(Relaxed) == seq
+    }
+
+    /// Records a change, called once the lock of the value has been released.
+    pub(crate) fn bump(&self) {
+        // paired with `watch`: either the writer sees the watcher or the watcher sees
+        // the new count
+        self.count.fetch_add(1, SeqCst);
+
+        #[cfg(feature = "std")]
+        if self.watchers.load(SeqCst) != 0 {
+            parking::unpark_all(self.park_key(), DEFAULT_UNPARK_TOKEN);
+        }
+    }
+
+    /// Registers a watcher, returning the current count.
+    pub(crate) fn watch(&self) -> usize {
+        self.watchers.fetch_add(1, SeqCst);
+        self.get()
+    }
+
+    pub(crate) fn unwatch(&self) {
+        self.watchers.fetch_sub(1, SeqCst);
+    }
+
+    /// Key of the parking lot queue of the watchers.
+    #[cfg(feature = "std")]
+    #[inline]
+    fn park_key(&self) -> usize {
+        (self as *const Self).addr()
+    }
+
+    /// Blocks until the count moves past `seen` or `deadline` is reached, returning `false`
+    /// on timeout. The caller must be registered through `watch`.
+    #[cfg(feature = "std")]
+    pub(crate) fn wait_past(&self, seen: usize, deadline: Option<Instant>) -> bool {
+        let backoff = Backoff::new();
+
+        loop {
+            if self.get() != seen {
+                return true;
+            }
+
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+
+            // checked again with the queue locked, so a `bump` can't be missed
+            let res = parking::park(self.park_key(), || self.get() == seen, |_| {}, deadline);
+
+            if res == ParkResult::TimedOut {
+                return self.get() != seen;
+            }
+        }
+    }
+}
@@ -1,35 +1,108 @@
-use crate::mutex::Mutex;
-use std::fmt::{Debug, Formatter};
-use std::ops::{Deref, DerefMut};
+use crate::mutex::{Mutex, MutexType};
+use core::fmt::{Debug, Formatter};
+use core::ops::{Deref, DerefMut};
 
-/// used as wrapper for a pointer to a reference
+/// Owned data paired with a [`Mutex`], remembering how the lock is held.
+///
+/// The guard starts unlocked or adopts a lock already held, moves between the group and
+/// exclusive modes on request and releases exactly the mode it holds when dropped.
+///
+/// # Example
+/// ```
+/// use castbox::mutex::{Mutex, MutexType, WatchGuard};
+///
+/// let m = Mutex::new();
+/// let mut g = WatchGuard::with_mutex(vec![1, 2], m.clone());
+/// assert_eq!(g.mode(), None);
+///
+/// g.lock_group();
+/// assert!(m.is_locked_group());
+///
+/// g.lock_exclusive();
+/// assert_eq!(g.mode(), Some(MutexType::Exclusive));
+/// g.push(3);
+///
+/// drop(g);
+/// assert!(!m.is_locked());
+/// ```
 #[must_use = "if unused the Mutex will immediately unlock"]
 pub struct WatchGuard<T: Sized> {
     data: T,
     lock: Mutex,
+    mode: Option<MutexType>,
 }
 
 impl<T> WatchGuard<T> {
-    ///create a new WatchGuard from a &mut T and AnyRef
+    /// Creates an unlocked guard with a new `Mutex`.
     pub fn new(data: T) -> WatchGuard<T> {
-        let lock = Mutex::new();
-        Self { data, lock }
+        Self::with_mutex(data, Mutex::new())
     }
 
-    pub fn new_locked(data: T, lock: Mutex) -> WatchGuard<T> {
-        Self { data, lock }
+    /// Creates an unlocked guard sharing `lock`.
+    pub fn with_mutex(data: T, lock: Mutex) -> WatchGuard<T> {
+        Self {
+            data,
+            lock,
+            mode: None,
+        }
     }
 
+    /// Creates a guard adopting `lock`, already held by the caller in `mode`:
+    /// the guard releases it when dropped.
+    pub fn new_locked(data: T, lock: Mutex, mode: MutexType) -> WatchGuard<T> {
+        Self {
+            data,
+            lock,
+            mode: Some(mode),
+        }
+    }
+
+    /// How the guard holds its lock, `None` if unlocked.
+    #[inline]
+    pub fn mode(&self) -> Option<MutexType> {
+        self.mode
+    }
+
+    #[inline]
     pub fn is_locked(&self) -> bool {
-        self.lock.is_locked_exclusive()
+        self.mode.is_some()
     }
 
-    pub fn lock(&self) {
+    /// Takes the lock exclusively, does nothing if the guard already does.
+    ///
+    /// A group lock is released first, so the upgrade is not atomic: another thread may
+    /// take the lock in between.
+    pub fn lock_exclusive(&mut self) {
+        match self.mode {
+            Some(MutexType::Exclusive) => return,
+            Some(MutexType::Group) => self.lock.unlock_group(),
+            None => {}
+        }
         self.lock.lock_exclusive();
+        self.mode = Some(MutexType::Exclusive);
+    }
+
+    /// Takes the lock in group mode, does nothing if the guard already does.
+    ///
+    /// An exclusive lock is released first, so the downgrade is not atomic: another thread
+    /// may take the lock in between.
+    pub fn lock_group(&mut self) {
+        match self.mode {
+            Some(MutexType::Group) => return,
+            Some(MutexType::Exclusive) => self.lock.unlock_exclusive(),
+            None => {}
+        }
+        self.lock.lock_group();
+        self.mode = Some(MutexType::Group);
     }
 
-    pub fn unlock(&self) {
-        self.lock.unlock_exclusive();
+    /// Releases the lock in the mode it is held, does nothing if unlocked.
+    pub fn unlock(&mut self) {
+        match self.mode.take() {
+            Some(MutexType::Exclusive) => self.lock.unlock_exclusive(),
+            Some(MutexType::Group) => self.lock.unlock_group(),
+            None => {}
+        }
     }
 }
 
@@ -53,7 +126,7 @@ impl<T> DerefMut for WatchGuard<T> {
 impl<T> Drop for WatchGuard<T> {
     #[inline]
     fn drop(&mut self) {
-        self.lock.unlock_group();
+        self.unlock();
     }
 }
 
@@ -67,9 +140,10 @@ where
 }
 
 impl<T: Debug> Debug for WatchGuard<T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("WatchGuard")
             .field("data", &self.data)
+            .field("mode", &self.mode)
             .field("lock", &self.lock)
             .finish()
     }
@@ -1,47 +1,239 @@
-use crate::mutex::Mutex;
-use std::fmt::{Debug, Formatter};
-use std::ops::{Deref, DerefMut};
+use crate::mutex::{MappedWatchGuardMut, Mutex, MutexType, Relock, Version};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
 
 /// used as wrapper for a pointer to a reference
 #[must_use = "if unused the Mutex will immediately unlock"]
 pub struct WatchGuardMut<'a, T: ?Sized> {
-    data: &'a mut T,
+    /// a reference is only made while the lock is held, see `unlocked`
+    data: NonNull<T>,
     lock: Mutex,
+    /// tracks the writes to the data, if set
+    version: Option<&'a Version>,
+    /// reports the guard as a change to the watchers of `version` once released
+    changed: bool,
+    _marker: PhantomData<&'a mut T>,
 }
 
 impl<'mutex, T: ?Sized> WatchGuardMut<'mutex, T> {
     ///create a new WatchGuard from a &mut T and AnyRef
     pub fn new(ptr: &'mutex mut T, lock: Mutex) -> WatchGuardMut<'mutex, T> {
-        Self { data: ptr, lock }
+        Self {
+            data: NonNull::from(ptr),
+            lock,
+            version: None,
+            changed: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a guard for the data at `ptr`, guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held exclusively and `ptr` must stay valid for reads and writes for
+    /// `'mutex` while it is held.
+    pub(crate) unsafe fn from_ptr(ptr: *mut T, lock: Mutex) -> WatchGuardMut<'mutex, T> {
+        Self {
+            // SAFETY: the caller hands a valid pointer
+            data: unsafe { NonNull::new_unchecked(ptr) },
+            lock,
+            version: None,
+            changed: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like [`WatchGuardMut::from_ptr`], recording the writes to the data in `version`
+    /// until the guard is released.
+    ///
+    /// # Safety
+    /// `lock` must be held exclusively and `ptr` must stay valid for reads and writes for
+    /// `'mutex` while it is held.
+    pub(crate) unsafe fn versioned(
+        ptr: *mut T,
+        lock: Mutex,
+        version: &'mutex Version,
+    ) -> WatchGuardMut<'mutex, T> {
+        version.begin_write();
+        // SAFETY: as for `from_ptr`
+        let mut guard = unsafe { Self::from_ptr(ptr, lock) };
+        guard.version = Some(version);
+        guard.changed = true;
+        guard
     }
 
     pub fn is_locked(&self) -> bool {
         self.lock.is_locked_exclusive()
     }
+
+    /// Doesn't report the guard as a change when released, for guards that turned out to
+    /// only read the value. See [`Arw::subscribe`](crate::Arw::subscribe).
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    ///
+    /// let a = Arw::new(vec![1]);
+    /// let w = a.subscribe();
+    /// let mut g = a.as_mut();
+    /// if g.contains(&1) {
+    ///     g.mark_unchanged();
+    /// }
+    /// drop(g);
+    /// assert!(!w.has_changed());
+    /// ```
+    #[inline]
+    pub fn mark_unchanged(&mut self) {
+        self.changed = false;
+    }
+
+    /// Releases the lock while running `f`, then takes it exclusively again, also if `f`
+    /// panics. Other threads may change the data in the meantime.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use std::thread;
+    ///
+    /// let a = Arw::new(0);
+    /// let mut g = a.as_mut();
+    /// let a2 = a.clone();
+    /// g.unlocked(|| thread::spawn(move || *a2.as_mut() += 1).join().unwrap());
+    /// *g += 1;
+    /// assert_eq!(*g, 2);
+    /// ```
+    pub fn unlocked<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        if let Some(version) = self.version {
+            version.end_write();
+        }
+        self.lock.unlock_exclusive();
+        let _relock = Relock {
+            lock: &self.lock,
+            mode: MutexType::Exclusive,
+            version: self.version,
+        };
+        f()
+    }
+
+    /// Hands the lock to the threads parked waiting for it, if any, and takes it back once
+    /// they are done. See [`Mutex::bump_exclusive`].
+    #[inline]
+    pub fn bump(&mut self) {
+        if let Some(version) = self.version {
+            version.end_write();
+        }
+        self.lock.bump_exclusive();
+        if let Some(version) = self.version {
+            version.begin_write();
+        }
+    }
+
+    /// Makes a guard for a component of the locked data, keeping the same lock held.
+    ///
+    /// The component may move once the lock is released, so the returned guard can't do
+    /// it. This is an associated function, to not shadow a `map` method of `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::WatchGuardMut;
+    ///
+    /// let a = Arw::new((String::from("limits"), 10u32));
+    /// {
+    ///     let mut limit = WatchGuardMut::map(a.as_mut(), |c| &mut c.1);
+    ///     *limit += 5;
+    /// }
+    /// assert_eq!(a.as_ref().1, 15);
+    /// ```
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedWatchGuardMut<'mutex, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // if `f` panics `this` is still around to release the lock
+        let mut this = this;
+        let data: *mut U = f(&mut this);
+        let (version, changed) = (this.version, this.changed);
+        // SAFETY: the component lives as long as the lock is held, which moves to the new guard
+        unsafe { MappedWatchGuardMut::from_parts(data, Self::take_lock(this), version, changed) }
+    }
+
+    /// Like [`WatchGuardMut::map`], but gives back the original guard if `f` returns `None`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::WatchGuardMut;
+    ///
+    /// let a = Arw::new(vec![1, 2, 3]);
+    /// if let Ok(mut last) = WatchGuardMut::try_map(a.as_mut(), |v| v.last_mut()) {
+    ///     *last = 30;
+    /// }
+    /// assert_eq!(*a.as_ref(), vec![1, 2, 30]);
+    /// ```
+    pub fn try_map<U: ?Sized, F>(this: Self, f: F) -> Result<MappedWatchGuardMut<'mutex, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let mut this = this;
+        let data: *mut U = match f(&mut this) {
+            Some(data) => data,
+            None => return Err(this),
+        };
+        let (version, changed) = (this.version, this.changed);
+        // SAFETY: as for `map`
+        Ok(unsafe {
+            MappedWatchGuardMut::from_parts(data, Self::take_lock(this), version, changed)
+        })
+    }
+
+    /// Moves the lock out of the guard without releasing it.
+    #[inline]
+    fn take_lock(this: Self) -> Mutex {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: `this` is never dropped, so the lock is moved out exactly once
+        unsafe { ptr::read(&this.lock) }
+    }
 }
 
 /// `T` must be `Sync` for a [`WatchGuardMut<T>`] to be `Sync`
 /// because it is possible to get a `&T` from `&WatchGuard` (via `Deref`).
 unsafe impl<T: ?Sized + Sync> Sync for WatchGuardMut<'_, T> {}
+/// Sending the guard hands the `&mut T` to the other thread, like sending a `&mut T`.
+unsafe impl<T: ?Sized + Send> Send for WatchGuardMut<'_, T> {}
 
 impl<T: ?Sized> Deref for WatchGuardMut<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        &*self.data
+        // SAFETY: the exclusive lock is held while the guard can be borrowed
+        unsafe { self.data.as_ref() }
     }
 }
 
 impl<T: ?Sized> DerefMut for WatchGuardMut<'_, T> {
     fn deref_mut(&mut self) -> &mut T {
-        &mut *self.data
+        // SAFETY: the exclusive lock is held while the guard can be borrowed
+        unsafe { self.data.as_mut() }
     }
 }
 
 impl<T: ?Sized> Drop for WatchGuardMut<'_, T> {
     #[inline]
     fn drop(&mut self) {
+        if let Some(version) = self.version {
+            version.end_write();
+        }
         self.lock.unlock_exclusive();
+        match self.version {
+            Some(version) if self.changed => version.bump(),
+            _ => {}
+        }
     }
 }
 
@@ -50,14 +242,14 @@ where
     T: PartialEq<U> + ?Sized,
 {
     fn eq(&self, other: &U) -> bool {
-        self.data == other
+        **self == *other
     }
 }
 
 impl<'a, T: Debug> Debug for WatchGuardMut<'a, T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("WatchGuardRef")
-            .field("data", self.data)
+            .field("data", &&**self)
             .field("lock", &self.lock)
             .finish()
     }
@@ -1,34 +1,145 @@
-use crate::mutex::Mutex;
-use std::fmt::{Debug, Formatter};
-use std::ops::Deref;
+use crate::mutex::{MappedWatchGuardRef, Mutex, MutexType, Relock};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
 
 /// used as wrapper for a pointer to a reference
 #[must_use = "if unused the Mutex will immediately unlock"]
 pub struct WatchGuardRef<'a, T: ?Sized> {
-    data: &'a T,
+    /// a reference is only made while the lock is held, see `unlocked`
+    data: NonNull<T>,
     lock: Mutex,
+    _marker: PhantomData<&'a T>,
 }
 
 impl<'mutex, T: ?Sized> WatchGuardRef<'mutex, T> {
     ///create a new WatchGuard from a &mut T and AnyRef
     pub fn new(ptr: &'mutex T, lock: Mutex) -> WatchGuardRef<'mutex, T> {
-        Self { data: ptr, lock }
+        Self {
+            data: NonNull::from(ptr),
+            lock,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a guard for the data at `ptr`, guarded by `lock`.
+    ///
+    /// # Safety
+    /// `lock` must be held in group mode and `ptr` must stay valid for reads for `'mutex`
+    /// while it is held.
+    pub(crate) unsafe fn from_ptr(ptr: *const T, lock: Mutex) -> WatchGuardRef<'mutex, T> {
+        Self {
+            // SAFETY: the caller hands a valid pointer
+            data: unsafe { NonNull::new_unchecked(ptr as *mut T) },
+            lock,
+            _marker: PhantomData,
+        }
     }
 
     pub fn is_locked(&self) -> bool {
         self.lock.is_locked_exclusive()
     }
+
+    /// Leaves the group while running `f`, then joins it again, also if `f` panics.
+    /// Other threads may change the data in the meantime.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use std::thread;
+    ///
+    /// let a = Arw::new(0);
+    /// let mut g = a.as_ref();
+    /// let a2 = a.clone();
+    /// g.unlocked(|| thread::spawn(move || *a2.as_mut() += 1).join().unwrap());
+    /// assert_eq!(*g, 1);
+    /// ```
+    pub fn unlocked<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.lock.unlock_group();
+        let _relock = Relock {
+            lock: &self.lock,
+            mode: MutexType::Group,
+            version: None,
+        };
+        f()
+    }
+
+    /// Makes a guard for a component of the locked data, keeping the same lock held.
+    ///
+    /// The component may move once the lock is released, so the returned guard can't do
+    /// it. This is an associated function, to not shadow a `map` method of `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::WatchGuardRef;
+    ///
+    /// let a = Arw::new((String::from("limits"), 10u32));
+    /// let limit = WatchGuardRef::map(a.as_ref(), |c| &c.1);
+    /// assert_eq!(*limit, 10);
+    /// ```
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedWatchGuardRef<'mutex, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        // if `f` panics `this` is still around to release the lock
+        let data: *const U = f(&this);
+        // SAFETY: the component lives as long as the lock is held, which moves to the new guard
+        unsafe { MappedWatchGuardRef::from_ptr(data, Self::take_lock(this)) }
+    }
+
+    /// Like [`WatchGuardRef::map`], but gives back the original guard if `f` returns `None`.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::Arw;
+    /// use castbox::mutex::WatchGuardRef;
+    ///
+    /// let a = Arw::new(vec![1, 2, 3]);
+    /// let first = WatchGuardRef::try_map(a.as_ref(), |v| v.first()).unwrap();
+    /// assert_eq!(*first, 1);
+    /// drop(first);
+    ///
+    /// let whole = WatchGuardRef::try_map(a.as_ref(), |v| v.get(5)).unwrap_err();
+    /// assert_eq!(whole.len(), 3);
+    /// ```
+    pub fn try_map<U: ?Sized, F>(this: Self, f: F) -> Result<MappedWatchGuardRef<'mutex, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(&this).map(|data| data as *const U) {
+            // SAFETY: as for `map`
+            Some(data) => Ok(unsafe { MappedWatchGuardRef::from_ptr(data, Self::take_lock(this)) }),
+            None => Err(this),
+        }
+    }
+
+    /// Moves the lock out of the guard without releasing it.
+    #[inline]
+    fn take_lock(this: Self) -> Mutex {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: `this` is never dropped, so the lock is moved out exactly once
+        unsafe { ptr::read(&this.lock) }
+    }
 }
 
 /// `T` must be `Sync` for a [`WatchGuard<T>`] to be `Sync`
 /// because it is possible to get a `&T` from `&WatchGuard` (via `Deref`).
 unsafe impl<T: ?Sized + Sync> Sync for WatchGuardRef<'_, T> {}
+/// Sending the guard shares the `&T` with the other thread, like sending a `&T`.
+unsafe impl<T: ?Sized + Sync> Send for WatchGuardRef<'_, T> {}
 
 impl<T: ?Sized> Deref for WatchGuardRef<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        &*self.data
+        // SAFETY: the group lock is held while the guard can be borrowed
+        unsafe { self.data.as_ref() }
     }
 }
 
@@ -44,14 +155,14 @@ where
     T: PartialEq<U> + ?Sized,
 {
     fn eq(&self, other: &U) -> bool {
-        self.data == other
+        **self == *other
     }
 }
 
 impl<'a, T: Debug> Debug for WatchGuardRef<'a, T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("WatchGuardRef")
-            .field("data", self.data)
+            .field("data", &&**self)
             .field("lock", &self.lock)
             .finish()
     }
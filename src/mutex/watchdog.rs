@@ -0,0 +1,335 @@
+use crate::mutex::{Mutex, MutexState, MutexType};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::time::Duration;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Instant;
+
+/// Set while a [`Watchdog`] is running, parked threads and holders are only tracked then.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static REGISTRY: StdMutex<Registry> = StdMutex::new(Registry {
+    next_ticket: 0,
+    parked: Vec::new(),
+});
+
+/// Number of shards of the holders, see [`holder_shard`].
+const HOLDER_SHARDS: usize = 16;
+
+/// The holders of the locks, sharded by lock: they are updated on every lock and unlock
+/// while a watchdog runs, so unrelated locks take different `std` mutexes.
+static HOLDERS: [StdMutex<Vec<Holder>>; HOLDER_SHARDS] =
+    [const { StdMutex::new(Vec::new()) }; HOLDER_SHARDS];
+
+struct Registry {
+    next_ticket: u64,
+    parked: Vec<Parked>,
+}
+
+/// A thread parked in `Mutex::suspend`.
+struct Parked {
+    ticket: u64,
+    /// the handle of the parked thread, alive until its entry is removed
+    lock: *const Mutex,
+    mode: MutexType,
+    thread: Thread,
+    since: Instant,
+    reported: bool,
+}
+
+struct Holder {
+    lock: usize,
+    mode: MutexType,
+    thread: Thread,
+}
+
+// SAFETY: `lock` is only dereferenced under the registry lock, while the parked thread,
+// which owns the handle, is blocked in `unparked` waiting for the same lock.
+unsafe impl Send for Parked {}
+
+#[inline]
+fn registry() -> StdMutexGuard<'static, Registry> {
+    // a panicking callback never runs under the registry lock, so poisoning is harmless
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The shard of the holders of `lock`. Taken after the registry when both are needed.
+#[inline]
+fn holder_shard(lock: usize) -> StdMutexGuard<'static, Vec<Holder>> {
+    // the low bits are the same for all the allocations
+    let shard = &HOLDERS[(lock >> 4) % HOLDER_SHARDS];
+    shard.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn clear_holders() {
+    for shard in &HOLDERS {
+        shard.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[inline]
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Relaxed)
+}
+
+/// Records that the current thread is about to park on `lock`, returns the ticket to
+/// hand to [`unparked`].
+pub(crate) fn parked(lock: &Mutex, mode: MutexType) -> u64 {
+    let mut reg = registry();
+    let ticket = reg.next_ticket;
+    reg.next_ticket += 1;
+    reg.parked.push(Parked {
+        ticket,
+        lock,
+        mode,
+        thread: thread::current(),
+        since: Instant::now(),
+        reported: false,
+    });
+    ticket
+}
+
+pub(crate) fn unparked(ticket: u64) {
+    let mut reg = registry();
+    if let Some(pos) = reg.parked.iter().position(|p| p.ticket == ticket) {
+        reg.parked.swap_remove(pos);
+    }
+}
+
+/// Records that the current thread now holds `lock` in `mode`.
+pub(crate) fn acquired(lock: usize, mode: MutexType) {
+    holder_shard(lock).push(Holder {
+        lock,
+        mode,
+        thread: thread::current(),
+    });
+}
+
+/// Forgets a holder of `lock` in `mode`, preferring the current thread: group locks
+/// may be released by another thread than the one that took them.
+pub(crate) fn released(lock: usize, mode: MutexType) {
+    let mut holders = holder_shard(lock);
+    let current = thread::current().id();
+    let pos = holders
+        .iter()
+        .position(|h| h.lock == lock && h.mode == mode && h.thread.id() == current)
+        .or_else(|| {
+            holders
+                .iter()
+                .position(|h| h.lock == lock && h.mode == mode)
+        });
+    if let Some(pos) = pos {
+        holders.swap_remove(pos);
+    }
+}
+
+/// Forgets all the holders of `lock` in `mode`.
+pub(crate) fn released_all(lock: usize, mode: MutexType) {
+    holder_shard(lock).retain(|h| !(h.lock == lock && h.mode == mode));
+}
+
+/// A thread that has been parked on a [`Mutex`] for longer than the watchdog threshold.
+pub struct StallReport {
+    /// The lock the thread is waiting for, [`Mutex::id`] identifies it.
+    pub mutex: Mutex,
+    /// The kind of lock the thread is waiting for.
+    pub mode: MutexType,
+    /// The waiting thread.
+    pub thread: Thread,
+    /// How long the thread has been parked.
+    pub waited: Duration,
+    /// The threads holding the lock and how, as far as the watchdog has seen them:
+    /// locks taken before the watchdog was started are not tracked.
+    pub holders: Vec<(Thread, MutexType)>,
+    /// The state of the lock when the stall was detected, see [`Mutex::snapshot`].
+    pub state: MutexState,
+}
+
+impl fmt::Debug for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let holders: Vec<_> = self
+            .holders
+            .iter()
+            .map(|(t, m)| (t.name().unwrap_or("<unnamed>"), t.id(), *m))
+            .collect();
+        f.debug_struct("StallReport")
+            .field("lock", &self.mutex.id())
+            .field("mode", &self.mode)
+            .field(
+                "thread",
+                &(self.thread.name().unwrap_or("<unnamed>"), self.thread.id()),
+            )
+            .field("waited", &self.waited)
+            .field("holders", &holders)
+            .field("state", &self.state)
+            .field("mutex", &self.mutex)
+            .finish()
+    }
+}
+
+struct WatchdogShared {
+    stop: AtomicBool,
+}
+
+/// A background thread reporting the threads parked on a [`Mutex`] for too long.
+///
+/// Tracking parked threads and lock holders has a cost, so it only happens while a
+/// `Watchdog` is running: every lock and unlock of any `Mutex` then records its holder
+/// under one of a few `std` mutexes picked by the lock, and every park registers the
+/// waiter under a global one. Locks with many holders, like large groups, make the
+/// unlocks slower too, as their holders are searched linearly. Every stalled wait is
+/// reported once, dropping the `Watchdog` stops the thread and the tracking.
+///
+/// # Example
+/// ```
+/// use castbox::mutex::{Mutex, Watchdog};
+/// use std::sync::mpsc;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let watchdog = Watchdog::start(Duration::from_millis(20), move |report| {
+///     let _ = tx.send(report.mutex.id());
+/// });
+///
+/// let m = Mutex::new();
+/// m.lock_exclusive();
+/// let m2 = m.clone();
+/// let h = thread::spawn(move || {
+///     m2.lock_exclusive();
+///     m2.unlock_exclusive();
+/// });
+///
+/// assert_eq!(rx.recv().unwrap(), m.id());
+/// m.unlock_exclusive();
+/// h.join().unwrap();
+/// drop(watchdog);
+/// ```
+pub struct Watchdog {
+    shared: Arc<WatchdogShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Starts the watchdog thread, calling `callback` for every thread parked on a
+    /// `Mutex` for longer than `threshold`.
+    ///
+    /// # Panics
+    /// Panics if another `Watchdog` is running.
+    pub fn start<F>(threshold: Duration, callback: F) -> Self
+    where
+        F: Fn(&StallReport) + Send + 'static,
+    {
+        if ENABLED
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .is_err()
+        {
+            panic!("A Watchdog is already running");
+        }
+        // holders recorded while the previous watchdog was stopping may be stale
+        clear_holders();
+
+        let shared = Arc::new(WatchdogShared {
+            stop: AtomicBool::new(false),
+        });
+        let callback: Box<dyn Fn(&StallReport) + Send> = Box::new(callback);
+        let period = (threshold / 2).clamp(Duration::from_millis(1), Duration::from_secs(1));
+
+        let s = shared.clone();
+        let handle = thread::Builder::new()
+            .name("castbox-watchdog".into())
+            .spawn(move || {
+                while !s.stop.load(Acquire) {
+                    for report in collect(threshold) {
+                        callback(&report);
+                    }
+                    thread::park_timeout(period);
+                }
+            })
+            .expect("failed to spawn the watchdog thread");
+
+        Self {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    #[inline]
+    pub fn is_running() -> bool {
+        is_enabled()
+    }
+}
+
+/// Builds the reports of the waits longer than `threshold` not reported yet.
+fn collect(threshold: Duration) -> Vec<StallReport> {
+    let now = Instant::now();
+    let mut stalled = Vec::new();
+    {
+        let mut reg = registry();
+        for p in reg.parked.iter_mut() {
+            let waited = now.saturating_duration_since(p.since);
+            if p.reported || waited < threshold {
+                continue;
+            }
+            p.reported = true;
+
+            // SAFETY: the parked thread is blocked until it removes its entry, see `Parked`
+            let mutex = unsafe { (*p.lock).clone() };
+            stalled.push((mutex, p.mode, p.thread.clone(), waited));
+        }
+    }
+
+    // the clones keep the mutexes alive once the registry is released, the holders and
+    // the snapshot, which takes parking lot bucket locks, are read without nesting under it
+    stalled
+        .into_iter()
+        .map(|(mutex, mode, thread, waited)| {
+            let holders = holder_shard(mutex.id())
+                .iter()
+                .filter(|h| h.lock == mutex.id())
+                .map(|h| (h.thread.clone(), h.mode))
+                .collect();
+            let state = mutex.snapshot();
+            StallReport {
+                mutex,
+                mode,
+                thread,
+                waited,
+                holders,
+                state,
+            }
+        })
+        .collect()
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+
+        // parked threads remove their own entries when they wake up
+        ENABLED.store(false, Release);
+        clear_holders();
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parked = registry().parked.len();
+        let holders: usize = HOLDERS
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .sum();
+        f.debug_struct("Watchdog")
+            .field("parked", &parked)
+            .field("holders", &holders)
+            .finish()
+    }
+}
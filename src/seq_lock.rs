@@ -0,0 +1,173 @@
+use crate::mutex::{Backoff, Mutex};
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::panic::{RefUnwindSafe, UnwindSafe};
+use core::sync::atomic;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::{fmt, ptr};
+
+struct SeqLockInner<T: Copy> {
+    /// even: stable, odd: a writer is updating the value
+    seq: AtomicUsize,
+    lock: Mutex,
+    ref_count: AtomicUsize,
+    val: UnsafeCell<T>,
+}
+
+/// A reference counted sequence lock for small `Copy` values that are read often and
+/// written rarely.
+///
+/// Readers never write to shared memory: they copy the value out and retry if a writer
+/// touched it in the meantime. Writers are serialised through an internal [`Mutex`].
+///
+/// # Example
+/// ```
+/// use castbox::SeqLock;
+/// let limits = SeqLock::new((100u32, 10u32));
+/// let l = limits.clone();
+/// l.write(|v| v.0 = 200);
+/// assert_eq!(limits.read(), (200, 10));
+/// ```
+#[repr(transparent)]
+pub struct SeqLock<T: Copy> {
+    ptr: *const SeqLockInner<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> UnwindSafe for SeqLock<T> {}
+impl<T: Copy> RefUnwindSafe for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        let ptr = Box::into_raw(Box::new(SeqLockInner {
+            seq: AtomicUsize::new(0),
+            lock: Mutex::new(),
+            ref_count: AtomicUsize::new(1),
+            val: UnsafeCell::new(value),
+        }));
+        Self { ptr }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &SeqLockInner<T> {
+        unsafe { &*self.ptr }
+    }
+
+    /// Returns a copy of the current value, retrying while a writer is active.
+    pub fn read(&self) -> T {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(val) = self.try_read() {
+                return val;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Makes a single attempt to read the value.
+    /// Returns `None` if a writer was active during the read.
+    ///
+    /// # Example
+    /// ```
+    /// use castbox::SeqLock;
+    /// let s = SeqLock::new(7u64);
+    /// assert_eq!(s.try_read(), Some(7));
+    /// ```
+    pub fn try_read(&self) -> Option<T> {
+        let inner = self.inner();
+        let seq = inner.seq.load(Acquire);
+        if seq & 1 == 1 {
+            return None;
+        }
+
+        // SAFETY: read as `MaybeUninit`, the bytes may be torn by a concurrent writer and
+        // need not be a valid `T`: they are only assumed so once the sequence validates
+        let val = unsafe { ptr::read_volatile(inner.val.get().cast::<MaybeUninit<T>>()) };
+
+        atomic::fence(Acquire);
+        if inner.seq.load(Relaxed) == seq {
+            // SAFETY: no writer touched the value while it was copied
+            Some(unsafe { val.assume_init() })
+        } else {
+            None
+        }
+    }
+
+    /// Updates the value under the writer lock, readers will observe either the old
+    /// or the new value.
+    pub fn write<R, F>(&self, func: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        /// Closes the sequence if open and releases the writer lock, also if `func` panics.
+        struct Unlock<'a, T: Copy>(&'a SeqLockInner<T>);
+
+        impl<T: Copy> Drop for Unlock<'_, T> {
+            fn drop(&mut self) {
+                let seq = self.0.seq.load(Relaxed);
+                if seq & 1 == 1 {
+                    self.0.seq.store(seq.wrapping_add(1), Release);
+                }
+                self.0.lock.unlock_exclusive();
+            }
+        }
+
+        let inner = self.inner();
+        inner.lock.lock_exclusive();
+        let _unlock = Unlock(inner);
+
+        // work on a copy so that readers never see the closure intermediate states
+        let mut val = unsafe { ptr::read(inner.val.get()) };
+        let res = func(&mut val);
+
+        let seq = inner.seq.load(Relaxed);
+        inner.seq.store(seq.wrapping_add(1), Relaxed);
+        atomic::fence(Release);
+
+        unsafe { ptr::write_volatile(inner.val.get(), val) };
+        res
+    }
+
+    /// Replaces the value.
+    #[inline]
+    pub fn set(&self, value: T) {
+        self.write(|v| *v = value);
+    }
+}
+
+impl<T: Copy> Clone for SeqLock<T> {
+    fn clone(&self) -> Self {
+        self.inner().ref_count.fetch_add(1, Relaxed);
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T: Copy> Drop for SeqLock<T> {
+    fn drop(&mut self) {
+        if self.inner().ref_count.fetch_sub(1, Release) == 1 {
+            atomic::fence(Acquire);
+
+            let ptr = self.ptr as *mut SeqLockInner<T>;
+            unsafe { drop(Box::from_raw(ptr)) };
+        }
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeqLock")
+            .field("val", &self.read())
+            .field("seq", &self.inner().seq.load(Relaxed))
+            .finish()
+    }
+}
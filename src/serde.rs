@@ -0,0 +1,127 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! [`Arw`](crate::Arw), [`WeakArw`](crate::WeakArw),
+//! [`AtomicVec`](crate::collections::AtomicVec) and
+//! [`AtomicHashMap`](crate::collections::AtomicHashMap) serialize their values, taking the
+//! locks needed for a consistent snapshot. An `Arw` is written as its value, so handles
+//! sharing an allocation are read back as separate ones: fields using [`shared`] keep the
+//! sharing when serialized and deserialized inside [`with_sharing`].
+//!
+//! The impls are available with `serde` alone, also without `std`. This module tracks the
+//! sharing per thread, so it is only built when `std` is enabled too.
+//!
+//! # Example
+//! ```
+//! use castbox::Arw;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct State {
+//!     #[serde(with = "castbox::serde::shared")]
+//!     config: Arw<String>,
+//!     #[serde(with = "castbox::serde::shared")]
+//!     fallback: Arw<String>,
+//! }
+//!
+//! let config = Arw::new(String::from("fast"));
+//! let state = State { config: config.clone(), fallback: config };
+//!
+//! let json = castbox::serde::with_sharing(|| serde_json::to_string(&state)).unwrap();
+//! let back: State = castbox::serde::with_sharing(|| serde_json::from_str(&json)).unwrap();
+//! assert!(Arw::ptr_eq(&back.config, &back.fallback));
+//! ```
+
+use alloc::boxed::Box;
+use core::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+struct Sharing {
+    /// allocations already written in full, by address
+    written: HashSet<usize>,
+    /// `Arw`s already read, by the address they were written with
+    read: HashMap<u64, Box<dyn Any>>,
+}
+
+std::thread_local! {
+    static SHARING: RefCell<Option<Sharing>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, serializing or deserializing, with the sharing of the [`shared`] fields
+/// tracked: the first handle to an allocation writes the value, the others only refer to
+/// it, and reading them back gives clones of the same `Arw`.
+///
+/// The tracking is per thread and covers the whole of `f`, a nested call starts afresh.
+pub fn with_sharing<R, F: FnOnce() -> R>(f: F) -> R {
+    /// Puts the outer tracking back, also if `f` panics.
+    struct Restore(Option<Sharing>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SHARING.with(|sharing| *sharing.borrow_mut() = self.0.take());
+        }
+    }
+
+    let outer = SHARING.with(|sharing| sharing.replace(Some(Sharing::default())));
+    let _restore = Restore(outer);
+    f()
+}
+
+/// Serializes an `Arw` as its address and, unless already written in the current
+/// [`with_sharing`] call, its value. Use it with `#[serde(with = "castbox::serde::shared")]`.
+///
+/// Outside of `with_sharing` every handle writes the value and is read as a new `Arw`.
+pub mod shared {
+    use super::SHARING;
+    use crate::Arw;
+    use alloc::boxed::Box;
+    use core::any::Any;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(arw: &Arw<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        let addr = Arw::addr(arw);
+        let first = SHARING.with(|sharing| match sharing.borrow_mut().as_mut() {
+            Some(sharing) => sharing.written.insert(addr),
+            None => true,
+        });
+
+        if first {
+            arw.with(|value| (addr as u64, Some(value)).serialize(serializer))
+        } else {
+            (addr as u64, None::<&T>).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Arw<T>, D::Error>
+    where
+        T: Any + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let (addr, value) = <(u64, Option<T>)>::deserialize(deserializer)?;
+
+        match value {
+            Some(value) => {
+                let arw = Arw::new(value);
+                SHARING.with(|sharing| {
+                    if let Some(sharing) = sharing.borrow_mut().as_mut() {
+                        sharing.read.insert(addr, Box::new(arw.clone()));
+                    }
+                });
+                Ok(arw)
+            }
+            None => SHARING
+                .with(|sharing| {
+                    let sharing = sharing.borrow();
+                    let arw = sharing.as_ref()?.read.get(&addr)?;
+                    arw.downcast_ref::<Arw<T>>().cloned()
+                })
+                .ok_or_else(|| D::Error::custom("reference to an Arw not read before")),
+        }
+    }
+}
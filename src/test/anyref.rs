@@ -1,5 +1,5 @@
 mod tests_any_ref {
-    use crate::mutex::WatchGuardRef;
+    use crate::mutex::MappedWatchGuardRef;
     use crate::{AnyRef, WeakAnyRef};
     use std::any::TypeId;
     use std::sync::Barrier;
@@ -7,6 +7,22 @@ mod tests_any_ref {
     use std::sync::atomic::Ordering::{Acquire, Relaxed};
     use std::thread;
 
+    #[test]
+    fn downcast_guard_holds_off_replace() {
+        let a = AnyRef::new(String::from("text"));
+        let s = a.try_downcast_ref::<String>().unwrap();
+
+        let a2 = a.clone();
+        let h = thread::spawn(move || drop(a2.replace(vec![0u8; 64])));
+        thread::sleep(std::time::Duration::from_millis(20));
+        // the boxed value would be freed by `replace`, which waits for the guard
+        assert!(!h.is_finished());
+        assert_eq!(*s, "text");
+        drop(s);
+        h.join().unwrap();
+        assert_eq!(a.as_ref::<Vec<u8>>().len(), 64);
+    }
+
     #[test]
     fn stress_test() {
         let a = AnyRef::new("hello".to_string());
@@ -59,7 +75,7 @@ mod tests_any_ref {
     fn test_map() {
         let x = AnyRef::new(5i32);
         assert_eq!(
-            *x.map(|x: WatchGuardRef<'_, i32>| (*x * 2) as u64)
+            *x.map(|x: MappedWatchGuardRef<'_, i32>| (*x * 2) as u64)
                 .as_ref::<u64>(),
             10u64
         );
@@ -68,13 +84,13 @@ mod tests_any_ref {
     #[test]
     fn new_and_type() {
         let x = AnyRef::new(42u32);
-        assert_eq!(x.inner().type_id, TypeId::of::<u32>());
+        assert_eq!(x.inner().type_id(), TypeId::of::<u32>());
         assert_eq!(
-            AnyRef::new(String::new()).inner().type_id,
+            AnyRef::new(String::new()).inner().type_id(),
             TypeId::of::<String>()
         );
         assert_eq!(
-            AnyRef::new(Box::new(String::new())).inner().type_id,
+            AnyRef::new(Box::new(String::new())).inner().type_id(),
             TypeId::of::<Box<String>>()
         );
     }
@@ -334,4 +350,128 @@ mod tests_any_ref {
             4
         );
     }
+
+    #[test]
+    fn test_owned_downcast() {
+        let a = AnyRef::new(String::from("any"));
+        assert!(a.downcast_owned::<u32>().is_none());
+        assert!(a.downcast_mut_owned::<u32>().is_none());
+        assert!(!a.is_locked());
+
+        let mut g = a.downcast_mut_owned::<String>().unwrap();
+        drop(a);
+        let t = thread::spawn(move || {
+            g.push('!');
+            g
+        });
+        let g = t.join().unwrap();
+        assert_eq!(*g, "any!");
+        assert!(g.is_locked());
+    }
+
+    #[test]
+    fn test_try_lock_downcast() {
+        use crate::mutex::LockError;
+
+        let a = AnyRef::new(5i32);
+        assert_eq!(
+            a.try_lock_downcast_mut::<u32>().unwrap_err(),
+            LockError::TypeMismatch
+        );
+
+        let r = a.try_lock_downcast_ref::<i32>().unwrap();
+        assert_eq!(
+            a.try_lock_downcast_mut::<i32>().unwrap_err(),
+            LockError::WouldBlock
+        );
+        drop(r);
+
+        let mut w = a.try_lock_downcast_mut::<i32>().unwrap();
+        *w += 1;
+        assert_eq!(
+            a.try_lock_downcast_ref::<i32>().unwrap_err(),
+            LockError::WouldBlock
+        );
+        // a type mismatch is reported without looking at the lock
+        assert_eq!(
+            a.try_lock_downcast_ref::<u8>().unwrap_err(),
+            LockError::TypeMismatch
+        );
+        drop(w);
+        assert_eq!(a.as_ref::<i32>(), 6);
+    }
+
+    #[test]
+    fn test_new_cyclic() {
+        struct Node {
+            parent: WeakAnyRef,
+            name: String,
+        }
+
+        let a = AnyRef::new_cyclic(|w| {
+            assert!(w.upgrade().is_none());
+            Node {
+                parent: w.clone(),
+                name: "root".to_string(),
+            }
+        });
+        assert_eq!(a.type_name(), std::any::type_name::<Node>());
+        assert!(a.try_downcast_ref::<i32>().is_none());
+
+        let me = a.as_ref::<Node>().parent.upgrade().unwrap();
+        assert!(AnyRef::ptr_eq(&me, &a));
+        assert_eq!(me.as_ref::<Node>().name, "root");
+        assert_eq!(AnyRef::strong_count(&a), 2);
+
+        let w = a.downgrade();
+        drop(me);
+        drop(a);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_replace() {
+        let a = AnyRef::new(0u32);
+        let old = a.replace(String::from("text"));
+        assert_eq!(*old.downcast::<u32>().unwrap(), 0);
+        assert_eq!(a.type_name(), std::any::type_name::<String>());
+        assert_eq!(a.as_ref::<String>(), "text");
+
+        let a = AnyRef::fill(a, 4u64);
+        assert_eq!(a.as_ref::<u64>(), 4);
+
+        // readers see either type with a matching value, never a mix
+        let writer = {
+            let a = a.clone();
+            thread::spawn(move || {
+                for i in 0..2000u64 {
+                    if i % 2 == 0 {
+                        a.replace(i);
+                    } else {
+                        a.replace(i.to_string());
+                    }
+                }
+            })
+        };
+        for _ in 0..2000 {
+            if let Some(n) = a.try_downcast_ref::<u64>() {
+                assert_eq!(*n % 2, 0);
+            } else if let Some(s) = a.try_downcast_ref::<String>() {
+                assert_eq!(s.parse::<u64>().unwrap() % 2, 1);
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(a.as_ref::<String>(), "1999");
+    }
+
+    #[test]
+    fn test_scoped_access() {
+        let a = AnyRef::new(vec![1u8]);
+        assert_eq!(a.with::<Vec<u8>, _>(|v| v.len()), Some(1));
+        assert_eq!(a.with::<String, _>(|_| unreachable!()), None);
+        assert_eq!(a.with_mut::<Vec<u8>, _>(|v| v.push(2)), Some(()));
+        assert_eq!(a.with_mut::<u8, _>(|_| unreachable!()), None);
+        assert!(!a.is_locked());
+        assert_eq!(*a.as_ref::<Vec<u8>>(), vec![1, 2]);
+    }
 }
@@ -1,5 +1,5 @@
 mod tests_arw {
-    use crate::mutex::WatchGuardRef;
+    use crate::mutex::{MappedWatchGuardMut, MappedWatchGuardRef, WatchGuardMut, WatchGuardRef};
     use crate::{Arw, WeakArw};
     use std::sync::atomic::AtomicU8;
     use std::sync::atomic::Ordering::{Acquire, Relaxed};
@@ -57,6 +57,146 @@ mod tests_arw {
         );
     }
 
+    #[test]
+    fn test_mapped_guards() {
+        let x = Arw::new((String::from("name"), vec![1u32, 2, 3]));
+
+        let name = WatchGuardRef::map(x.as_ref(), |t| t.0.as_str());
+        assert_eq!(&*name, "name");
+        // still a group lock, other readers can join
+        assert_eq!(x.as_ref().1.len(), 3);
+        drop(name);
+
+        {
+            let mut items = WatchGuardMut::map(x.as_mut(), |t| &mut t.1);
+            assert!(x.is_locked());
+            items.push(4);
+        }
+        assert!(!x.is_locked());
+
+        let guard = WatchGuardMut::try_map(x.as_mut(), |t| t.1.get_mut(10)).unwrap_err();
+        assert!(x.is_locked());
+        drop(guard);
+        assert!(!x.is_locked());
+
+        let last = WatchGuardMut::try_map(x.as_mut(), |t| t.1.last_mut());
+        *last.unwrap() *= 10;
+        assert!(!x.is_locked());
+        assert_eq!(x.as_ref().1, vec![1, 2, 3, 40]);
+    }
+
+    #[test]
+    fn test_mapped_guards_map_further() {
+        let x = Arw::new((String::from("name"), vec![1u32, 2, 3]));
+        let w = x.subscribe();
+
+        let items = WatchGuardRef::map(x.as_ref(), |t| &t.1);
+        let second = MappedWatchGuardRef::map(items, |v| &v[1]);
+        assert_eq!(*second, 2);
+        let missing = MappedWatchGuardRef::try_map(second, |_| None::<&u32>).unwrap_err();
+        assert_eq!(*missing, 2);
+        drop(missing);
+        assert!(x.try_write().is_ok());
+
+        {
+            let items = WatchGuardMut::map(x.as_mut(), |t| &mut t.1);
+            let mut last = MappedWatchGuardMut::try_map(items, |v| v.last_mut()).unwrap();
+            *last = 30;
+            assert!(x.is_locked());
+        }
+        assert!(!x.is_locked());
+        assert_eq!(x.as_ref().1, vec![1, 2, 30]);
+        // the write through the mapped guards reaches the watchers
+        assert!(w.has_changed());
+    }
+
+    #[test]
+    fn test_mapped_guard_panic_releases_lock() {
+        let x = Arw::new(vec![1u32]);
+        let x2 = x.clone();
+        let res = std::panic::catch_unwind(move || {
+            let _g = WatchGuardMut::map(x2.as_mut(), |v| &mut v[5]);
+        });
+        assert!(res.is_err());
+        assert!(!x.is_locked());
+        assert_eq!(*x.as_ref(), vec![1]);
+    }
+
+    #[test]
+    fn test_mapped_guard_holds_off_writers() {
+        let x = Arw::new(vec![1u32, 2, 3]);
+        let first = WatchGuardRef::map(x.as_ref(), |v| &v[0]);
+
+        let x2 = x.clone();
+        let h = thread::spawn(move || x2.as_mut().clear());
+        thread::sleep(std::time::Duration::from_millis(20));
+        // the component would be freed by the writer, which waits for the guard
+        assert!(!h.is_finished());
+        assert_eq!(*first, 1);
+        drop(first);
+        h.join().unwrap();
+
+        // the whole value stays in the allocation, so its guard may let the writers in
+        let mut g = x.as_ref();
+        g.unlocked(|| x.as_mut().push(5));
+        assert_eq!(*g, vec![5]);
+    }
+
+    #[test]
+    fn test_guard_unlocked() {
+        let x = Arw::new(vec![1u32]);
+
+        let mut g = x.as_mut();
+        let x2 = x.clone();
+        let len = g.unlocked(|| {
+            // the lock is free while the closure runs
+            thread::spawn(move || x2.as_mut().push(2)).join().unwrap();
+            x.as_ref().len()
+        });
+        assert_eq!(len, 2);
+        assert!(x.is_locked());
+        g.push(3);
+        drop(g);
+
+        let mut g = x.as_ref();
+        g.unlocked(|| x.as_mut().push(4));
+        assert_eq!(*g, vec![1, 2, 3, 4]);
+        drop(g);
+        assert!(!x.is_locked());
+    }
+
+    #[test]
+    fn test_guard_unlocked_relocks_on_panic() {
+        let x = Arw::new(0);
+        let mut g = x.as_mut();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            g.unlocked(|| panic!("boom"));
+        }));
+        assert!(res.is_err());
+        assert!(x.is_locked());
+        *g += 1;
+        drop(g);
+        assert!(!x.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_guard_bump_lets_waiters_in() {
+        let x = Arw::new(0);
+        let mut g = x.as_mut();
+
+        let x2 = x.clone();
+        let t = thread::spawn(move || *x2.as_mut() += 1);
+        // the waiter parks after spinning a while, until then there is nobody to hand over to
+        while *g == 0 {
+            g.bump();
+            hint::spin_loop();
+        }
+        assert_eq!(*g, 1);
+        drop(g);
+        t.join().unwrap();
+    }
+
     #[test]
     fn test_strong_weak_counts() {
         let x = Arw::new("hello");
@@ -267,4 +407,575 @@ mod tests_arw {
         let _y = Arw::clone(&x);
         assert_eq!(*Arw::try_unwrap(x).unwrap_err().as_ref(), 4);
     }
+
+    #[test]
+    fn test_owned_guards() {
+        fn make() -> crate::mutex::OwnedWatchGuardMut<Arw<Vec<u32>>, Vec<u32>> {
+            Arw::new(vec![1]).write_owned()
+        }
+
+        // the guard keeps the value alive after the handle is gone
+        let mut g = make();
+        g.push(2);
+        assert!(g.is_locked());
+        assert_eq!(*g, vec![1, 2]);
+        drop(g);
+
+        let x = Arw::new(0u32);
+        let mut w = x.write_owned();
+        let t = thread::spawn(move || *w += 1);
+        t.join().unwrap();
+        assert!(!x.is_locked());
+
+        let r = x.read_owned();
+        let r2 = x.read_owned();
+        assert_eq!(Arw::strong_count(&x), 3);
+        let t = thread::spawn(move || *r2);
+        assert_eq!(t.join().unwrap(), 1);
+        assert_eq!(*r, 1);
+        drop(r);
+        assert!(!x.is_locked());
+        assert_eq!(Arw::strong_count(&x), 1);
+    }
+
+    #[test]
+    fn test_try_access() {
+        use crate::mutex::LockError;
+
+        let x = Arw::new(1);
+
+        let r = x.try_read().unwrap();
+        assert_eq!(*x.try_read().unwrap(), 1);
+        assert_eq!(x.try_write().unwrap_err(), LockError::WouldBlock);
+        drop(r);
+
+        let mut w = x.try_write().unwrap();
+        *w += 1;
+        assert_eq!(x.try_read().unwrap_err(), LockError::WouldBlock);
+        drop(w);
+        assert_eq!(*x.try_read().unwrap(), 2);
+        assert!(!x.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_timed_access() {
+        use crate::mutex::LockError;
+        use std::time::Duration;
+
+        let x = Arw::new(1);
+
+        let r = x.try_read().unwrap();
+        assert_eq!(
+            x.write_timeout(Duration::from_millis(10)).unwrap_err(),
+            LockError::TimedOut
+        );
+        drop(r);
+
+        let mut w = x.try_write().unwrap();
+        *w += 1;
+        assert_eq!(
+            x.read_timeout(Duration::from_millis(10)).unwrap_err(),
+            LockError::TimedOut
+        );
+
+        let x2 = x.clone();
+        let t = thread::spawn(move || *x2.read_timeout(Duration::from_secs(10)).unwrap());
+        thread::sleep(Duration::from_millis(10));
+        drop(w);
+        assert_eq!(t.join().unwrap(), 2);
+        assert!(!x.is_locked());
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut x = Arw::new(String::from("a"));
+        x.get_mut().unwrap().push('b');
+
+        let w = x.downgrade();
+        assert!(x.get_mut().is_none());
+        drop(w);
+
+        let g = x.read_owned();
+        assert!(x.get_mut().is_none());
+        drop(g);
+
+        x.get_mut().unwrap().push('c');
+        assert!(!x.is_locked());
+        assert_eq!(*x.as_ref(), "abc");
+    }
+
+    #[test]
+    fn test_make_mut() {
+        let mut x = Arw::new(vec![1]);
+        let addr = |x: &Arw<Vec<i32>>| &*x.as_ref() as *const Vec<i32>;
+        let ptr = addr(&x);
+        // unique, no clone
+        x.make_mut().push(2);
+        assert_eq!(addr(&x), ptr);
+
+        let y = x.clone();
+        let w = x.downgrade();
+        x.make_mut().push(3);
+        assert!(!Arw::ptr_eq(&x, &y));
+        assert_eq!(*x.as_ref(), vec![1, 2, 3]);
+        assert_eq!(*y.as_ref(), vec![1, 2]);
+        assert_eq!(Arw::strong_count(&x), 1);
+        assert_eq!(Arw::strong_count(&y), 1);
+
+        // the weak reference follows the old value
+        assert_eq!(*w.upgrade().unwrap().as_ref(), vec![1, 2]);
+        drop(y);
+        assert!(w.upgrade().is_none());
+
+        // a value shared only with weak references is cloned too
+        let w = x.downgrade();
+        x.make_mut().push(4);
+        assert!(w.upgrade().is_none());
+        assert_eq!(*x.as_ref(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_unsized_slices_and_str() {
+        let a: Arw<[String]> = Arw::from(vec!["a".to_string(), "b".to_string()]);
+        let b = a.clone();
+        b.as_mut()[1].push('c');
+        assert_eq!(*a.as_ref(), ["a", "bc"]);
+
+        let w = a.downgrade();
+        drop(a);
+        assert_eq!(w.upgrade().unwrap().as_ref().len(), 2);
+        drop(b);
+        assert!(w.upgrade().is_none());
+
+        let empty: Arw<[u64]> = Arw::from(Vec::new());
+        assert!(empty.as_ref().is_empty());
+
+        let s: Arw<str> = Arw::from(String::from("hello"));
+        assert_eq!(&*s.as_ref(), "hello");
+        assert_eq!(&*Arw::copy_from_str("").as_ref(), "");
+    }
+
+    #[test]
+    fn test_unsized_trait_objects() {
+        use std::fmt::Debug;
+
+        struct Counted(Arw<AtomicU8>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.as_ref().fetch_add(1, Relaxed);
+            }
+        }
+
+        trait Named {
+            fn name(&self) -> String;
+            fn rename(&mut self, name: &str);
+        }
+
+        impl Named for (Counted, String) {
+            fn name(&self) -> String {
+                self.1.clone()
+            }
+            fn rename(&mut self, name: &str) {
+                self.1 = name.to_string();
+            }
+        }
+
+        let drops = Arw::new(AtomicU8::new(0));
+        let concrete = Arw::new((Counted(drops.clone()), "first".to_string()));
+        let named = unsafe { Arw::unsize(concrete.clone(), |v| v as &dyn Named) };
+        named.as_mut().rename("second");
+        assert_eq!(concrete.as_ref().1, "second");
+        assert_eq!(named.as_ref().name(), "second");
+
+        // the value is dropped once, by whichever handle is last
+        drop(concrete);
+        assert_eq!(drops.as_ref().load(Relaxed), 0);
+        drop(named);
+        assert_eq!(drops.as_ref().load(Relaxed), 1);
+
+        let boxed: Box<dyn Named> = Box::new((Counted(drops.clone()), "boxed".to_string()));
+        let named = Arw::from_box(boxed);
+        assert_eq!(named.as_ref().name(), "boxed");
+        drop(named);
+        assert_eq!(drops.as_ref().load(Relaxed), 2);
+
+        let zst: Arw<dyn Debug> = Arw::from_box(Box::new(()));
+        assert_eq!(format!("{:?}", &*zst.as_ref()), "()");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unsize_rejects_other_values() {
+        let a = Arw::new((1u32, 2u32));
+        // breaks the contract, but is caught by the check before the handle is made
+        let _ = unsafe { Arw::unsize(a, |v| &v.1 as &dyn std::fmt::Debug) };
+    }
+
+    #[test]
+    fn test_new_cyclic() {
+        struct Node {
+            me: WeakArw<Node>,
+            children: Vec<Arw<Node>>,
+        }
+
+        let root = Arw::new_cyclic(|me| {
+            assert!(me.upgrade().is_none());
+            assert_eq!(me.strong_count(), 0);
+            Node {
+                me: me.clone(),
+                children: vec![],
+            }
+        });
+        assert_eq!(Arw::strong_count(&root), 1);
+        assert_eq!(Arw::weak_count(&root), 1);
+
+        let me = root.as_ref().me.upgrade().unwrap();
+        assert!(Arw::ptr_eq(&me, &root));
+        drop(me);
+
+        let child = Arw::new_cyclic(|me| Node {
+            me: me.clone(),
+            children: vec![],
+        });
+        root.as_mut().children.push(child.clone());
+        assert_eq!(Arw::strong_count(&child), 2);
+
+        let w = root.downgrade();
+        drop(root);
+        assert!(w.upgrade().is_none());
+        assert_eq!(Arw::strong_count(&child), 1);
+    }
+
+    #[test]
+    fn test_new_cyclic_panic() {
+        let kept = std::sync::Mutex::new(None);
+        let res = std::panic::catch_unwind(|| {
+            Arw::<String>::new_cyclic(|me| {
+                *kept.lock().unwrap() = Some(me.clone());
+                panic!("no value");
+            })
+        });
+        assert!(res.is_err());
+
+        // the weak reference outlives the failed construction
+        let w = kept.into_inner().unwrap().unwrap();
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.strong_count(), 0);
+    }
+
+    #[test]
+    fn test_replace_take_swap() {
+        let a = Arw::new(vec![1]);
+        let b = a.clone();
+        assert_eq!(b.replace(vec![2]), vec![1]);
+        assert_eq!(*a.as_ref(), vec![2]);
+        assert_eq!(a.take(), vec![2]);
+        assert!(b.as_ref().is_empty());
+
+        let a = Arw::fill(a, vec![3]);
+        assert_eq!(*b.as_ref(), vec![3]);
+
+        let c = Arw::new(vec![4]);
+        Arw::swap(&a, &c);
+        assert_eq!(*b.as_ref(), vec![4]);
+        assert_eq!(*c.as_ref(), vec![3]);
+        // swapping with itself is a no-op, not a deadlock
+        Arw::swap(&a, &b);
+        assert_eq!(*a.as_ref(), vec![4]);
+
+        // opposite swaps of the same pair don't deadlock
+        let (x, y) = (Arw::new(0), Arw::new(1));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let (x, y) = (x.clone(), y.clone());
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if i % 2 == 0 {
+                            Arw::swap(&x, &y);
+                        } else {
+                            Arw::swap(&y, &x);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let mut values = [*x.as_ref(), *y.as_ref()];
+        values.sort();
+        assert_eq!(values, [0, 1]);
+    }
+
+    #[test]
+    fn test_scoped_access() {
+        let a = Arw::new(vec![1, 2]);
+        assert_eq!(a.with(|v| v.iter().sum::<i32>()), 3);
+        assert_eq!(a.with_mut(|v| v.pop()), Some(2));
+        assert!(!a.is_locked());
+
+        // the lock goes away with the closure, also when it panics
+        let res = std::panic::catch_unwind(|| a.with_mut(|_| panic!("oops")));
+        assert!(res.is_err());
+        assert!(!a.is_locked());
+        assert_eq!(a.with(|v| v.clone()), vec![1]);
+    }
+
+    #[test]
+    fn test_watcher_counts_write_guards() {
+        let a = Arw::new(vec![0]);
+        let mut w = a.subscribe();
+        assert!(!w.has_changed());
+
+        // readers are not changes
+        drop(a.as_ref());
+        a.with(|v| v.len());
+        assert!(!w.has_changed());
+
+        a.as_mut().push(1);
+        assert!(w.has_changed());
+        assert_eq!(*w.borrow_and_update(), vec![0, 1]);
+        assert!(!w.has_changed());
+
+        for write in [
+            &|a: &Arw<Vec<i32>>| drop(a.try_write().unwrap()),
+            &|a: &Arw<Vec<i32>>| a.with_mut(|v| v.push(2)),
+            &|a: &Arw<Vec<i32>>| drop(a.take()),
+            &|a: &Arw<Vec<i32>>| drop(a.write_owned()),
+            &|a: &Arw<Vec<i32>>| Arw::swap(a, &Arw::new(vec![])),
+        ] as [&dyn Fn(&Arw<Vec<i32>>); 5]
+        {
+            write(&a);
+            assert!(w.has_changed());
+            w.mark_seen();
+        }
+
+        // mapped guards still count, guards marked unchanged don't
+        drop(WatchGuardMut::map(a.as_mut(), |v| v.as_mut_slice()));
+        assert!(w.has_changed());
+        w.mark_seen();
+        a.as_mut().mark_unchanged();
+        a.write_owned().mark_unchanged();
+        assert!(!w.has_changed());
+
+        // a clone keeps what was seen
+        a.as_mut().push(3);
+        let w2 = w.clone();
+        assert!(w2.has_changed());
+        assert!(Arw::ptr_eq(w2.arw(), &a));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_watcher_waits_for_changes() {
+        let a = Arw::new(0u32);
+        let mut w = a.subscribe();
+        assert!(!w.changed_timeout(std::time::Duration::from_millis(20)));
+
+        let writer = {
+            let a = a.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    *a.as_mut() += 1;
+                }
+            })
+        };
+
+        // every wake up sees a newer value, and the last one is eventually seen
+        let mut last = 0;
+        while last < 100 {
+            w.changed();
+            let now = *w.borrow_and_update();
+            assert!(now > last);
+            last = now;
+        }
+        writer.join().unwrap();
+
+        // a change already made is reported at once
+        *a.as_mut() += 1;
+        w.changed();
+        assert_eq!(*w.borrow(), 101);
+    }
+
+    #[test]
+    fn test_read_optimistic_never_sees_torn_writes() {
+        let a = Arw::new([0u64; 8]);
+        let done = Arw::new(0u8);
+
+        let writer = {
+            let (a, done) = (a.clone(), done.clone());
+            thread::spawn(move || {
+                for n in 1..=20_000u64 {
+                    let mut g = a.as_mut();
+                    for v in g.iter_mut() {
+                        *v = n;
+                    }
+                    if n % 100 == 0 {
+                        // a guard given back and taken again still counts as writing
+                        g.bump();
+                        g.unlocked(hint::spin_loop);
+                    }
+                }
+                *done.as_mut() = 1;
+            })
+        };
+
+        let mut last = 0;
+        while done.read_optimistic(|d| *d) == 0 {
+            let (first, same) = a.read_optimistic(|v| (v[0], v.iter().all(|&x| x == v[0])));
+            assert!(same);
+            assert!(first >= last);
+            last = first;
+        }
+        writer.join().unwrap();
+        assert_eq!(a.read_optimistic(|v| v[7]), 20_000);
+    }
+
+    #[test]
+    fn test_read_optimistic_falls_back_to_the_lock() {
+        let a = Arw::new((1u32, 1u32));
+        let mut g = a.as_mut();
+        *g = (2, 2);
+
+        // a writer holds the value: the reader waits for it on the group lock
+        let reader = {
+            let a = a.clone();
+            thread::spawn(move || a.read_optimistic(|&(x, y)| x + y))
+        };
+        thread::sleep(std::time::Duration::from_millis(20));
+        g.mark_unchanged();
+        drop(g);
+        assert_eq!(reader.join().unwrap(), 4);
+
+        // guards marked unchanged, owned and mapped guards all close their writes
+        drop(a.write_owned());
+        drop(WatchGuardMut::map(a.as_mut(), |v| &mut v.0));
+        assert_eq!(a.read_optimistic(|&(x, y)| (x, y)), (2, 2));
+
+        // types with invalid bit patterns are only seen whole
+        let c = Arw::new(('a', false));
+        assert_eq!(c.read_optimistic(|v| *v), ('a', false));
+    }
+
+    #[test]
+    fn test_read_optimistic_opt_in_copies_are_not_dropped() {
+        use crate::OptimisticRead;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted(u32);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        // SAFETY: `Counted` owns nothing, its drop only counts
+        unsafe impl OptimisticRead for Counted {}
+
+        let a = Arw::new(Counted(1));
+        for _ in 0..10 {
+            assert_eq!(a.read_optimistic(|c| c.0), 1);
+        }
+        // also through the fallback under the lock
+        let g = a.as_mut();
+        let reader = {
+            let a = a.clone();
+            thread::spawn(move || a.read_optimistic(|c| c.0))
+        };
+        thread::sleep(std::time::Duration::from_millis(20));
+        drop(g);
+        assert_eq!(reader.join().unwrap(), 1);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+
+        drop(a);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_value_traits() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let a = Arw::new(String::from("b"));
+        let b = Arw::new(String::from("a"));
+        assert!(a != b && a == Arw::new(String::from("b")) && a == a.clone());
+        assert!(b < a);
+        assert_eq!(a.to_string(), "b");
+
+        let mut map = HashMap::new();
+        map.insert(a.clone(), 1);
+        assert_eq!(map.get(&Arw::new(String::from("b"))), Some(&1));
+
+        let set: BTreeSet<_> = [a.clone(), b.clone(), Arw::new(String::from("a"))].into();
+        assert_eq!(set.len(), 2);
+        assert_eq!(*set.first().unwrap().as_ref(), "a");
+
+        // the comparison of a value with itself follows `T`, not the allocation
+        let nan = Arw::new(f64::NAN);
+        assert!(nan != nan.clone());
+
+        let c = Arw::new("c");
+        let _w = c.downgrade();
+        assert_eq!(format!("{c:?}"), r#"Arw { data: "c", S: 1, W: 2 }"#);
+        let _g = c.as_mut();
+        assert_eq!(format!("{c:?}"), "Arw { data: <locked>, S: 1, W: 2 }");
+    }
+
+    #[test]
+    fn test_comparisons_dont_deadlock_with_writers() {
+        let a = Arw::new(0u64);
+        let b = Arw::new(0u64);
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (a, b) = (a.clone(), b.clone());
+                s.spawn(move || {
+                    for _ in 0..2_000 {
+                        match t {
+                            0 => *a.as_mut() += 1,
+                            1 => *b.as_mut() += 1,
+                            // opposite orders, and an allocation compared with itself
+                            2 => _ = (a == b, a.clone() == a),
+                            _ => _ = (b < a, b.clone() == b),
+                        }
+                    }
+                });
+            }
+        });
+        assert!(a == b);
+    }
+
+    #[test]
+    fn test_by_address() {
+        use crate::ByAddress;
+        use std::collections::{BTreeSet, HashSet};
+
+        let a = Arw::new(1);
+        let b = Arw::new(1);
+        assert_eq!(ByAddress(a.clone()), ByAddress(a.clone()));
+        assert_ne!(ByAddress(a.clone()), ByAddress(b.clone()));
+
+        // the value may change, the key stays the same
+        let set: HashSet<_> = [ByAddress(a.clone()), ByAddress(b.clone())].into();
+        *a.as_mut() = 2;
+        assert!(set.contains(&ByAddress(a.clone())));
+        let _g = a.as_mut();
+        assert!(set.contains(&ByAddress(a.clone())));
+
+        let weak: BTreeSet<_> = [
+            ByAddress(a.downgrade()),
+            ByAddress(b.downgrade()),
+            ByAddress(a.downgrade()),
+            ByAddress(WeakArw::new()),
+            ByAddress(WeakArw::new()),
+        ]
+        .into();
+        assert_eq!(weak.len(), 3);
+        drop((b, set));
+        assert!(weak.iter().any(|w| w.upgrade().is_none() && w.weak_count() == 1));
+    }
 }
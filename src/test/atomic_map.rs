@@ -3,6 +3,36 @@ mod tests_atomic_map {
     use std::thread;
     use crate::collections::AtomicHashMap;
 
+    #[test]
+    fn guards_hold_off_remove() {
+        let map = AtomicHashMap::new();
+        map.insert(1, vec![1u32]);
+        let v = map.get(&1).unwrap();
+
+        let removed = thread::scope(|s| {
+            let h = s.spawn(|| map.remove(&1));
+            thread::sleep(std::time::Duration::from_millis(20));
+            // the entry would be freed by `remove`, which waits for the guard
+            assert!(!h.is_finished());
+            assert_eq!(*v, vec![1]);
+            drop(v);
+            h.join().unwrap()
+        });
+        assert_eq!(removed, Some(vec![1]));
+
+        map.insert(2, vec![2]);
+        let mut v = map.get_mut(&2).unwrap();
+        thread::scope(|s| {
+            let h = s.spawn(|| map.remove(&2));
+            thread::sleep(std::time::Duration::from_millis(20));
+            assert!(!h.is_finished());
+            v.push(3);
+            drop(v);
+            assert_eq!(h.join().unwrap(), Some(vec![2, 3]));
+        });
+        assert!(map.get(&2).is_none());
+    }
+
     #[test]
     fn stress_test() {
         use std::sync::Arc;
@@ -74,7 +104,7 @@ mod tests_atomic_map {
             h.join().unwrap();
         }
 
-        assert_eq!(vec.len() > 50, true);
+        assert!(vec.len() > 50);
     }
 
     #[test]
@@ -177,4 +207,52 @@ mod tests_atomic_map {
         let total = map.len();
         assert_eq!(total, 4 * 50);
     }
+
+    #[test]
+    fn custom_hasher() {
+        use std::collections::hash_map::RandomState;
+
+        let map = AtomicHashMap::with_capacity_and_hasher(16, RandomState::new());
+        for i in 0..32 {
+            map.insert(i, i + 1);
+        }
+        assert_eq!(map.len(), 32);
+        assert_eq!(*map.get(&7).unwrap(), 8);
+        assert_eq!(map.remove(&7), Some(8));
+        assert!(map.get(&7).is_none());
+    }
+
+    #[test]
+    fn get_owned_outlives_map() {
+        let map = AtomicHashMap::new();
+        map.insert(1, String::from("one"));
+        assert!(map.get_owned(&2).is_none());
+
+        let g = map.get_owned(&1).unwrap();
+        let map2 = map.clone();
+        drop(map);
+        let t = thread::spawn(move || g.len());
+        assert_eq!(t.join().unwrap(), 3);
+
+        // the item is free again
+        map2.get_mut(&1).unwrap().push('!');
+        assert_eq!(*map2.get(&1).unwrap(), "one!");
+    }
+
+    #[test]
+    fn scoped_access() {
+        let map = AtomicHashMap::new();
+        map.insert(String::from("a"), vec![1]);
+
+        assert_eq!(map.with("a", |v| v.len()), Some(1));
+        assert_eq!(map.with_mut("a", |v| v.push(2)), Some(()));
+        assert_eq!(map.with_mut("b", |v| v.push(2)), None);
+        assert_eq!(map.get_cloned("a"), Some(vec![1, 2]));
+        assert_eq!(map.get_cloned("b"), None);
+
+        // the lock is released with the closure, so the value can be removed right after
+        let res = std::panic::catch_unwind(|| map.with_mut("a", |_| panic!("oops")));
+        assert!(res.is_err());
+        assert_eq!(map.remove("a"), Some(vec![1, 2]));
+    }
 }
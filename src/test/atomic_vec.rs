@@ -129,12 +129,8 @@ mod tests_atomic_vec {
             let b = barrier.clone();
             ths.push(thread::spawn(move || {
                 b.wait();
-                loop {
-                    if let Some(x) = vv.pop() {
-                        ss.fetch_add(x as isize, Ordering::AcqRel);
-                    } else {
-                        break;
-                    }
+                while let Some(x) = vv.pop() {
+                    ss.fetch_add(x as isize, Ordering::AcqRel);
                 }
             }));
         }
@@ -0,0 +1,89 @@
+mod tests_capi {
+    use crate::capi::*;
+    use core::ffi::c_void;
+    use core::{ptr, slice};
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    #[test]
+    fn header_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/castbox.h"));
+        let committed = include_str!("../../include/castbox.h");
+        assert!(
+            generated == committed,
+            "include/castbox.h is stale, copy it from {}",
+            env!("OUT_DIR")
+        );
+    }
+
+    extern "C" fn increment(_: *mut c_void, data: *mut u8, len: usize) {
+        let data = unsafe { slice::from_raw_parts_mut(data, len) };
+        let n = u32::from_ne_bytes(data.try_into().unwrap());
+        data.copy_from_slice(&(n + 1).to_ne_bytes());
+    }
+
+    #[test]
+    fn handles_shared_across_threads() {
+        let bytes = unsafe { castbox_bytes_new(0u32.to_ne_bytes().as_ptr(), 4) };
+        let map = castbox_map_new();
+        let mutex = castbox_mutex_new();
+        let counter = AtomicU32::new(0);
+
+        thread::scope(|s| {
+            for t in 0..4u8 {
+                // each thread owns its handles, as a plugin would
+                let (b, m, l) = unsafe {
+                    (
+                        castbox_bytes_clone(bytes) as usize,
+                        castbox_map_clone(map) as usize,
+                        castbox_mutex_clone(mutex) as usize,
+                    )
+                };
+                let counter = &counter;
+                s.spawn(move || unsafe {
+                    let (b, m, l) = (
+                        b as *mut CastboxBytes,
+                        m as *mut CastboxMap,
+                        l as *mut CastboxMutex,
+                    );
+                    for i in 0..100u8 {
+                        castbox_bytes_write(b, increment, ptr::null_mut());
+                        castbox_map_insert(m, [t, i].as_ptr(), 2, [i].as_ptr(), 1);
+
+                        castbox_mutex_lock(l);
+                        let n = counter.load(Relaxed);
+                        counter.store(n + 1, Relaxed);
+                        castbox_mutex_unlock(l);
+                    }
+                    castbox_bytes_free(b);
+                    castbox_map_free(m);
+                    castbox_mutex_free(l);
+                });
+            }
+        });
+
+        unsafe {
+            let mut total = [0u8; 4];
+            extern "C" fn copy(ctx: *mut c_void, data: *const u8, len: usize) {
+                unsafe { ptr::copy_nonoverlapping(data, ctx as *mut u8, len) };
+            }
+            castbox_bytes_read(bytes, copy, total.as_mut_ptr() as *mut c_void);
+            assert_eq!(u32::from_ne_bytes(total), 400);
+            assert_eq!(castbox_map_len(map), 400);
+            assert_eq!(counter.into_inner(), 400);
+
+            let mut buf = CastboxBuf {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert!(castbox_map_get(map, [3, 7].as_ptr(), 2, &mut buf));
+            assert_eq!(slice::from_raw_parts(buf.data, buf.len), [7]);
+            castbox_buf_free(&mut buf);
+
+            castbox_bytes_free(bytes);
+            castbox_map_free(map);
+            castbox_mutex_free(mutex);
+        }
+    }
+}
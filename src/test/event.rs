@@ -0,0 +1,131 @@
+mod tests_event {
+    use crate::mutex::{Event, EventMode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn stress_test() {
+        let event = Event::manual();
+        let woken = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..100 {
+            let e = event.clone();
+            let w = woken.clone();
+            handles.push(thread::spawn(move || {
+                e.wait();
+                w.fetch_add(1, Ordering::Relaxed);
+            }));
+        }
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(woken.load(Ordering::Relaxed), 0);
+
+        event.set();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(woken.load(Ordering::Relaxed), 100);
+        assert!(event.is_set());
+    }
+
+    #[test]
+    fn manual_reset_stays_set() {
+        let e = Event::manual();
+        assert_eq!(e.mode(), EventMode::ManualReset);
+        assert!(!e.is_set());
+
+        e.set();
+        e.wait();
+        e.wait();
+        assert!(e.is_set());
+
+        e.reset();
+        assert!(!e.is_set());
+        assert!(!e.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn auto_reset_consumes_signal() {
+        let e = Event::auto();
+        assert_eq!(e.mode(), EventMode::AutoReset);
+
+        e.set();
+        assert!(e.is_set());
+        e.wait();
+        assert!(!e.is_set());
+        assert!(!e.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn auto_reset_releases_one_waiter_per_set() {
+        const N: usize = 8;
+        let e = Event::auto();
+        let barrier = Arc::new(Barrier::new(N + 1));
+        let woken = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = vec![];
+        for _ in 0..N {
+            let e = e.clone();
+            let b = barrier.clone();
+            let w = woken.clone();
+            handles.push(thread::spawn(move || {
+                b.wait();
+                e.wait();
+                w.fetch_add(1, Ordering::AcqRel);
+            }));
+        }
+
+        barrier.wait();
+        thread::sleep(Duration::from_millis(20));
+
+        for i in 1..=N {
+            e.set();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while woken.load(Ordering::Acquire) < i && Instant::now() < deadline {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(5));
+            assert_eq!(woken.load(Ordering::Acquire), i);
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(!e.is_set());
+    }
+
+    #[test]
+    fn wait_timeout_expires() {
+        let e = Event::manual();
+        let start = Instant::now();
+        assert!(!e.wait_timeout(Duration::from_millis(30)));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn wait_timeout_is_woken_by_set() {
+        let e = Event::manual();
+        let e2 = e.clone();
+
+        let h = thread::spawn(move || e2.wait_timeout(Duration::from_secs(5)));
+
+        thread::sleep(Duration::from_millis(20));
+        e.set();
+        assert!(h.join().unwrap());
+    }
+
+    #[test]
+    fn refcount_clone_drop() {
+        let e = Event::auto();
+        assert_eq!(e.get_ref_count(), 1);
+        let c = e.clone();
+        assert_eq!(e.get_ref_count(), 2);
+        drop(c);
+        assert_eq!(e.get_ref_count(), 1);
+    }
+}
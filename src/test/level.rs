@@ -0,0 +1,174 @@
+mod tests_level {
+    use crate::Arw;
+    use crate::level::{Level, LockToken, Root};
+    use crate::mutex::{LockError, Mutex, MutexType};
+    use std::mem::size_of;
+    use std::panic;
+    use std::sync::Barrier;
+    use std::thread;
+
+    enum Low {}
+    impl Level for Low {
+        const LEVEL: u32 = 10;
+    }
+
+    enum High {}
+    impl Level for High {
+        const LEVEL: u32 = 20;
+    }
+
+    #[test]
+    fn levels_are_zero_cost() {
+        assert_eq!(size_of::<LockToken<'_, Root>>(), 0);
+        assert_eq!(size_of::<Mutex<Low>>(), size_of::<Mutex>());
+        assert_eq!(size_of::<Arw<u64, High>>(), size_of::<Arw<u64>>());
+    }
+
+    #[test]
+    fn nested_locks_in_order() {
+        let low = Arw::<_, Low>::new_leveled(vec![1, 2, 3]);
+        let high = Mutex::<High>::new_leveled();
+
+        let mut root = LockToken::root();
+        {
+            let (mut v, mut token) = low.as_mut_leveled(&mut root);
+            let guard = high.lock_exclusive_leveled(&mut token);
+            assert_eq!(guard.mode(), MutexType::Exclusive);
+            v.push(4);
+        }
+        assert!(!high.is_locked());
+
+        // the root token is usable again once the lower locks are released
+        let (v, _) = low.as_ref_leveled(&mut root);
+        assert_eq!(*v, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn skipping_levels() {
+        let low = Mutex::<Low>::new_leveled();
+        let high = Arw::<_, High>::new_leveled(0);
+
+        let mut root = LockToken::root();
+        let (mut v, _) = high.as_mut_leveled(&mut root);
+        *v += 1;
+        drop(v);
+
+        let guard = low.try_lock_exclusive_leveled(&mut root);
+        assert!(guard.is_some());
+        assert!(low.is_locked_exclusive());
+        drop(guard);
+        assert!(!low.is_locked());
+    }
+
+    #[test]
+    fn guards_hand_their_token_up() {
+        let low = Mutex::<Low>::new_leveled();
+        let high = Mutex::<High>::new_leveled();
+
+        let mut root = LockToken::root();
+        let mut guard = low.lock_group_leveled(&mut root);
+        assert!(high.try_lock_exclusive_leveled(guard.token()).is_some());
+        let high_guard = high.lock_group_leveled(guard.token());
+        assert!(low.is_locked_group() && high.is_locked_group());
+
+        drop(high_guard);
+        drop(guard);
+        assert!(low.try_lock_exclusive_leveled(&mut root).is_some());
+        assert!(!low.is_locked());
+    }
+
+    #[test]
+    fn try_leveled_locks() {
+        let low = Mutex::<Low>::new_leveled();
+        let high = Arw::<_, High>::new_leveled(1);
+
+        let mut root = LockToken::root();
+        let mut guard = low.try_lock_group_leveled(&mut root).unwrap();
+        assert_eq!(guard.mode(), MutexType::Group);
+        {
+            let (v, _) = high.try_read_leveled(guard.token()).unwrap();
+            assert_eq!(*v, 1);
+        }
+        {
+            let (mut v, _) = high.try_write_leveled(guard.token()).unwrap();
+            *v += 1;
+        }
+        drop(guard);
+
+        // a writer on another thread makes every attempt fail
+        let (locked, release) = (Barrier::new(2), Barrier::new(2));
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut root = LockToken::root();
+                let mut guard = low.lock_exclusive_leveled(&mut root);
+                let _w = high.as_mut_leveled(guard.token());
+                locked.wait();
+                release.wait();
+            });
+            locked.wait();
+            assert!(low.try_lock_group_leveled(&mut root).is_none());
+            let err = high.try_read_leveled(&mut root).err();
+            assert_eq!(err, Some(LockError::WouldBlock));
+            let err = high.try_write_leveled(&mut root).err();
+            assert_eq!(err, Some(LockError::WouldBlock));
+            release.wait();
+        });
+        assert!(!low.is_locked());
+        assert_eq!(*high.try_read_leveled(&mut root).unwrap().0, 2);
+    }
+
+    #[test]
+    fn unwrap_and_make_mut_leveled() {
+        let mut a = Arw::<_, Low>::new_leveled(vec![1]);
+        let b = a.clone();
+
+        let mut root = LockToken::root();
+        a.make_mut_leveled(&mut root).push(2);
+        assert!(!Arw::ptr_eq(&a, &b));
+        assert_eq!(*b.as_ref_leveled(&mut root).0, vec![1]);
+
+        let a = Arw::try_unwrap_leveled(a, &mut root).unwrap();
+        assert_eq!(a, vec![1, 2]);
+        let c = b.clone();
+        let b = Arw::try_unwrap_leveled(b, &mut root).unwrap_err();
+        drop(c);
+        assert_eq!(Arw::try_unwrap_leveled(b, &mut root).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn one_root_per_thread() {
+        let root = LockToken::root();
+        assert!(panic::catch_unwind(LockToken::root).is_err());
+
+        // other threads have their own
+        thread::spawn(|| drop(LockToken::root())).join().unwrap();
+
+        drop(root);
+        let _root = LockToken::root();
+    }
+
+    #[test]
+    fn leveled_handles_are_shared() {
+        let a = Arw::<_, Low>::new_leveled(0u32);
+        let w = a.downgrade();
+
+        let mut ths = Vec::new();
+        for _ in 0..8 {
+            let a = w.upgrade().unwrap();
+            ths.push(thread::spawn(move || {
+                let mut root = LockToken::root();
+                for _ in 0..100 {
+                    let (mut v, _) = a.as_mut_leveled(&mut root);
+                    *v += 1;
+                }
+            }));
+        }
+        for t in ths {
+            t.join().unwrap();
+        }
+
+        let mut root = LockToken::root();
+        assert_eq!(*a.as_ref_leveled(&mut root).0, 800);
+        assert_eq!(Arw::strong_count(&a), 1);
+    }
+}
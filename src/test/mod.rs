@@ -4,5 +4,22 @@ mod atomic_vec;
 
 mod mutex;
 
+#[cfg(feature = "std")]
 mod atomic_map;
-mod arw;
\ No newline at end of file
+mod arw;
+#[cfg(feature = "std")]
+mod event;
+#[cfg(feature = "std")]
+mod phaser;
+mod seq_lock;
+#[cfg(feature = "std")]
+mod watchdog;
+// without `std` there is a single root token per process, the tests take one per thread
+#[cfg(feature = "std")]
+mod level;
+#[cfg(feature = "std")]
+mod watch_guard;
+#[cfg(all(feature = "serde", feature = "std"))]
+mod serde;
+#[cfg(feature = "capi")]
+mod capi;
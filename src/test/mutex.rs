@@ -1,9 +1,11 @@
 mod tests_mutex {
-    use crate::mutex::Mutex;
+    use crate::mutex::{LockState, Mutex, MutexBackend};
     use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
     use std::sync::{Arc, Barrier};
     use std::thread;
     use std::time::Duration;
+    #[cfg(feature = "std")]
+    use std::time::Instant;
 
     #[test]
     fn stress_test() {
@@ -86,11 +88,9 @@ mod tests_mutex {
     fn is_locked_reflects_state() {
         let m = Mutex::new();
         assert!(!m.is_locked_exclusive());
-        {
-            let _g = m.lock_exclusive();
-            assert!(m.is_locked_exclusive());
-            m.unlock_exclusive();
-        }
+        m.lock_exclusive();
+        assert!(m.is_locked_exclusive());
+        m.unlock_exclusive();
         assert!(!m.is_locked_exclusive());
     }
 
@@ -288,4 +288,370 @@ mod tests_mutex {
         assert!(excl_sum.load(Ordering::Relaxed) > 0);
         assert!(group_entries.load(Ordering::Relaxed) > 0);
     }
+
+    /// Runs the same mixed workload on a mutex with the given backend,
+    /// returns the exclusive and group entries.
+    fn mixed_workload(backend: MutexBackend, threads: usize, rounds: usize) -> (usize, usize) {
+        let m = Mutex::with_backend(backend);
+        let excl = Arc::new(AtomicUsize::new(0));
+        let group = Arc::new(AtomicUsize::new(0));
+        let inside = Arc::new(AtomicBool::new(false));
+
+        let mut ths = Vec::new();
+        for id in 0..threads {
+            let mm = m.clone();
+            let excl = excl.clone();
+            let group = group.clone();
+            let inside = inside.clone();
+            ths.push(thread::spawn(move || {
+                for i in 0..rounds {
+                    if (id + i) % 4 == 0 {
+                        mm.lock_exclusive();
+                        assert!(!inside.swap(true, Ordering::Relaxed));
+                        excl.fetch_add(1, Ordering::Relaxed);
+                        inside.store(false, Ordering::Relaxed);
+                        mm.unlock_exclusive();
+                    } else {
+                        mm.lock_group();
+                        assert!(!inside.load(Ordering::Relaxed));
+                        group.fetch_add(1, Ordering::Relaxed);
+                        mm.unlock_group();
+                    }
+                }
+            }));
+        }
+        for t in ths {
+            t.join().unwrap();
+        }
+        assert!(!m.is_locked());
+        (excl.load(Ordering::Relaxed), group.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn queued_backend_throughput() {
+        let threads = thread::available_parallelism().map_or(4, |n| n.get()).max(4) * 2;
+        let rounds = 2_000;
+
+        for backend in [MutexBackend::Standard, MutexBackend::Queued] {
+            let started = Instant::now();
+            let (excl, group) = mixed_workload(backend, threads, rounds);
+            assert_eq!(excl + group, threads * rounds);
+            println!("{:?}: {} threads, {:?}", backend, threads, started.elapsed());
+        }
+    }
+
+    #[test]
+    fn queued_backend_hands_over_in_order() {
+        let m = Mutex::with_backend(MutexBackend::Queued);
+        assert_eq!(m.backend(), MutexBackend::Queued);
+        m.lock_exclusive();
+
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut ths = Vec::new();
+        for id in 0..4 {
+            let mm = m.clone();
+            let order = order.clone();
+            ths.push(thread::spawn(move || {
+                mm.lock_exclusive();
+                order.lock().unwrap().push(id);
+                mm.unlock_exclusive();
+            }));
+            // let the thread enqueue before spawning the next one
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        m.unlock_exclusive();
+        for t in ths {
+            t.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn adaptive_spinning_backs_off_on_long_holds() {
+        let m = Mutex::new();
+        assert!(m.is_adaptive());
+
+        // short critical sections under contention
+        mixed_workload(MutexBackend::Standard, 8, 1_000);
+
+        // the waiter never sees the lock released while spinning, so it stops spinning
+        for _ in 0..20 {
+            m.lock_exclusive();
+            let mm = m.clone();
+            let h = thread::spawn(move || {
+                mm.lock_exclusive();
+                mm.unlock_exclusive();
+            });
+            thread::sleep(Duration::from_millis(5));
+            m.unlock_exclusive();
+            h.join().unwrap();
+        }
+        // the moving average moves by eighths, so it settles below 8
+        assert!(m.spin_estimate() < 8, "estimate {}", m.spin_estimate());
+    }
+
+    #[test]
+    fn adaptive_spinning_can_be_disabled() {
+        let m = Mutex::new();
+        m.set_adaptive(false);
+        assert!(!m.is_adaptive());
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut ths = Vec::new();
+        for _ in 0..8 {
+            let mm = m.clone();
+            let counter = counter.clone();
+            ths.push(thread::spawn(move || {
+                for _ in 0..500 {
+                    mm.lock_exclusive();
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    mm.unlock_exclusive();
+                }
+            }));
+        }
+        for t in ths {
+            t.join().unwrap();
+        }
+        assert_eq!(counter.load(Ordering::Relaxed), 4_000);
+        assert_eq!(m.spin_estimate(), 0);
+    }
+
+    #[test]
+    fn snapshot_reports_states() {
+        let m = Mutex::new();
+        let c = m.clone();
+        let s = m.snapshot();
+        assert_eq!(s.state, LockState::Unlocked);
+        assert_eq!(s.group_holders, 0);
+        assert_eq!(s.ref_count, 2);
+        drop(c);
+
+        m.lock_exclusive();
+        assert_eq!(m.snapshot().state, LockState::Exclusive);
+        m.unlock_exclusive();
+
+        m.lock_group();
+        m.lock_group();
+        let s = m.snapshot();
+        assert_eq!(s.state, LockState::Group);
+        assert_eq!(s.group_holders, 2);
+
+        m.unlock_group();
+        m.unlock_group();
+        let s = m.snapshot();
+        assert_eq!(s.state, LockState::Dirty);
+        assert_eq!(s.group_holders, 0);
+        assert_eq!(s.ref_count, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn snapshot_counts_parked_waiters() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+
+        let mut ths = Vec::new();
+        for i in 0..3 {
+            let mm = m.clone();
+            ths.push(thread::spawn(move || {
+                if i == 0 {
+                    mm.lock_exclusive();
+                    mm.unlock_exclusive();
+                } else {
+                    mm.lock_group();
+                    mm.unlock_group();
+                }
+            }));
+        }
+
+        // wait for all of them to give up spinning
+        let mut s = m.snapshot();
+        while s.parked_exclusive + s.parked_group < 3 {
+            thread::sleep(Duration::from_millis(1));
+            s = m.snapshot();
+        }
+        assert_eq!(s.state, LockState::Exclusive);
+        assert_eq!(s.parked_exclusive, 1);
+        assert_eq!(s.parked_group, 2);
+        // the group waiters are registered as soon as they try
+        assert_eq!(s.group_holders, 2);
+
+        m.unlock_exclusive();
+        for t in ths {
+            t.join().unwrap();
+        }
+        let s = m.snapshot();
+        assert_eq!((s.parked_exclusive, s.parked_group), (0, 0));
+    }
+
+    #[test]
+    fn try_lock_exclusive_on_free_mutex() {
+        let m = Mutex::new();
+        assert!(m.try_lock_exclusive());
+        assert!(!m.try_lock_exclusive());
+        m.unlock_exclusive();
+
+        // free again after a group round
+        m.lock_group();
+        assert!(!m.try_lock_exclusive());
+        m.unlock_group();
+        assert!(m.try_lock_exclusive());
+        m.unlock_exclusive();
+    }
+
+    /// Locks `m` in a new thread, that sets `done` while holding it.
+    fn waiter(m: &Mutex, exclusive: bool, done: &Arc<AtomicBool>) -> thread::JoinHandle<()> {
+        let (m, done) = (m.clone(), done.clone());
+        thread::spawn(move || {
+            if exclusive {
+                m.lock_exclusive();
+                done.store(true, Ordering::Relaxed);
+                m.unlock_exclusive();
+            } else {
+                m.lock_group();
+                done.store(true, Ordering::Relaxed);
+                m.unlock_group();
+            }
+        })
+    }
+
+    fn wait_parked(m: &Mutex, count: usize) {
+        let mut s = m.snapshot();
+        while s.parked_exclusive + s.parked_group < count {
+            thread::sleep(Duration::from_millis(1));
+            s = m.snapshot();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn bump_hands_over_to_exclusive_waiter() {
+        let m = Mutex::new();
+        let done = Arc::new(AtomicBool::new(false));
+        m.lock_exclusive();
+
+        let t = waiter(&m, true, &done);
+        wait_parked(&m, 1);
+
+        // the waiter owns the lock as soon as it wakes, we get it back after it
+        m.bump_exclusive();
+        assert!(done.load(Ordering::Relaxed));
+        assert!(m.is_locked_exclusive());
+
+        m.unlock_exclusive();
+        t.join().unwrap();
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn bump_hands_over_to_group_waiters() {
+        let m = Mutex::new();
+        let done = Arc::new(AtomicBool::new(false));
+        m.lock_exclusive();
+
+        let t = waiter(&m, false, &done);
+        wait_parked(&m, 1);
+
+        m.bump_exclusive();
+        assert!(done.load(Ordering::Relaxed));
+        assert!(m.is_locked_exclusive());
+
+        m.unlock_exclusive();
+        t.join().unwrap();
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn bump_without_waiters_keeps_lock() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+        m.bump_exclusive();
+        assert!(m.is_locked_exclusive());
+        m.unlock_exclusive();
+
+        // and a fair unlock without waiters is a plain unlock
+        m.lock_exclusive();
+        m.unlock_exclusive_fair();
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn try_lock_group_never_waits() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+        assert!(!m.try_lock_group());
+        assert_eq!(m.snapshot().group_holders, 0);
+        m.unlock_exclusive();
+
+        assert!(m.try_lock_group());
+        assert!(m.try_lock_group());
+        assert_eq!(m.snapshot().group_holders, 2);
+        m.unlock_group();
+        m.unlock_group();
+        assert!(!m.is_locked());
+        assert!(m.try_lock_exclusive());
+        m.unlock_exclusive();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timed_locks_give_up() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+
+        let started = Instant::now();
+        assert!(!m.lock_group_timeout(Duration::from_millis(20)));
+        assert!(!m.lock_exclusive_timeout(Duration::from_millis(20)));
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        // the abandoned group wait left nothing behind
+        let s = m.snapshot();
+        assert_eq!(s.group_holders, 0);
+        assert_eq!((s.parked_exclusive, s.parked_group), (0, 0));
+        m.unlock_exclusive();
+        assert!(m.try_lock_exclusive());
+        m.unlock_exclusive();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timed_locks_succeed_when_released() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+
+        let m2 = m.clone();
+        let t = thread::spawn(move || {
+            let group = m2.lock_group_timeout(Duration::from_secs(10));
+            m2.unlock_group();
+            let exclusive = m2.lock_exclusive_timeout(Duration::from_secs(10));
+            m2.unlock_exclusive();
+            (group, exclusive)
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        m.unlock_exclusive();
+        assert_eq!(t.join().unwrap(), (true, true));
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timed_out_group_waiter_does_not_block_exclusive() {
+        let m = Mutex::new();
+        m.lock_exclusive();
+
+        // an exclusive waiter parks while the group waiter is counted
+        let done = Arc::new(AtomicBool::new(false));
+        let t = waiter(&m, true, &done);
+        assert!(!m.lock_group_timeout(Duration::from_millis(30)));
+
+        m.unlock_exclusive();
+        t.join().unwrap();
+        assert!(done.load(Ordering::Relaxed));
+        assert!(!m.is_locked());
+    }
 }
@@ -0,0 +1,129 @@
+mod tests_phaser {
+    use crate::mutex::Phaser;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn stress_test() {
+        const WORKERS: usize = 16;
+        const PHASES: usize = 50;
+
+        let phaser = Phaser::new(WORKERS);
+        let done = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..WORKERS {
+            let p = phaser.clone();
+            let d = done.clone();
+            handles.push(thread::spawn(move || {
+                for phase in 0..PHASES {
+                    d.fetch_add(1, Ordering::Relaxed);
+                    assert_eq!(p.arrive_and_await_advance(), phase as u32 + 1);
+                    // everybody finished the previous phase
+                    assert!(d.load(Ordering::Relaxed) >= (phase + 1) * WORKERS);
+                }
+            }));
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(phaser.phase(), PHASES as u32);
+        assert_eq!(done.load(Ordering::Relaxed), WORKERS * PHASES);
+    }
+
+    #[test]
+    fn parties_join_and_leave() {
+        let p = Phaser::new(1);
+        assert_eq!(p.register(), 0);
+        assert_eq!(p.registered_parties(), 2);
+        assert_eq!(p.unarrived_parties(), 2);
+
+        assert_eq!(p.arrive(), 0);
+        assert_eq!(p.arrived_parties(), 1);
+        assert_eq!(p.phase(), 0);
+
+        // the last unarrived party leaves: the phase completes without it
+        assert_eq!(p.arrive_and_deregister(), 0);
+        assert_eq!(p.phase(), 1);
+        assert_eq!(p.registered_parties(), 1);
+        assert_eq!(p.unarrived_parties(), 1);
+        assert!(!p.is_terminated());
+
+        assert_eq!(p.arrive_and_deregister(), 1);
+        assert!(p.is_terminated());
+        // a terminated phaser never blocks
+        assert_eq!(p.await_advance(p.phase()), 2);
+        assert_eq!(p.register(), 2);
+        assert_eq!(p.registered_parties(), 0);
+    }
+
+    #[test]
+    fn worker_joins_running_phase() {
+        let p = Phaser::new(2);
+        let p1 = p.clone();
+        let first = thread::spawn(move || p1.arrive_and_await_advance());
+
+        // joins while the first phase is in progress: it must arrive as well
+        let p2 = p.clone();
+        p2.register();
+        let second = thread::spawn(move || p2.arrive_and_await_advance());
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(p.phase(), 0);
+        assert_eq!(p.arrive_and_await_advance(), 1);
+
+        assert_eq!(first.join().unwrap(), 1);
+        assert_eq!(second.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn await_advance_timeout() {
+        let p = Phaser::new(2);
+        let phase = p.arrive();
+        assert_eq!(
+            p.await_advance_timeout(phase, Duration::from_millis(20)),
+            None
+        );
+
+        // a stale phase returns immediately
+        assert_eq!(p.await_advance_timeout(7, Duration::from_secs(10)), Some(0));
+
+        let p1 = p.clone();
+        let h = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            p1.arrive()
+        });
+        assert_eq!(
+            p.await_advance_timeout(phase, Duration::from_secs(10)),
+            Some(1)
+        );
+        assert_eq!(h.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn deregistering_releases_waiters() {
+        let p = Phaser::new(3);
+        let mut handles = vec![];
+        for _ in 0..2 {
+            let p = p.clone();
+            handles.push(thread::spawn(move || p.arrive_and_await_advance()));
+        }
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(p.arrive_and_deregister(), 0);
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 1);
+        }
+        assert_eq!(p.registered_parties(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn arrive_without_parties_panics() {
+        let p = Phaser::new(0);
+        p.arrive();
+    }
+}
@@ -0,0 +1,102 @@
+mod tests_seq_lock {
+    use crate::SeqLock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn stress_test() {
+        // writers always keep the two halves equal, readers must never see a torn pair
+        let lock = SeqLock::new((0u64, 0u64));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handles = vec![];
+
+        for _ in 0..4 {
+            let l = lock.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    l.write(|v| {
+                        v.0 += 1;
+                        v.1 += 1;
+                    });
+                }
+            }));
+        }
+
+        let mut readers = vec![];
+        for _ in 0..8 {
+            let l = lock.clone();
+            let stop = stop.clone();
+            readers.push(thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    let (a, b) = l.read();
+                    assert_eq!(a, b);
+                }
+            }));
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        stop.store(true, Ordering::Release);
+        for h in readers {
+            h.join().unwrap();
+        }
+
+        assert_eq!(lock.read(), (4000, 4000));
+    }
+
+    #[test]
+    fn read_write_set() {
+        let s = SeqLock::new(1i32);
+        assert_eq!(s.read(), 1);
+
+        let old = s.write(|v| {
+            let old = *v;
+            *v = 5;
+            old
+        });
+        assert_eq!(old, 1);
+        assert_eq!(s.read(), 5);
+
+        s.set(9);
+        assert_eq!(s.try_read(), Some(9));
+    }
+
+    #[test]
+    fn readers_see_old_value_during_write() {
+        let s = SeqLock::new(0u8);
+        let s2 = s.clone();
+        s.write(|v| {
+            *v = 1;
+            assert_eq!(s2.try_read(), Some(0));
+        });
+        assert_eq!(s2.try_read(), Some(1));
+    }
+
+    #[test]
+    fn clone_shares_value() {
+        let s: SeqLock<u32> = SeqLock::default();
+        let c = s.clone();
+        c.set(3);
+        drop(c);
+        assert_eq!(s.read(), 3);
+    }
+
+    #[test]
+    fn panicking_write_releases_the_lock() {
+        let s = SeqLock::new((false, 'a'));
+        let res = std::panic::catch_unwind(|| {
+            s.write(|v| {
+                v.0 = true;
+                panic!("writer failed");
+            })
+        });
+        assert!(res.is_err());
+
+        // the value is untouched and both readers and writers go on
+        assert_eq!(s.try_read(), Some((false, 'a')));
+        s.set((true, 'b'));
+        assert_eq!(s.read(), (true, 'b'));
+    }
+}
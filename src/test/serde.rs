@@ -0,0 +1,173 @@
+mod tests_serde {
+    use crate::collections::{AtomicHashMap, AtomicVec};
+    use crate::serde::{shared, with_sharing};
+    use crate::{Arw, WeakArw};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Shared {
+        #[serde(with = "shared")]
+        a: Arw<Vec<u32>>,
+        #[serde(with = "shared")]
+        b: Arw<Vec<u32>>,
+        #[serde(with = "shared")]
+        c: Arw<Vec<u32>>,
+    }
+
+    #[test]
+    fn arw_round_trip() {
+        let a = Arw::new(vec![1, 2]);
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(json, "[1,2]");
+        let back: Arw<Vec<i32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(*back.as_ref(), vec![1, 2]);
+
+        let s: Arw<str> = serde_json::from_str("\"text\"").unwrap();
+        assert_eq!(&*s.as_ref(), "text");
+        let v: Arw<[u8]> = serde_json::from_str("[3,4]").unwrap();
+        assert_eq!(*v.as_ref(), [3, 4]);
+    }
+
+    #[test]
+    fn arw_waits_for_writers() {
+        let a = Arw::new(1);
+        let w = a.as_mut();
+        let json = std::thread::scope(|s| {
+            let h = s.spawn(|| serde_json::to_string(&a).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            drop(w);
+            h.join().unwrap()
+        });
+        assert_eq!(json, "1");
+    }
+
+    #[test]
+    fn weak_round_trip() {
+        let a = Arw::new(5);
+        let w = a.downgrade();
+        assert_eq!(serde_json::to_string(&w).unwrap(), "5");
+        drop(a);
+        assert_eq!(serde_json::to_string(&w).unwrap(), "null");
+
+        let w: WeakArw<i32> = serde_json::from_str("5").unwrap();
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn collections_round_trip() {
+        let vec = AtomicVec::new();
+        vec.push(1);
+        vec.push(2);
+        vec.pop();
+        vec.push(3);
+        let json = serde_json::to_string(&vec).unwrap();
+        assert_eq!(json, "[2,3]");
+        // serializing doesn't consume the items
+        assert_eq!(vec.len(), 2);
+
+        let back: AtomicVec<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.pop(), Some(2));
+        assert_eq!(back.pop(), Some(3));
+        assert_eq!(back.pop(), None);
+
+        let map = AtomicHashMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        let json = serde_json::to_string(&map).unwrap();
+        let back: AtomicHashMap<String, i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.len(), 2);
+        assert_eq!(back.get_cloned("a"), Some(1));
+        assert_eq!(back.get_cloned("b"), Some(2));
+
+        // the map lock is released afterwards
+        map.insert("c".to_string(), 3);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn map_waits_for_writers() {
+        let map = AtomicHashMap::new();
+        map.insert(1, vec![1]);
+        let mut w = map.get_mut(&1).unwrap();
+        let json = std::thread::scope(|s| {
+            let h = s.spawn(|| serde_json::to_string(&map).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            w.push(2);
+            drop(w);
+            h.join().unwrap()
+        });
+        assert_eq!(json, r#"{"1":[1,2]}"#);
+
+        // the entry lock is released afterwards
+        map.get_mut(&1).unwrap().push(3);
+        assert_eq!(map.get_cloned(&1), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn map_writers_stall_inserts_while_serializing() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+        use std::time::Duration;
+
+        let map = AtomicHashMap::new();
+        map.insert(1, vec![1]);
+        let inserted = AtomicBool::new(false);
+        let w = map.get_mut(&1).unwrap();
+
+        let json = thread::scope(|s| {
+            let h = s.spawn(|| serde_json::to_string(&map).unwrap());
+            thread::sleep(Duration::from_millis(20));
+
+            // the serializer holds the map lock while it waits for `w`
+            s.spawn(|| {
+                map.insert(2, vec![2]);
+                inserted.store(true, Ordering::Release);
+            });
+            thread::sleep(Duration::from_millis(20));
+            assert!(!inserted.load(Ordering::Acquire));
+
+            drop(w);
+            h.join().unwrap()
+        });
+        assert!(inserted.load(Ordering::Acquire));
+        assert_eq!(json, r#"{"1":[1]}"#);
+        assert_eq!(map.get_cloned(&2), Some(vec![2]));
+    }
+
+    #[test]
+    fn shared_keeps_sharing() {
+        let a = Arw::new(vec![1]);
+        let state = Shared {
+            a: a.clone(),
+            b: a,
+            c: Arw::new(vec![2]),
+        };
+
+        let json = with_sharing(|| serde_json::to_string(&state)).unwrap();
+        let back: Shared = with_sharing(|| serde_json::from_str(&json)).unwrap();
+        assert!(Arw::ptr_eq(&back.a, &back.b));
+        assert!(!Arw::ptr_eq(&back.a, &back.c));
+        back.a.as_mut().push(3);
+        assert_eq!(*back.b.as_ref(), vec![1, 3]);
+        assert_eq!(*back.c.as_ref(), vec![2]);
+
+        // outside of `with_sharing` every handle carries its value
+        let json = serde_json::to_string(&state).unwrap();
+        let back: Shared = serde_json::from_str(&json).unwrap();
+        assert!(!Arw::ptr_eq(&back.a, &back.b));
+        assert_eq!(*back.b.as_ref(), vec![1]);
+    }
+
+    #[test]
+    fn shared_rejects_unknown_references() {
+        let a = Arw::new(vec![1]);
+        let state = Shared {
+            a: a.clone(),
+            b: a.clone(),
+            c: a,
+        };
+        let json = with_sharing(|| serde_json::to_string(&state)).unwrap();
+        // the references can't be resolved without the tracking
+        assert!(serde_json::from_str::<Shared>(&json).is_err());
+    }
+}
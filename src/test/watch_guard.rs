@@ -0,0 +1,130 @@
+mod tests_watch_guard {
+    use crate::mutex::{Mutex, MutexType, WatchGuard};
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn new_is_unlocked() {
+        let g = WatchGuard::new(5);
+        assert_eq!(g.mode(), None);
+        assert!(!g.is_locked());
+        assert_eq!(g, 5);
+        // dropping an unlocked guard releases nothing
+        drop(g);
+
+        let m = Mutex::new();
+        drop(WatchGuard::with_mutex(5, m.clone()));
+        assert!(!m.is_locked());
+        assert!(m.try_lock_exclusive());
+        m.unlock_exclusive();
+    }
+
+    #[test]
+    fn none_to_group_and_back() {
+        let m = Mutex::new();
+        let mut g = WatchGuard::with_mutex((), m.clone());
+
+        g.lock_group();
+        assert_eq!(g.mode(), Some(MutexType::Group));
+        assert!(m.is_locked_group());
+
+        g.unlock();
+        assert_eq!(g.mode(), None);
+        assert!(!m.is_locked());
+
+        // unlocking twice is a no-op
+        g.unlock();
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn none_to_exclusive_and_back() {
+        let m = Mutex::new();
+        let mut g = WatchGuard::with_mutex((), m.clone());
+
+        g.lock_exclusive();
+        assert_eq!(g.mode(), Some(MutexType::Exclusive));
+        assert!(m.is_locked_exclusive());
+        assert!(!m.try_lock_exclusive());
+
+        // locking again in the same mode is a no-op
+        g.lock_exclusive();
+
+        g.unlock();
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn group_to_exclusive() {
+        let m = Mutex::new();
+        let mut g = WatchGuard::with_mutex((), m.clone());
+
+        g.lock_group();
+        g.lock_exclusive();
+        assert_eq!(g.mode(), Some(MutexType::Exclusive));
+        assert!(!m.is_locked_group());
+        assert!(!m.try_lock_exclusive());
+
+        drop(g);
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn exclusive_to_group() {
+        let m = Mutex::new();
+        let mut g = WatchGuard::with_mutex((), m.clone());
+
+        g.lock_exclusive();
+        g.lock_group();
+        assert_eq!(g.mode(), Some(MutexType::Group));
+        assert!(m.is_locked_group());
+
+        // other readers can join
+        let mut other = WatchGuard::with_mutex((), m.clone());
+        other.lock_group();
+        drop(g);
+        assert!(m.is_locked_group());
+        drop(other);
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn drop_releases_held_mode() {
+        let m = Mutex::new();
+
+        m.lock_exclusive();
+        drop(WatchGuard::new_locked(1, m.clone(), MutexType::Exclusive));
+        assert!(!m.is_locked());
+
+        m.lock_group();
+        drop(WatchGuard::new_locked(1, m.clone(), MutexType::Group));
+        assert!(!m.is_locked());
+    }
+
+    #[test]
+    fn exclusive_guard_blocks_others() {
+        let m = Mutex::new();
+        let mut g = WatchGuard::with_mutex(0, m.clone());
+        g.lock_exclusive();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let (m2, d2) = (m.clone(), done.clone());
+        let h = thread::spawn(move || {
+            let mut g = WatchGuard::with_mutex(0, m2);
+            g.lock_group();
+            d2.store(true, Relaxed);
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(!done.load(Relaxed));
+        *g += 1;
+        drop(g);
+
+        h.join().unwrap();
+        assert!(done.load(Relaxed));
+        assert!(!m.is_locked());
+    }
+}
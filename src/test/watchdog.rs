@@ -0,0 +1,123 @@
+mod tests_watchdog {
+    use crate::mutex::{LockState, Mutex, MutexState, MutexType, StallReport, Watchdog};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::{Arc, mpsc};
+    use std::thread;
+    use std::time::Duration;
+
+    /// waiting thread name, waited mode, holders and state of the lock
+    type Seen = (
+        Option<String>,
+        MutexType,
+        Vec<(Option<String>, MutexType)>,
+        MutexState,
+    );
+
+    /// Only one watchdog can run at a time.
+    static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn reports_waiter_and_holders() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let m = Mutex::new();
+        let id = m.id();
+        let (tx, rx) = mpsc::channel::<Seen>();
+        let watchdog = Watchdog::start(Duration::from_millis(30), move |r: &StallReport| {
+            if r.mutex.id() == id {
+                assert!(r.waited >= Duration::from_millis(30));
+                let holders = r
+                    .holders
+                    .iter()
+                    .map(|(t, m)| (t.name().map(String::from), *m))
+                    .collect();
+                let _ = tx.send((
+                    r.thread.name().map(String::from),
+                    r.mode,
+                    holders,
+                    r.state,
+                ));
+            }
+        });
+        assert!(Watchdog::is_running());
+
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (locked_tx, locked_rx) = mpsc::channel::<()>();
+        let mh = m.clone();
+        let holder = thread::Builder::new()
+            .name("holder".into())
+            .spawn(move || {
+                mh.lock_exclusive();
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+                mh.unlock_exclusive();
+            })
+            .unwrap();
+        locked_rx.recv().unwrap();
+
+        let mw = m.clone();
+        let waiter = thread::Builder::new()
+            .name("waiter".into())
+            .spawn(move || {
+                mw.lock_group();
+                mw.unlock_group();
+            })
+            .unwrap();
+
+        let (thread, mode, holders, state) = rx.recv().unwrap();
+        assert_eq!(thread.as_deref(), Some("waiter"));
+        assert_eq!(mode, MutexType::Group);
+        assert_eq!(
+            holders,
+            vec![(Some("holder".to_string()), MutexType::Exclusive)]
+        );
+        assert_eq!(state.state, LockState::Exclusive);
+        assert_eq!(state.parked_group, 1);
+        assert_eq!(state.parked_exclusive, 0);
+
+        release_tx.send(()).unwrap();
+        holder.join().unwrap();
+        waiter.join().unwrap();
+
+        drop(watchdog);
+        assert!(!Watchdog::is_running());
+    }
+
+    #[test]
+    fn reports_each_stall_once() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let m = Mutex::new();
+        let id = m.id();
+        let reports = Arc::new(AtomicUsize::new(0));
+        let r = reports.clone();
+        let watchdog = Watchdog::start(Duration::from_millis(20), move |report| {
+            if report.mutex.id() == id {
+                r.fetch_add(1, Relaxed);
+            }
+        });
+
+        m.lock_exclusive();
+        let mm = m.clone();
+        let h = thread::spawn(move || {
+            mm.lock_exclusive();
+            mm.unlock_exclusive();
+        });
+        thread::sleep(Duration::from_millis(200));
+        m.unlock_exclusive();
+        h.join().unwrap();
+
+        drop(watchdog);
+        assert_eq!(reports.load(Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn only_one_watchdog() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+
+        let _first = Watchdog::start(Duration::from_secs(1), |_| {});
+        let _second = Watchdog::start(Duration::from_secs(1), |_| {});
+    }
+}
@@ -1,5 +1,8 @@
-use std::alloc::{Layout, alloc, dealloc};
-use std::{ptr, thread};
+use alloc::alloc::{Layout, alloc, dealloc};
+use core::ptr;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
 use std::time::Instant;
 
 /// Calculate layout for `T` using the inner value's layout
@@ -20,7 +23,7 @@ pub fn create_raw_pointer<T>(s: T) -> *mut T {
     let raw: *mut T = unsafe {
         let mem_ptr = alloc(layout) as *mut T;
         if mem_ptr.is_null() {
-            std::alloc::handle_alloc_error(layout);
+            alloc::alloc::handle_alloc_error(layout);
         }
         ptr::write(mem_ptr, s);
         mem_ptr
@@ -43,13 +46,39 @@ pub fn dealloc_raw_pointer<T>(raw: *mut T) {
     }
 }
 
+#[cfg(feature = "std")]
 pub fn wait_until(deadline: Instant, pred: impl Fn() -> bool) -> bool {
     while Instant::now() < deadline {
         if pred() {
             return true;
         }
-        std::hint::spin_loop();
+        core::hint::spin_loop();
         thread::yield_now();
     }
     false
 }
+
+/// Aborts the process, used when a reference counter overflows.
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn abort() -> ! {
+    std::process::abort()
+}
+
+/// Aborts the process, used when a reference counter overflows.
+///
+/// Without `std` there is no process to abort: panicking while already panicking
+/// is the portable way to get the same effect.
+#[cfg(not(feature = "std"))]
+#[cold]
+pub(crate) fn abort() -> ! {
+    struct Abort;
+    impl Drop for Abort {
+        fn drop(&mut self) {
+            panic!("abort");
+        }
+    }
+
+    let _abort = Abort;
+    panic!("abort");
+}
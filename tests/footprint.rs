@@ -0,0 +1,96 @@
+//! Memory footprint of the locks, measured with a counting global allocator: it lives in
+//! its own test binary so that it doesn't replace the allocator of the unit tests.
+#![cfg(feature = "std")]
+
+use castbox::collections::AtomicHashMap;
+use castbox::mutex::{Mutex, MutexBackend};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem::size_of;
+
+/// Counts the allocations made by the current thread, so that tests running in
+/// parallel don't disturb each other.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|c| {
+            let (n, bytes) = c.get();
+            c.set((n + 1, bytes + layout.size()));
+        });
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Returns the number of allocations and the allocated bytes while running `f`.
+fn measure<R>(f: impl FnOnce() -> R) -> (usize, usize, R) {
+    let before = ALLOCS.with(|c| c.get());
+    let res = f();
+    let after = ALLOCS.with(|c| c.get());
+    (after.0 - before.0, after.1 - before.1, res)
+}
+
+#[test]
+fn mutex_is_a_single_small_allocation() {
+    let (allocs, bytes, m) = measure(Mutex::new);
+    assert_eq!(allocs, 1);
+    // the state word, with the parked bits, flags and spin estimate bytes next to the
+    // state, then the group counter and the reference counter
+    assert!(bytes <= 3 * size_of::<usize>(), "Mutex uses {bytes} bytes");
+    assert_eq!(size_of::<Mutex>(), size_of::<usize>());
+
+    let (allocs, _, c) = measure(|| m.clone());
+    assert_eq!(allocs, 0);
+    drop(c);
+
+    // only the queued backend pays for the tail of its queue
+    let (allocs, bytes, _) = measure(|| Mutex::with_backend(MutexBackend::Queued));
+    assert_eq!(allocs, 1);
+    assert!(bytes <= 4 * size_of::<usize>(), "Mutex uses {bytes} bytes");
+}
+
+#[test]
+fn map_buckets_do_not_allocate_queues() {
+    const BUCKETS: usize = 256;
+    let (allocs, bytes, map) = measure(|| AtomicHashMap::<u32, u32>::with_capacity(BUCKETS));
+
+    // the inner, the buckets vector, the map lock and one mutex per bucket
+    assert_eq!(allocs, BUCKETS + 3);
+    assert!(bytes <= BUCKETS * 8 * size_of::<usize>());
+    drop(map);
+}
+
+#[test]
+fn contended_mutex_does_not_allocate_queues() {
+    use std::thread;
+    use std::time::Duration;
+
+    let m = Mutex::new();
+    m.lock_exclusive();
+
+    let m2 = m.clone();
+    let h = thread::spawn(move || {
+        // parks on the global table
+        let (allocs, _, _) = measure(|| {
+            m2.lock_exclusive();
+            m2.unlock_exclusive();
+        });
+        allocs
+    });
+
+    thread::sleep(Duration::from_millis(50));
+    let (allocs, _, _) = measure(|| m.unlock_exclusive());
+    assert_eq!(allocs, 0);
+    assert_eq!(h.join().unwrap(), 0);
+}